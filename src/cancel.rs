@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Set by the SIGINT handler while an [`InterruptGuard`] is installed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Why generation was stopped early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    Interrupted,
+    TimedOut,
+}
+
+impl AbortReason {
+    pub fn message(self) -> &'static str {
+        match self {
+            AbortReason::Interrupted => "Generation cancelled",
+            AbortReason::TimedOut => "Generation timed out",
+        }
+    }
+}
+
+/// Stop condition checked by the llama session between prompt chunks, between
+/// generated tokens and from inside llama.cpp's compute graph.
+#[derive(Debug, Clone, Default)]
+pub struct AbortSignal {
+    deadline: Option<Instant>,
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl AbortSignal {
+    /// A signal that fires on Ctrl-C (while an [`InterruptGuard`] is
+    /// installed) or once `timeout` has elapsed.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            cancelled: None,
+        }
+    }
+
+    /// Also fires once `flag` is set, e.g. when a daemon client disconnects.
+    pub fn with_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancelled = Some(flag);
+        self
+    }
+
+    /// Time left before the deadline, if there is one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn reason(&self) -> Option<AbortReason> {
+        let cancelled = self
+            .cancelled
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst));
+        if cancelled || INTERRUPTED.load(Ordering::SeqCst) {
+            Some(AbortReason::Interrupted)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(AbortReason::TimedOut)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.reason().is_some()
+    }
+}
+
+/// Turns Ctrl-C into a cancellation request for as long as it is alive, so
+/// decoding stops cleanly instead of killing the process mid-output. A second
+/// Ctrl-C exits immediately. The previous handler is restored on drop.
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl InterruptGuard {
+    pub fn install() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+
+        #[cfg(unix)]
+        {
+            let handler = on_interrupt as extern "C" fn(libc::c_int);
+            let previous = unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+            Self { previous }
+        }
+        #[cfg(not(unix))]
+        {
+            Self {}
+        }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // Only async-signal-safe calls here: reset colours and leave.
+        const RESET: &[u8] = b"\x1b[0m\n";
+        unsafe {
+            libc::write(libc::STDERR_FILENO, RESET.as_ptr().cast(), RESET.len());
+            libc::_exit(130);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_abort_reasons() {
+        let signal = AbortSignal::new(Some(Duration::from_secs(3600)));
+        assert_eq!(signal.reason(), None);
+
+        let expired = AbortSignal::new(Some(Duration::ZERO));
+        assert_eq!(expired.reason(), Some(AbortReason::TimedOut));
+        assert_eq!(expired.remaining(), Some(Duration::ZERO));
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let flagged = AbortSignal::new(None).with_flag(flag.clone());
+        assert_eq!(flagged.reason(), None);
+        flag.store(true, Ordering::SeqCst);
+        assert_eq!(flagged.reason(), Some(AbortReason::Interrupted));
+
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        assert_eq!(signal.reason(), Some(AbortReason::Interrupted));
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,741 @@
+//! `git ca daemon`: keeps a model loaded and serves generation requests over a
+//! Unix domain socket so each commit skips backend start-up and model loading.
+//!
+//! Every request and response is a frame: a header line of space-separated
+//! words whose last word is the byte length of the payload that follows.
+//! Generation requests carry the client's sampling settings as a JSON line
+//! ahead of the prompt, so each repository's config applies even though the
+//! daemon was started elsewhere.
+
+use crate::cancel::{AbortReason, AbortSignal};
+use crate::llama::{
+    Generator, LlamaError, LlamaSession, Mirostat, SamplingOptions, SessionOptions,
+};
+use crate::options::{session_options, LlamaFlags};
+use crate::{
+    get_model_path, home_dir, AppError, GitConfig, Language, Result, DEFAULT_CONTEXT_SIZE,
+};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONFIG_IDLE_TIMEOUT_KEY: &str = "commit-analyzer.daemon.idleTimeout";
+/// Idle shutdown delay in seconds; `0` keeps the daemon running until stopped.
+const DEFAULT_IDLE_TIMEOUT_SECS: i64 = 1800;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const START_TIMEOUT: Duration = Duration::from_secs(120);
+/// Largest payload either side accepts; prompts are trimmed to the context
+/// window long before they get near this.
+const MAX_FRAME_BYTES: usize = 8 * 1024 * 1024;
+/// How long the server waits on one client's reads and writes, so a client
+/// that connects and goes quiet cannot hold up everyone else.
+const CLIENT_IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client waits on HELLO, COUNT and STATUS. The daemon serves one
+/// connection at a time, so a busy or hung daemon runs into this and the
+/// caller loads the model itself instead of waiting behind it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest header line accepted, so a stray client cannot stream one forever.
+const MAX_HEADER_BYTES: u64 = 256;
+
+fn cache_dir() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(".cache/git-ca"))
+}
+
+fn socket_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("daemon.sock"))
+}
+
+fn log_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("daemon.log"))
+}
+
+fn idle_timeout(git_config: &GitConfig) -> Option<Duration> {
+    let seconds = git_config
+        .config
+        .get_i64(CONFIG_IDLE_TIMEOUT_KEY)
+        .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS);
+    (seconds > 0).then(|| Duration::from_secs(seconds as u64))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    canonical(a) == canonical(b)
+}
+
+fn write_frame(writer: &mut impl Write, header: &str, payload: &str) -> io::Result<()> {
+    writeln!(writer, "{header} {}", payload.len())?;
+    writer.write_all(payload.as_bytes())?;
+    writer.flush()
+}
+
+fn read_frame(reader: &mut impl BufRead) -> io::Result<Option<(Vec<String>, String)>> {
+    let mut line = String::new();
+    if reader
+        .by_ref()
+        .take(MAX_HEADER_BYTES)
+        .read_line(&mut line)?
+        == 0
+    {
+        return Ok(None);
+    }
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed daemon frame");
+    if !line.ends_with('\n') {
+        return Err(malformed());
+    }
+    let mut words: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+    let length = words
+        .pop()
+        .and_then(|word| word.parse::<usize>().ok())
+        .ok_or_else(malformed)?;
+    if length > MAX_FRAME_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("daemon frame of {length} bytes exceeds the {MAX_FRAME_BYTES}-byte limit"),
+        ));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((
+        words,
+        String::from_utf8_lossy(&payload).into_owned(),
+    )))
+}
+
+/// Sends one request and reads the single response frame, giving up once a
+/// read or write has waited `timeout`.
+fn request(
+    socket: &Path,
+    header: &str,
+    payload: &str,
+    timeout: Duration,
+) -> io::Result<(Vec<String>, String)> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write_frame(&mut stream, header, payload)?;
+    read_frame(&mut BufReader::new(stream))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the connection"))
+}
+
+fn expect_ok(response: (Vec<String>, String)) -> std::result::Result<String, String> {
+    let (words, payload) = response;
+    match words.first().map(String::as_str) {
+        Some("OK") => Ok(payload),
+        _ => Err(payload),
+    }
+}
+
+/// What `git ca daemon status` reports.
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub model: PathBuf,
+    pub idle_remaining: Option<Duration>,
+}
+
+pub fn status() -> Option<DaemonStatus> {
+    let payload = expect_ok(request(&socket_path()?, "STATUS", "", REQUEST_TIMEOUT).ok()?).ok()?;
+    let mut lines = payload.lines();
+    let pid = lines.next()?.parse().ok()?;
+    let model = PathBuf::from(lines.next()?);
+    let idle_remaining = lines
+        .next()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs);
+    Some(DaemonStatus {
+        pid,
+        model,
+        idle_remaining,
+    })
+}
+
+fn sampling_to_json(sampling: &SamplingOptions) -> Value {
+    json!({
+        "temperature": sampling.temperature,
+        "top_k": sampling.top_k,
+        "top_p": sampling.top_p,
+        "min_p": sampling.min_p,
+        "typical_p": sampling.typical_p,
+        "repeat_penalty": sampling.repeat_penalty,
+        "frequency_penalty": sampling.frequency_penalty,
+        "presence_penalty": sampling.presence_penalty,
+        "penalty_last_n": sampling.penalty_last_n,
+        "mirostat": match sampling.mirostat {
+            Mirostat::Disabled => 0,
+            Mirostat::V1 => 1,
+            Mirostat::V2 => 2,
+        },
+        "mirostat_tau": sampling.mirostat_tau,
+        "mirostat_eta": sampling.mirostat_eta,
+        "dry_multiplier": sampling.dry_multiplier,
+        "dry_base": sampling.dry_base,
+        "dry_allowed_length": sampling.dry_allowed_length,
+        "dry_penalty_last_n": sampling.dry_penalty_last_n,
+        "grammar": sampling.grammar,
+        "seed": sampling.seed,
+        "stop": sampling.stop,
+    })
+}
+
+fn sampling_from_json(value: &Value) -> Option<SamplingOptions> {
+    let float = |key: &str| value.get(key)?.as_f64().map(|number| number as f32);
+    let int = |key: &str| value.get(key)?.as_i64();
+    Some(SamplingOptions {
+        temperature: float("temperature")?,
+        top_k: usize::try_from(int("top_k")?).ok()?,
+        top_p: float("top_p")?,
+        min_p: float("min_p")?,
+        typical_p: float("typical_p")?,
+        repeat_penalty: float("repeat_penalty")?,
+        frequency_penalty: float("frequency_penalty")?,
+        presence_penalty: float("presence_penalty")?,
+        penalty_last_n: usize::try_from(int("penalty_last_n")?).ok()?,
+        mirostat: Mirostat::parse(&int("mirostat")?.to_string())?,
+        mirostat_tau: float("mirostat_tau")?,
+        mirostat_eta: float("mirostat_eta")?,
+        dry_multiplier: float("dry_multiplier")?,
+        dry_base: float("dry_base")?,
+        dry_allowed_length: i32::try_from(int("dry_allowed_length")?).ok()?,
+        dry_penalty_last_n: i32::try_from(int("dry_penalty_last_n")?).ok()?,
+        grammar: value.get("grammar")?.as_str().map(str::to_string),
+        seed: match value.get("seed")? {
+            Value::Null => None,
+            seed => Some(u32::try_from(seed.as_u64()?).ok()?),
+        },
+        stop: value
+            .get("stop")?
+            .as_array()?
+            .iter()
+            .map(|stop| stop.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()?,
+    })
+}
+
+fn lost_connection(err: io::Error) -> LlamaError {
+    LlamaError::Inference(format!("Lost connection to the git-ca daemon: {err}"))
+}
+
+/// Generation served by a running daemon. Cancellation is local: the client
+/// stops reading and hangs up, and the daemon aborts when it notices.
+pub struct DaemonClient {
+    socket: PathBuf,
+    n_ctx: usize,
+    /// Sent with every request; the daemon's own settings are not used.
+    sampling: SamplingOptions,
+    abort: AbortSignal,
+    server_abort: Option<AbortReason>,
+}
+
+impl DaemonClient {
+    /// Connects to a daemon that serves `model_path` with the context size in
+    /// `options`, or returns `None` so the caller loads the model itself.
+    pub fn connect(model_path: &Path, options: &SessionOptions) -> Option<Self> {
+        let context_size = options.n_ctx;
+        let socket = socket_path()?;
+        let payload = expect_ok(request(&socket, "HELLO", "", REQUEST_TIMEOUT).ok()?).ok()?;
+        let (n_ctx, served) = payload.split_once('\n')?;
+        let n_ctx: usize = n_ctx.parse().ok()?;
+        if n_ctx != context_size.max(0) as usize || !same_file(Path::new(served), model_path) {
+            return None;
+        }
+        Some(Self {
+            socket,
+            n_ctx,
+            sampling: options.sampling.clone(),
+            abort: AbortSignal::default(),
+            server_abort: None,
+        })
+    }
+}
+
+impl Generator for DaemonClient {
+    fn context_size(&self) -> usize {
+        self.n_ctx
+    }
+
+    fn count_tokens(&self, text: &str) -> std::result::Result<usize, LlamaError> {
+        let response =
+            request(&self.socket, "COUNT", text, REQUEST_TIMEOUT).map_err(lost_connection)?;
+        expect_ok(response)
+            .map_err(LlamaError::Inference)?
+            .parse()
+            .map_err(|_| {
+                LlamaError::Inference("Malformed token count from the git-ca daemon".to_string())
+            })
+    }
+
+    fn infer_streaming(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> std::result::Result<String, LlamaError> {
+        self.server_abort = None;
+        if let Some(reason) = self.abort.reason() {
+            return Err(LlamaError::Aborted(reason));
+        }
+
+        let lost = lost_connection;
+        let timeout_ms = self
+            .abort
+            .remaining()
+            .map_or(0, |remaining| remaining.as_millis().max(1));
+        let mut stream = UnixStream::connect(&self.socket).map_err(lost)?;
+        stream
+            .set_write_timeout(Some(CLIENT_IO_TIMEOUT))
+            .map_err(lost)?;
+        write_frame(
+            &mut stream,
+            &format!("INFER {max_tokens} {timeout_ms}"),
+            &format!("{}\n{prompt}", sampling_to_json(&self.sampling)),
+        )
+        .map_err(lost)?;
+
+        // Frames are read on a helper thread so this one can keep polling the
+        // abort signal while the daemon is still busy with the prompt.
+        let reader_stream = stream.try_clone().map_err(lost)?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader_stream);
+            loop {
+                let frame = read_frame(&mut reader);
+                let more = match &frame {
+                    Ok(Some((words, _))) => words.first().is_some_and(|word| word == "TOKEN"),
+                    _ => false,
+                };
+                if sender.send(frame).is_err() || !more {
+                    break;
+                }
+            }
+        });
+
+        let mut generated = String::new();
+        loop {
+            if let Some(reason) = self.abort.reason() {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Err(LlamaError::Aborted(reason));
+            }
+
+            let (words, payload) = match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) => {
+                    return Err(lost(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(Err(err)) => return Err(lost(err)),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(lost(io::ErrorKind::BrokenPipe.into()));
+                }
+            };
+
+            match words.first().map(String::as_str) {
+                Some("TOKEN") => {
+                    on_token(&payload);
+                    generated.push_str(&payload);
+                }
+                Some("END") => return Ok(generated),
+                Some("ABORT") => {
+                    let reason = if payload == "timeout" {
+                        AbortReason::TimedOut
+                    } else {
+                        AbortReason::Interrupted
+                    };
+                    self.server_abort = Some(reason);
+                    return Err(LlamaError::Aborted(reason));
+                }
+                _ => return Err(LlamaError::Inference(payload)),
+            }
+        }
+    }
+
+    fn set_abort_signal(&mut self, signal: AbortSignal) {
+        self.abort = signal;
+    }
+
+    fn abort_reason(&self) -> Option<AbortReason> {
+        self.server_abort.or_else(|| self.abort.reason())
+    }
+}
+
+/// Removes the socket file when the server exits, however it exits.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Binds `socket` under a umask that leaves it to its owner alone, so it is
+/// never reachable by other users, not even between `bind` and `chmod`.
+fn bind_private(socket: &Path) -> io::Result<UnixListener> {
+    let previous = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    unsafe { libc::umask(previous) };
+    listener
+}
+
+/// Loads `model_path` and serves requests until stopped or idle for
+/// `idle_timeout`.
+pub fn serve(
+    model_path: &Path,
+    options: &SessionOptions,
+    idle_timeout: Option<Duration>,
+    language: &Language,
+) -> Result<()> {
+    let socket = socket_path().ok_or_else(|| language.home_dir_unknown().to_string())?;
+    // A busy daemon may not answer STATUS in time, but it still accepts
+    // connections; only a stale socket file refuses them.
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(AppError::Custom(
+            language.daemon_already_running().to_string(),
+        ));
+    }
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A socket file left behind by a daemon that did not exit cleanly.
+    let _ = fs::remove_file(&socket);
+
+    let mut session = LlamaSession::new(model_path, options).map_err(AppError::from)?;
+    let listener = bind_private(&socket)?;
+    let _socket_file = SocketFile(socket.clone());
+    fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    eprintln!(
+        "[git-ca] daemon {} serving {} on {}",
+        std::process::id(),
+        model_path.display(),
+        socket.display()
+    );
+
+    let mut last_active = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let idle_remaining =
+                    idle_timeout.map(|idle| idle.saturating_sub(last_active.elapsed()));
+                let served = stream
+                    .set_nonblocking(false)
+                    .and_then(|_| stream.set_read_timeout(Some(CLIENT_IO_TIMEOUT)))
+                    .and_then(|_| stream.set_write_timeout(Some(CLIENT_IO_TIMEOUT)))
+                    .and_then(|_| {
+                        handle_connection(&mut session, stream, model_path, idle_remaining)
+                    });
+                last_active = Instant::now();
+                match served {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => eprintln!("[git-ca] warning: daemon request failed: {err}"),
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if idle_timeout.is_some_and(|idle| last_active.elapsed() >= idle) {
+                    eprintln!("[git-ca] daemon idle, shutting down");
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Serves one request; returns `false` when the daemon should stop.
+fn handle_connection(
+    session: &mut LlamaSession,
+    stream: UnixStream,
+    model_path: &Path,
+    idle_remaining: Option<Duration>,
+) -> io::Result<bool> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let Some((words, payload)) = read_frame(&mut reader)? else {
+        return Ok(true);
+    };
+
+    match words.first().map(String::as_str) {
+        Some("HELLO") => write_frame(
+            &mut writer,
+            "OK",
+            &format!("{}\n{}", session.context_size(), model_path.display()),
+        )?,
+        Some("STATUS") => write_frame(
+            &mut writer,
+            "OK",
+            &format!(
+                "{}\n{}\n{}",
+                std::process::id(),
+                model_path.display(),
+                idle_remaining.map_or(String::new(), |idle| idle.as_secs().to_string())
+            ),
+        )?,
+        Some("STOP") => {
+            write_frame(&mut writer, "OK", "")?;
+            return Ok(false);
+        }
+        Some("COUNT") => match session.count_tokens(&payload) {
+            Ok(count) => write_frame(&mut writer, "OK", &count.to_string())?,
+            Err(err) => write_frame(&mut writer, "ERR", &err.to_string())?,
+        },
+        Some("INFER") => {
+            let number = |index: usize| {
+                words
+                    .get(index)
+                    .and_then(|word| word.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            let max_tokens = number(1) as usize;
+            let timeout = Some(number(2))
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis);
+
+            let Some((sampling, prompt)) = payload.split_once('\n').and_then(|(line, prompt)| {
+                let sampling = sampling_from_json(&serde_json::from_str(line).ok()?)?;
+                Some((sampling, prompt))
+            }) else {
+                write_frame(&mut writer, "ERR", "Malformed sampling settings")?;
+                return Ok(true);
+            };
+            session.set_sampling(sampling);
+
+            let disconnected = Arc::new(AtomicBool::new(false));
+            session.set_abort_signal(AbortSignal::new(timeout).with_flag(disconnected.clone()));
+            let result = session.infer_streaming(prompt, max_tokens, &mut |piece| {
+                if write_frame(&mut writer, "TOKEN", piece).is_err() {
+                    disconnected.store(true, Ordering::SeqCst);
+                }
+            });
+            let reason = session.abort_reason();
+            session.set_abort_signal(AbortSignal::default());
+
+            if disconnected.load(Ordering::SeqCst) {
+                return Ok(true);
+            }
+            match (result, reason) {
+                (Ok(_), _) => write_frame(&mut writer, "END", "")?,
+                (Err(_), Some(AbortReason::TimedOut)) => {
+                    write_frame(&mut writer, "ABORT", "timeout")?
+                }
+                (Err(_), Some(AbortReason::Interrupted)) => {
+                    write_frame(&mut writer, "ABORT", "cancelled")?
+                }
+                (Err(err), None) => write_frame(&mut writer, "ERR", &err.to_string())?,
+            }
+        }
+        _ => write_frame(&mut writer, "ERR", "Unknown daemon request")?,
+    }
+
+    Ok(true)
+}
+
+fn print_status(status: &DaemonStatus, language: &Language) {
+    println!(
+        "{}",
+        language
+            .daemon_running()
+            .replace("{pid}", &status.pid.to_string())
+            .replace("{model}", &status.model.to_string_lossy())
+    );
+    if let Some(idle) = status.idle_remaining {
+        println!(
+            "{}",
+            language
+                .daemon_idle_remaining()
+                .replace("{}", &idle.as_secs().to_string())
+        );
+    }
+}
+
+/// Starts a detached `git ca daemon run` with the same llama flags and waits
+/// until it answers.
+fn start(flags: &LlamaFlags, language: &Language) -> Result<()> {
+    if let Some(status) = status() {
+        print_status(&status, language);
+        return Ok(());
+    }
+
+    let model_path = get_model_path(language)?;
+    let log = log_path().ok_or_else(|| language.home_dir_unknown().to_string())?;
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log_file = OpenOptions::new().create(true).append(true).open(&log)?;
+
+    println!("{}", language.daemon_starting());
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("daemon")
+        .arg("run")
+        .arg(&model_path)
+        .args(flags.raw())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log_file)
+        // Own process group, so Ctrl-C in this terminal does not reach it.
+        .process_group(0)
+        .spawn()?;
+
+    let started = Instant::now();
+    while started.elapsed() < START_TIMEOUT {
+        if let Some(status) = status() {
+            print_status(&status, language);
+            return Ok(());
+        }
+        if child.try_wait()?.is_some() {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    Err(AppError::Custom(
+        language
+            .daemon_start_failed()
+            .replace("{}", &log.to_string_lossy()),
+    ))
+}
+
+/// Entry point for `git ca daemon [start|run [model]|status|stop]`.
+pub fn run_command(
+    args: &[String],
+    git_config: &GitConfig,
+    language: &Language,
+    flags: &LlamaFlags,
+) -> Result<()> {
+    match args.first().map(String::as_str) {
+        None | Some("start") => start(flags, language),
+        Some("run") => {
+            let model_path = match args.get(1) {
+                Some(path) => PathBuf::from(path),
+                None => get_model_path(language)?,
+            };
+            let options = session_options(&git_config.config, flags, DEFAULT_CONTEXT_SIZE);
+            serve(&model_path, &options, idle_timeout(git_config), language)
+        }
+        Some("status") => {
+            match status() {
+                Some(status) => print_status(&status, language),
+                None => println!("{}", language.daemon_not_running()),
+            }
+            Ok(())
+        }
+        Some("stop") => {
+            // A daemon in the middle of a generation answers once it is done.
+            let stopped = socket_path()
+                .and_then(|socket| request(&socket, "STOP", "", START_TIMEOUT).ok())
+                .is_some();
+            if stopped {
+                println!("{}", language.daemon_stopped());
+            } else {
+                println!("{}", language.daemon_not_running());
+            }
+            Ok(())
+        }
+        Some(_) => {
+            println!("{}", language.daemon_usage());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_frames_with_multiline_payloads() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, "INFER 256 1000", "line one\nline two\n").unwrap();
+        write_frame(&mut buffer, "END", "").unwrap();
+
+        let mut reader = BufReader::new(buffer.as_slice());
+        let (words, payload) = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(words, vec!["INFER", "256", "1000"]);
+        assert_eq!(payload, "line one\nline two\n");
+        let (words, payload) = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!((words, payload), (vec!["END".to_string()], String::new()));
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        let mut malformed = BufReader::new("TOKEN x\n".as_bytes());
+        assert!(read_frame(&mut malformed).is_err());
+    }
+
+    #[test]
+    fn round_trips_sampling_settings() {
+        let sampling = SamplingOptions {
+            temperature: 0.35,
+            top_k: 0,
+            mirostat: Mirostat::V2,
+            grammar: Some("root ::= \"feat\"".to_string()),
+            seed: Some(42),
+            stop: vec!["\n\n".to_string(), "Explanation:".to_string()],
+            ..SamplingOptions::default()
+        };
+        let line = sampling_to_json(&sampling).to_string();
+        assert!(!line.contains('\n'));
+        let parsed = sampling_from_json(&serde_json::from_str(&line).unwrap());
+        assert_eq!(parsed, Some(sampling));
+
+        let defaults = SamplingOptions::default();
+        let value = sampling_to_json(&defaults);
+        assert_eq!(sampling_from_json(&value), Some(defaults));
+        assert_eq!(sampling_from_json(&json!({ "temperature": 0.1 })), None);
+    }
+
+    #[test]
+    fn binds_sockets_for_the_owner_only() {
+        let dir = std::env::temp_dir().join(format!("git-ca-daemon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("test.sock");
+        let _ = fs::remove_file(&socket);
+
+        let listener = bind_private(&socket).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        drop(listener);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn rejects_oversized_frames_before_allocating() {
+        let header = format!("INFER 256 0 {}\n", usize::MAX);
+        let mut reader = BufReader::new(header.as_bytes());
+        let err = read_frame(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let endless = "A".repeat(MAX_HEADER_BYTES as usize * 2);
+        assert!(read_frame(&mut BufReader::new(endless.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn gives_up_on_a_daemon_that_does_not_answer() {
+        let dir = std::env::temp_dir().join(format!("git-ca-busy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("busy.sock");
+        let _ = fs::remove_file(&socket);
+
+        // Bound but never accepting, like a daemon busy with another client.
+        let listener = bind_private(&socket).unwrap();
+        let started = Instant::now();
+        let result = request(&socket, "HELLO", "", Duration::from_millis(100));
+        let waited = started.elapsed();
+        drop(listener);
+        let _ = fs::remove_dir_all(&dir);
+
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(waited < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,595 @@
+use git2::{
+    Config, Delta, Diff, DiffFile, DiffFindOptions, DiffFormat, DiffOptions, FileMode, Index,
+    Patch, Repository, Tree,
+};
+use std::path::Path;
+
+/// Staged changes computed with libgit2, kept both as unified patch text and
+/// as per-file sections so the prompt builder does not need to re-parse text.
+#[derive(Debug, Default)]
+pub struct StagedDiff {
+    pub files: Vec<FileDiff>,
+    pub text: String,
+}
+
+impl StagedDiff {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// What happened to a staged path, beyond its line counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    Renamed {
+        from: String,
+    },
+    Copied {
+        from: String,
+    },
+    ModeChanged {
+        old_mode: u32,
+        new_mode: u32,
+    },
+    /// A submodule pointer bump; commit ids are abbreviated and `None` when the
+    /// submodule was added or removed.
+    Submodule {
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct FileDiff {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    /// Hunk headers and `+`/`-`/context lines, without trailing newlines.
+    pub lines: Vec<String>,
+}
+
+/// Computes the diff between `HEAD` (or the empty tree on an unborn branch)
+/// and `index`, honouring the repository's `diff.*` configuration.
+pub fn staged_diff(repo: &Repository, index: &Index) -> Result<StagedDiff, git2::Error> {
+    let head_tree = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(err)
+            if matches!(
+                err.code(),
+                git2::ErrorCode::UnbornBranch | git2::ErrorCode::NotFound
+            ) =>
+        {
+            None
+        }
+        Err(err) => return Err(err),
+    };
+
+    index_diff(repo, head_tree.as_ref(), index)
+}
+
+/// Computes the diff between `base` (the empty tree when `None`) and `index`,
+/// which need not be the repository's own index.
+pub fn index_diff(
+    repo: &Repository,
+    base: Option<&Tree>,
+    index: &Index,
+) -> Result<StagedDiff, git2::Error> {
+    let config = repo.config()?;
+    let mut options = diff_options(&config);
+    let mut diff = repo.diff_tree_to_index(base, Some(index), Some(&mut options))?;
+    if let Some(mut find_options) = find_options(&config) {
+        diff.find_similar(Some(&mut find_options))?;
+    }
+
+    collect_diff(&diff)
+}
+
+/// Rebuilds `path` as it is in `base` with only the `selected` hunks of its
+/// staged change applied. Hunks are numbered in the order [`index_diff`]
+/// lists them for `base` against `index`.
+pub fn apply_hunks(
+    repo: &Repository,
+    base: Option<&Tree>,
+    index: &Index,
+    path: &str,
+    selected: &[usize],
+) -> Result<Vec<u8>, git2::Error> {
+    let config = repo.config()?;
+    let mut options = diff_options(&config);
+    options.pathspec(path).disable_pathspec_match(true);
+    let diff = repo.diff_tree_to_index(base, Some(index), Some(&mut options))?;
+    let patch = Patch::from_diff(&diff, 0)?
+        .ok_or_else(|| git2::Error::from_str(&format!("no staged text changes in {path}")))?;
+
+    let old = match base.map(|tree| tree.get_path(Path::new(path))) {
+        Some(Ok(entry)) => repo.find_blob(entry.id())?.content().to_vec(),
+        _ => Vec::new(),
+    };
+    let old_lines: Vec<&[u8]> = old.split_inclusive(|&byte| byte == b'\n').collect();
+    let out_of_range = || git2::Error::from_str(&format!("hunk outside of {path}"));
+
+    let mut content = Vec::with_capacity(old.len());
+    let mut next = 0;
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_index)?;
+        // A hunk that only adds lines names the line it follows.
+        let start = hunk.old_start() as usize - usize::from(hunk.old_lines() > 0);
+        let end = start + hunk.old_lines() as usize;
+        content.extend(
+            old_lines
+                .get(next..start)
+                .ok_or_else(out_of_range)?
+                .concat(),
+        );
+        if selected.contains(&hunk_index) {
+            for line_index in 0..line_count {
+                let line = patch.line_in_hunk(hunk_index, line_index)?;
+                if matches!(line.origin(), ' ' | '+') {
+                    content.extend_from_slice(line.content());
+                }
+            }
+        } else {
+            content.extend(old_lines.get(start..end).ok_or_else(out_of_range)?.concat());
+        }
+        next = end;
+    }
+    content.extend(old_lines.get(next..).ok_or_else(out_of_range)?.concat());
+
+    Ok(content)
+}
+
+/// Parses a patch in `git diff` format, as if its changes were staged.
+pub fn parse_patch(patch: &str) -> Result<StagedDiff, git2::Error> {
+    collect_diff(&Diff::from_buffer(patch.as_bytes())?)
+}
+
+fn collect_diff(diff: &Diff<'_>) -> Result<StagedDiff, git2::Error> {
+    let mut staged = StagedDiff::default();
+    diff.print(DiffFormat::Patch, |delta, _hunk, line| {
+        let origin = line.origin();
+        let content = String::from_utf8_lossy(line.content());
+
+        if matches!(origin, '+' | '-' | ' ') {
+            staged.text.push(origin);
+        }
+        staged.text.push_str(&content);
+
+        if origin == 'F' {
+            staged.files.push(FileDiff::from_delta(&delta));
+            return true;
+        }
+
+        let Some(file) = staged.files.last_mut() else {
+            return true;
+        };
+
+        match origin {
+            '+' => file.additions += 1,
+            '-' => file.deletions += 1,
+            'B' => file.binary = true,
+            _ => {}
+        }
+
+        if matches!(origin, '+' | '-' | ' ') {
+            file.lines.push(format!(
+                "{origin}{}",
+                content.trim_end_matches(['\n', '\r'])
+            ));
+        } else if origin == 'H' {
+            file.lines
+                .push(content.trim_end_matches(['\n', '\r']).to_string());
+        }
+
+        true
+    })?;
+
+    Ok(staged)
+}
+
+impl FileDiff {
+    fn from_delta(delta: &git2::DiffDelta<'_>) -> Self {
+        let old_file = delta.old_file();
+        let new_file = delta.new_file();
+        let old_path = file_path(&old_file);
+        let path = file_path(&new_file)
+            .or_else(|| old_path.clone())
+            .unwrap_or_default();
+
+        let kind = if old_file.mode() == FileMode::Commit || new_file.mode() == FileMode::Commit {
+            ChangeKind::Submodule {
+                old: short_id(&old_file),
+                new: short_id(&new_file),
+            }
+        } else {
+            match delta.status() {
+                Delta::Added | Delta::Untracked => ChangeKind::Added,
+                Delta::Deleted => ChangeKind::Deleted,
+                Delta::Renamed => ChangeKind::Renamed {
+                    from: old_path.unwrap_or_default(),
+                },
+                Delta::Copied => ChangeKind::Copied {
+                    from: old_path.unwrap_or_default(),
+                },
+                _ if old_file.mode() != new_file.mode() => ChangeKind::ModeChanged {
+                    old_mode: i32::from(old_file.mode()) as u32,
+                    new_mode: i32::from(new_file.mode()) as u32,
+                },
+                _ => ChangeKind::Modified,
+            }
+        };
+
+        FileDiff {
+            path,
+            kind,
+            binary: delta.flags().is_binary(),
+            additions: 0,
+            deletions: 0,
+            lines: Vec::new(),
+        }
+    }
+}
+
+fn file_path(file: &DiffFile<'_>) -> Option<String> {
+    file.path().map(|p| p.to_string_lossy().into_owned())
+}
+
+fn short_id(file: &DiffFile<'_>) -> Option<String> {
+    let id = file.id();
+    if id.is_zero() {
+        None
+    } else {
+        Some(id.to_string().chars().take(7).collect())
+    }
+}
+
+fn diff_options(config: &Config) -> DiffOptions {
+    let mut options = DiffOptions::new();
+
+    if let Ok(lines) = config.get_i32("diff.context") {
+        if let Ok(lines) = u32::try_from(lines) {
+            options.context_lines(lines);
+        }
+    }
+    if let Ok(lines) = config.get_i32("diff.interHunkContext") {
+        if let Ok(lines) = u32::try_from(lines) {
+            options.interhunk_lines(lines);
+        }
+    }
+
+    match config
+        .get_string("diff.algorithm")
+        .map(|value| value.to_ascii_lowercase())
+        .as_deref()
+    {
+        Ok("patience") | Ok("histogram") => {
+            options.patience(true);
+        }
+        Ok("minimal") => {
+            options.minimal(true);
+        }
+        _ => {}
+    }
+
+    if config.get_bool("diff.noprefix").unwrap_or(false) {
+        options.old_prefix("").new_prefix("");
+    } else if config.get_bool("diff.mnemonicPrefix").unwrap_or(false) {
+        options.old_prefix("c/").new_prefix("i/");
+    }
+
+    if config
+        .get_string("diff.ignoreSubmodules")
+        .is_ok_and(|value| value.eq_ignore_ascii_case("all"))
+    {
+        options.ignore_submodules(true);
+    }
+
+    options
+}
+
+/// Mirrors git's `diff.renames`: rename detection is on by default, `copies`
+/// additionally detects copies, and a false value disables both.
+fn find_options(config: &Config) -> Option<DiffFindOptions> {
+    let setting = config
+        .get_string("diff.renames")
+        .map(|value| value.to_ascii_lowercase())
+        .unwrap_or_else(|_| "true".to_string());
+
+    let copies = match setting.as_str() {
+        "copies" | "copy" => true,
+        "false" | "no" | "off" | "0" => return None,
+        _ => false,
+    };
+
+    let mut options = DiffFindOptions::new();
+    options.renames(true).copies(copies);
+    if let Ok(limit) = config.get_i32("diff.renameLimit") {
+        if let Ok(limit) = usize::try_from(limit) {
+            if limit > 0 {
+                options.rename_limit(limit);
+            }
+        }
+    }
+    Some(options)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::fs;
+    use std::path::PathBuf;
+
+    pub(crate) struct TempRepo {
+        dir: PathBuf,
+        pub(crate) repo: Repository,
+    }
+
+    impl TempRepo {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("git-ca-diff-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).expect("temp dir");
+            let repo = Repository::init(&dir).expect("init repo");
+            TempRepo { dir, repo }
+        }
+
+        pub(crate) fn write(&self, path: &str, contents: &[u8]) {
+            let full = self.dir.join(path);
+            if let Some(parent) = full.parent() {
+                fs::create_dir_all(parent).expect("parent dir");
+            }
+            fs::write(full, contents).expect("write file");
+        }
+
+        pub(crate) fn stage_all(&self) -> Index {
+            let mut index = self.repo.index().expect("index");
+            index
+                .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+                .expect("add all");
+            index.update_all(["*"], None).expect("update all");
+            index.write().expect("write index");
+            index
+        }
+
+        pub(crate) fn commit(&self) {
+            let mut index = self.stage_all();
+            let tree_id = index.write_tree().expect("tree");
+            let tree = self.repo.find_tree(tree_id).expect("find tree");
+            let signature = Signature::now("Test", "test@example.com").expect("signature");
+            let parents = match self.repo.head() {
+                Ok(head) => vec![head.peel_to_commit().expect("head commit")],
+                Err(_) => Vec::new(),
+            };
+            let parent_refs: Vec<&git2::Commit> = parents.iter().collect();
+            self.repo
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    "initial",
+                    &tree,
+                    &parent_refs,
+                )
+                .expect("commit");
+        }
+
+        pub(crate) fn remove(&self, path: &str) {
+            fs::remove_file(self.dir.join(Path::new(path))).expect("remove file");
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// A text file change with `lines` as its diff, counting `+` and `-`
+    /// lines as additions and deletions.
+    pub(crate) fn file_diff(path: &str, kind: ChangeKind, lines: &[&str]) -> FileDiff {
+        FileDiff {
+            path: path.to_string(),
+            kind,
+            binary: false,
+            additions: lines.iter().filter(|line| line.starts_with('+')).count(),
+            deletions: lines.iter().filter(|line| line.starts_with('-')).count(),
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn diffs_unborn_branch_against_empty_tree() {
+        let repo = TempRepo::new("unborn");
+        repo.write("src/lib.rs", b"pub fn answer() -> u32 {\n    42\n}\n");
+        let index = repo.stage_all();
+
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        assert_eq!(diff.files.len(), 1);
+        let file = &diff.files[0];
+        assert_eq!(file.path, "src/lib.rs");
+        assert_eq!(file.kind, ChangeKind::Added);
+        assert_eq!((file.additions, file.deletions), (3, 0));
+        assert!(file.lines.iter().any(|line| line == "+    42"));
+        assert!(diff.text.contains("diff --git a/src/lib.rs b/src/lib.rs"));
+        assert!(diff.text.contains("new file mode"));
+    }
+
+    #[test]
+    fn detects_renames_binary_and_non_utf8_content() {
+        let repo = TempRepo::new("kinds");
+        let body = "line one\nline two\nline three\nline four\n";
+        repo.write("old_name.txt", body.as_bytes());
+        repo.write("notes.txt", b"hello\n");
+        repo.commit();
+
+        repo.remove("old_name.txt");
+        repo.write("new_name.txt", body.as_bytes());
+        repo.write("logo.bin", &[0u8, 159, 146, 150, 0, 1, 2]);
+        repo.write("notes.txt", b"hello\ncaf\xe9\n");
+        let index = repo.stage_all();
+
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+
+        let renamed = diff
+            .files
+            .iter()
+            .find(|file| file.path == "new_name.txt")
+            .expect("renamed file");
+        assert_eq!(
+            renamed.kind,
+            ChangeKind::Renamed {
+                from: "old_name.txt".to_string()
+            }
+        );
+
+        let binary = diff
+            .files
+            .iter()
+            .find(|file| file.path == "logo.bin")
+            .expect("binary file");
+        assert!(binary.binary);
+        assert!(binary.lines.is_empty());
+
+        let notes = diff
+            .files
+            .iter()
+            .find(|file| file.path == "notes.txt")
+            .expect("notes file");
+        assert!(notes.lines.iter().any(|line| line == "+caf\u{fffd}"));
+    }
+
+    #[test]
+    fn honours_diff_renames_config() {
+        let repo = TempRepo::new("no-renames");
+        let body = "alpha\nbeta\ngamma\ndelta\n";
+        repo.write("a.txt", body.as_bytes());
+        repo.commit();
+        repo.repo
+            .config()
+            .expect("config")
+            .set_bool("diff.renames", false)
+            .expect("set config");
+
+        repo.remove("a.txt");
+        repo.write("b.txt", body.as_bytes());
+        let index = repo.stage_all();
+
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let kinds: Vec<&ChangeKind> = diff.files.iter().map(|file| &file.kind).collect();
+        assert_eq!(kinds, vec![&ChangeKind::Deleted, &ChangeKind::Added]);
+    }
+
+    #[test]
+    fn detects_mode_changes_and_submodule_updates() {
+        let repo = TempRepo::new("modes");
+        repo.write("run.sh", b"#!/bin/sh\necho hi\n");
+        repo.commit();
+
+        let mut index = repo.stage_all();
+        let mut entry = index.get_path(Path::new("run.sh"), 0).expect("entry");
+        entry.mode = 0o100755;
+        index.add(&entry).expect("update mode");
+
+        let gitlink = |id: &str| git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o160000,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: git2::Oid::from_str(id).expect("oid"),
+            flags: 0,
+            flags_extended: 0,
+            path: b"vendor/foo".to_vec(),
+        };
+        index
+            .add(&gitlink("1111111111111111111111111111111111111111"))
+            .expect("add gitlink");
+        let tree_id = index.write_tree().expect("tree");
+        let tree = repo.repo.find_tree(tree_id).expect("find tree");
+        let signature = Signature::now("Test", "test@example.com").expect("signature");
+        let head = repo
+            .repo
+            .head()
+            .expect("head")
+            .peel_to_commit()
+            .expect("commit");
+        repo.repo
+            .commit(Some("HEAD"), &signature, &signature, "pin", &tree, &[&head])
+            .expect("commit");
+
+        index
+            .add(&gitlink("2222222222222222222222222222222222222222"))
+            .expect("bump gitlink");
+        entry.mode = 0o100644;
+        index.add(&entry).expect("restore mode");
+
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let kind_of = |path: &str| {
+            diff.files
+                .iter()
+                .find(|file| file.path == path)
+                .map(|file| file.kind.clone())
+                .expect("file present")
+        };
+        assert_eq!(
+            kind_of("run.sh"),
+            ChangeKind::ModeChanged {
+                old_mode: 0o100755,
+                new_mode: 0o100644
+            }
+        );
+        assert_eq!(
+            kind_of("vendor/foo"),
+            ChangeKind::Submodule {
+                old: Some("1111111".to_string()),
+                new: Some("2222222".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn applies_a_subset_of_staged_hunks() {
+        let repo = TempRepo::new("hunks");
+        let lines: Vec<String> = (1..=20).map(|n| format!("line {n}\n")).collect();
+        repo.write("notes.txt", lines.concat().as_bytes());
+        repo.commit();
+
+        let mut changed = lines.clone();
+        changed[1] = "line two\n".to_string();
+        changed.insert(17, "line 17.5\n".to_string());
+        repo.write("notes.txt", changed.concat().as_bytes());
+        let index = repo.stage_all();
+        let head = repo.repo.head().unwrap().peel_to_tree().unwrap();
+
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let headers = diff.files[0]
+            .lines
+            .iter()
+            .filter(|line| line.starts_with("@@"));
+        assert_eq!(headers.count(), 2);
+
+        let apply = |selected: &[usize]| {
+            String::from_utf8(
+                apply_hunks(&repo.repo, Some(&head), &index, "notes.txt", selected).expect("apply"),
+            )
+            .unwrap()
+        };
+        let mut first_only = lines.clone();
+        first_only[1] = "line two\n".to_string();
+        let mut second_only = lines.clone();
+        second_only.insert(17, "line 17.5\n".to_string());
+        assert_eq!(apply(&[]), lines.concat());
+        assert_eq!(apply(&[0]), first_only.concat());
+        assert_eq!(apply(&[1]), second_only.concat());
+        assert_eq!(apply(&[0, 1]), changed.concat());
+    }
+}
@@ -0,0 +1,772 @@
+use crate::cancel::{AbortReason, AbortSignal};
+use crate::diff::parse_patch;
+use crate::gguf::{format_parameters, read_info, GgufInfo};
+use crate::llama::{
+    EffectiveParams, Generator, LlamaError, LlamaSession, PerfStats, SessionOptions,
+};
+use crate::{
+    canonical_model_path, expand_model_path, find_local_models, generate_commit_message,
+    get_message_language, get_model_path, is_gguf, load_persisted_model_path, missing_model_hint,
+    process_model_response, AppError, Language, Result,
+};
+use indicatif::HumanBytes;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Small staged diffs every usable model should turn into a valid subject.
+const FORMAT_PROBES: &[(&str, &str)] = &[
+    (
+        "bug fix",
+        "diff --git a/src/pager.rs b/src/pager.rs
+index 3b18e51..a9c2f04 100644
+--- a/src/pager.rs
++++ b/src/pager.rs
+@@ -12,4 +12,4 @@ impl Pager {
+     /// Number of pages needed to show every item.
+     pub fn page_count(&self) -> usize {
+-        self.total / self.per_page
++        (self.total + self.per_page - 1) / self.per_page
+     }
+",
+    ),
+    (
+        "documentation",
+        "diff --git a/README.md b/README.md
+index 8c1e2a4..f07d9b3 100644
+--- a/README.md
++++ b/README.md
+@@ -20,4 +20,10 @@ cargo install --path .
+
+ ## Usage
+
++Set the `PAGER_WIDTH` environment variable to change the column width:
++
++```bash
++PAGER_WIDTH=120 pager notes.txt
++```
++
+ Run `pager --help` for the full list of options.
+",
+    ),
+    (
+        "feature",
+        "diff --git a/src/cli.rs b/src/cli.rs
+index 5d0c7e1..2b94a6f 100644
+--- a/src/cli.rs
++++ b/src/cli.rs
+@@ -8,4 +8,6 @@ pub struct Args {
+     pub input: PathBuf,
+     /// Lines per page.
+     pub lines: usize,
++    /// Print the page number above every page.
++    pub numbered: bool,
+ }
+@@ -31,4 +33,5 @@ pub fn parse(args: &[String]) -> Args {
+         match arg.as_str() {
+             \"--lines\" => parsed.lines = next_number(&mut iter),
++            \"--numbered\" => parsed.numbered = true,
+             _ => parsed.input = PathBuf::from(arg),
+         }
+",
+    ),
+];
+
+/// Prompt and length of the short generations used to compare thread counts.
+const TUNING_PROMPT: &str = "List the days of the week, one per line.";
+const TUNING_TOKENS: usize = 32;
+
+/// A thread count must beat the configured one by this factor to be
+/// recommended, so measurement noise does not cause churn.
+const THREADS_MARGIN: f64 = 1.1;
+/// Below this many tokens per second a larger context is not worth its cost,
+/// and a faster setup is suggested instead.
+const SLOW_GENERATION_RATE: f64 = 5.0;
+/// Context size suggested for models that are fast enough and trained for it.
+const SUGGESTED_CONTEXT_SIZE: u32 = 4096;
+
+struct Probe {
+    name: &'static str,
+    /// First line of the cleaned-up response, or of the raw output when no
+    /// commit subject was found.
+    output: String,
+    valid: bool,
+}
+
+struct ThreadRun {
+    threads: i32,
+    rate: Option<f64>,
+}
+
+/// A change `git ca doctor` suggests, with the config key that makes it when
+/// there is one.
+#[derive(Debug, PartialEq)]
+struct Recommendation {
+    setting: Option<(&'static str, String)>,
+    reason: String,
+}
+
+struct Report {
+    model: PathBuf,
+    file_size: u64,
+    info: Option<GgufInfo>,
+    load_time: Duration,
+    params: EffectiveParams,
+    model_bytes: u64,
+    peak_memory: Option<u64>,
+    perf: PerfStats,
+    probes: Vec<Probe>,
+    thread_runs: Vec<ThreadRun>,
+    recommendations: Vec<Recommendation>,
+}
+
+/// `git ca doctor [--json]`: loads the model, measures it, checks that it
+/// follows the commit format and suggests settings.
+pub fn run_doctor(language: &Language, options: &SessionOptions, json: bool) -> Result<()> {
+    if !json {
+        println!("{}", language.doctor_running());
+    }
+    let model = doctor_model_path(language, json)?;
+    if !json {
+        println!(
+            "{}",
+            language
+                .doctor_using_model()
+                .replace("{}", &model.to_string_lossy())
+        );
+        eprintln!("\x1b[90m{}\x1b[0m", language.this_may_take_moment());
+    }
+
+    let report = collect_report(&model, language, &get_message_language(), options)?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report_json(&report)).unwrap_or_default()
+        );
+    } else {
+        print_report(&report, language);
+    }
+    Ok(())
+}
+
+/// The model `git ca` would use. JSON reports must not prompt or print, so
+/// they only consider the configured default and installed models.
+fn doctor_model_path(language: &Language, json: bool) -> Result<PathBuf> {
+    if !json {
+        return get_model_path(language);
+    }
+    load_persisted_model_path(&get_message_language())
+        .map(|stored| expand_model_path(&stored))
+        .filter(|path| path.is_file() && is_gguf(path))
+        .or_else(|| find_local_models().into_iter().next())
+        .map(|path| canonical_model_path(&path))
+        .ok_or_else(|| AppError::ModelNotFound(missing_model_hint(language).to_string()))
+}
+
+/// Loads the model and runs the probes; `message_language` is the language
+/// the sample commit messages are requested in, `language` the one the
+/// recommendations are written in.
+fn collect_report(
+    model: &Path,
+    language: &Language,
+    message_language: &Language,
+    options: &SessionOptions,
+) -> Result<Report> {
+    let info = match read_info(model) {
+        Ok(info) => Some(info),
+        Err(err) => {
+            eprintln!(
+                "[git-ca] warning: cannot read GGUF metadata from {}: {err}",
+                model.display()
+            );
+            None
+        }
+    };
+    let file_size = fs::metadata(model).map(|meta| meta.len()).unwrap_or(0);
+
+    let started = Instant::now();
+    let mut session = LlamaSession::new(model, options).map_err(AppError::from)?;
+    let load_time = started.elapsed();
+
+    session.reset_perf();
+    let mut probes = Vec::with_capacity(FORMAT_PROBES.len());
+    for (name, patch) in FORMAT_PROBES {
+        probes.push(run_probe(&mut session, name, patch, message_language)?);
+    }
+    let perf = session.perf();
+
+    let thread_runs = tune_threads(&mut session)?;
+    let params = session.params().clone();
+    let trained_context = info.as_ref().and_then(|info| info.context_length);
+    let recommendations = recommend(
+        &params,
+        trained_context,
+        &perf,
+        &probes,
+        &thread_runs,
+        language,
+    );
+
+    Ok(Report {
+        model: model.to_path_buf(),
+        file_size,
+        info,
+        load_time,
+        model_bytes: session.model_size(),
+        peak_memory: peak_memory(),
+        params,
+        perf,
+        probes,
+        thread_runs,
+        recommendations,
+    })
+}
+
+/// Passes everything through to `inner`, keeping the last response so a
+/// probe the model fails can still show what it wrote.
+struct Recording<'a> {
+    inner: &'a mut dyn Generator,
+    last_response: String,
+}
+
+impl Generator for Recording<'_> {
+    fn context_size(&self) -> usize {
+        self.inner.context_size()
+    }
+
+    fn count_tokens(&self, text: &str) -> std::result::Result<usize, LlamaError> {
+        self.inner.count_tokens(text)
+    }
+
+    fn infer_streaming(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> std::result::Result<String, LlamaError> {
+        let response = self.inner.infer_streaming(prompt, max_tokens, on_token)?;
+        self.last_response = response.clone();
+        Ok(response)
+    }
+
+    fn prime_prefix(&mut self, prefix: &str) -> std::result::Result<(), LlamaError> {
+        self.inner.prime_prefix(prefix)
+    }
+
+    fn set_abort_signal(&mut self, signal: AbortSignal) {
+        self.inner.set_abort_signal(signal);
+    }
+
+    fn abort_reason(&self) -> Option<AbortReason> {
+        self.inner.abort_reason()
+    }
+}
+
+/// Asks for a message for one probe diff the way `git ca` does for staged
+/// changes: same prompt budgeting, retry, cleanup and validation.
+fn run_probe(
+    session: &mut dyn Generator,
+    name: &'static str,
+    patch: &str,
+    language: &Language,
+) -> Result<Probe> {
+    let diff = parse_patch(patch)?;
+    let mut recording = Recording {
+        inner: session,
+        last_response: String::new(),
+    };
+    let message = generate_commit_message(&mut recording, &diff, language, language, true)?;
+    let first_line = |text: &str| {
+        text.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string()
+    };
+    Ok(match message {
+        Some(message) => Probe {
+            name,
+            output: first_line(&message),
+            valid: true,
+        },
+        None => Probe {
+            name,
+            output: process_model_response(&recording.last_response)
+                .map(|message| first_line(&message))
+                .unwrap_or_else(|| first_line(&recording.last_response)),
+            valid: false,
+        },
+    })
+}
+
+/// Measures generation speed with the configured thread count, half the
+/// cores and all of them, then restores the configured setting.
+fn tune_threads(session: &mut LlamaSession) -> Result<Vec<ThreadRun>> {
+    let configured = (session.params().threads, session.params().threads_batch);
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get() as i32)
+        .unwrap_or(configured.0);
+
+    let mut candidates = vec![configured.0, (cores / 2).max(1), cores];
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut runs = Vec::with_capacity(candidates.len());
+    for threads in candidates {
+        session.set_threads(threads, threads);
+        session.reset_perf();
+        session
+            .infer(TUNING_PROMPT, TUNING_TOKENS)
+            .map_err(AppError::from)?;
+        runs.push(ThreadRun {
+            threads,
+            rate: session.perf().generation_rate(),
+        });
+    }
+    session.set_threads(configured.0, configured.1);
+    Ok(runs)
+}
+
+fn recommend(
+    params: &EffectiveParams,
+    trained_context: Option<u64>,
+    perf: &PerfStats,
+    probes: &[Probe],
+    thread_runs: &[ThreadRun],
+    language: &Language,
+) -> Vec<Recommendation> {
+    let mut recommendations = Vec::new();
+
+    let configured_rate = thread_runs
+        .iter()
+        .find(|run| run.threads == params.threads)
+        .and_then(|run| run.rate);
+    let fastest = thread_runs
+        .iter()
+        .filter_map(|run| run.rate.map(|rate| (run.threads, rate)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let (Some(current), Some((threads, rate))) = (configured_rate, fastest) {
+        if threads != params.threads && rate > current * THREADS_MARGIN {
+            recommendations.push(Recommendation {
+                setting: Some(("commit-analyzer.llama.threads", threads.to_string())),
+                reason: language
+                    .doctor_recommend_threads()
+                    .replace("{threads}", &threads.to_string())
+                    .replace("{rate}", &format!("{rate:.1}"))
+                    .replace("{current}", &format!("{current:.1}"))
+                    .replace("{configured}", &params.threads.to_string()),
+            });
+        }
+    }
+
+    let generation_rate = perf.generation_rate();
+    match trained_context {
+        Some(trained) if trained < u64::from(params.n_ctx) => {
+            recommendations.push(Recommendation {
+                setting: Some(("commit-analyzer.llama.contextSize", trained.to_string())),
+                reason: language
+                    .doctor_recommend_trained_context()
+                    .replace("{trained}", &trained.to_string())
+                    .replace("{current}", &params.n_ctx.to_string()),
+            });
+        }
+        Some(trained)
+            if params.n_ctx < SUGGESTED_CONTEXT_SIZE
+                && trained >= u64::from(SUGGESTED_CONTEXT_SIZE)
+                && generation_rate.is_some_and(|rate| rate >= SLOW_GENERATION_RATE) =>
+        {
+            recommendations.push(Recommendation {
+                setting: Some((
+                    "commit-analyzer.llama.contextSize",
+                    SUGGESTED_CONTEXT_SIZE.to_string(),
+                )),
+                reason: language
+                    .doctor_recommend_larger_context()
+                    .replace("{trained}", &trained.to_string()),
+            });
+        }
+        _ => {}
+    }
+
+    let passed = probes.iter().filter(|probe| probe.valid).count();
+    if passed < probes.len() {
+        recommendations.push(Recommendation {
+            setting: None,
+            reason: language
+                .doctor_recommend_better_model()
+                .replace("{passed}", &passed.to_string())
+                .replace("{total}", &probes.len().to_string()),
+        });
+    }
+
+    if generation_rate.is_some_and(|rate| rate < SLOW_GENERATION_RATE) {
+        recommendations.push(Recommendation {
+            setting: None,
+            reason: if params.gpu_layers == 0 {
+                language.doctor_recommend_gpu_layers().to_string()
+            } else {
+                language.doctor_recommend_smaller_model().to_string()
+            },
+        });
+    }
+
+    recommendations
+}
+
+/// Peak resident memory of this process in bytes.
+#[cfg(unix)]
+fn peak_memory() -> Option<u64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let max = unsafe { usage.assume_init() }.ru_maxrss.max(0) as u64;
+    // Linux reports kilobytes, macOS bytes.
+    Some(if cfg!(target_os = "macos") {
+        max
+    } else {
+        max * 1024
+    })
+}
+
+#[cfg(not(unix))]
+fn peak_memory() -> Option<u64> {
+    None
+}
+
+fn format_rate(rate: Option<f64>, language: &Language) -> String {
+    rate.map_or_else(
+        || language.doctor_rate_unavailable().to_string(),
+        |rate| language.doctor_rate().replace("{}", &format!("{rate:.1}")),
+    )
+}
+
+fn print_report(report: &Report, language: &Language) {
+    let unknown = || language.model_info_unknown().to_string();
+    let line = |template: &str, value: &str| println!("  {}", template.replace("{}", value));
+
+    println!("\n{}", language.doctor_section_model());
+    line(
+        language.model_info_size(),
+        &HumanBytes(report.file_size).to_string(),
+    );
+    if let Some(info) = &report.info {
+        line(
+            language.model_info_name(),
+            &info.name.clone().unwrap_or_else(unknown),
+        );
+        line(
+            language.model_info_architecture(),
+            &info.architecture.clone().unwrap_or_else(unknown),
+        );
+        line(
+            language.model_info_quantization(),
+            &info
+                .quantization()
+                .map(str::to_string)
+                .unwrap_or_else(unknown),
+        );
+        line(
+            language.model_info_parameters(),
+            &format_parameters(info.parameters),
+        );
+        line(
+            language.doctor_trained_context(),
+            &info
+                .context_length
+                .map(|length| length.to_string())
+                .unwrap_or_else(unknown),
+        );
+        line(
+            language.model_info_gguf_version(),
+            &info.version.to_string(),
+        );
+    }
+
+    let params = &report.params;
+    println!("\n{}", language.doctor_section_settings());
+    line(
+        language.model_info_context_length(),
+        &params.n_ctx.to_string(),
+    );
+    println!(
+        "  {}",
+        language
+            .doctor_threads()
+            .replace("{threads}", &params.threads.to_string())
+            .replace("{batch}", &params.threads_batch.to_string())
+    );
+    println!(
+        "  {}",
+        language
+            .doctor_batch_size()
+            .replace("{batch}", &params.batch_size.to_string())
+            .replace("{ubatch}", &params.ubatch_size.to_string())
+    );
+    line(language.doctor_gpu_layers(), &params.gpu_layers.to_string());
+    println!(
+        "  {}",
+        language
+            .doctor_memory_mapping()
+            .replace("{mmap}", &params.mmap.to_string())
+            .replace("{mlock}", &params.mlock.to_string())
+    );
+    line(
+        language.doctor_flash_attention(),
+        params.flash_attention.as_str(),
+    );
+    line(
+        language.doctor_kv_cache_type(),
+        params
+            .kv_cache_type
+            .map_or(language.doctor_kv_cache_other(), |kind| kind.as_str()),
+    );
+
+    println!("\n{}", language.doctor_section_performance());
+    line(
+        language.doctor_load_time(),
+        &format!("{:.2}", report.load_time.as_secs_f64()),
+    );
+    line(
+        language.doctor_prompt_rate(),
+        &format_rate(report.perf.prompt_rate(), language),
+    );
+    line(
+        language.doctor_generation_rate(),
+        &format_rate(report.perf.generation_rate(), language),
+    );
+    for run in &report.thread_runs {
+        println!(
+            "  {}",
+            language
+                .doctor_thread_rate()
+                .replace("{threads}", &run.threads.to_string())
+                .replace("{rate}", &format_rate(run.rate, language))
+        );
+    }
+    line(
+        language.doctor_model_weights(),
+        &HumanBytes(report.model_bytes).to_string(),
+    );
+    if let Some(peak) = report.peak_memory {
+        line(language.doctor_peak_memory(), &HumanBytes(peak).to_string());
+    }
+
+    let passed = report.probes.iter().filter(|probe| probe.valid).count();
+    println!(
+        "\n{}",
+        language
+            .doctor_commit_format()
+            .replace("{passed}", &passed.to_string())
+            .replace("{total}", &report.probes.len().to_string())
+    );
+    for probe in &report.probes {
+        println!(
+            "  {} {}: {}",
+            if probe.valid {
+                language.doctor_probe_ok()
+            } else {
+                language.doctor_probe_failed()
+            },
+            probe.name,
+            probe.output
+        );
+    }
+
+    println!("\n{}", language.doctor_recommendations());
+    if report.recommendations.is_empty() {
+        println!("  {}", language.doctor_no_recommendations());
+    }
+    for recommendation in &report.recommendations {
+        println!("  - {}", recommendation.reason);
+        if let Some((key, value)) = &recommendation.setting {
+            println!("    git config --global {key} {value}");
+        }
+    }
+}
+
+fn report_json(report: &Report) -> Value {
+    let params = &report.params;
+    let info = report.info.as_ref();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "model": {
+            "path": report.model.display().to_string(),
+            "file_size": report.file_size,
+            "name": info.and_then(|info| info.name.clone()),
+            "architecture": info.and_then(|info| info.architecture.clone()),
+            "quantization": info.and_then(GgufInfo::quantization),
+            "parameters": info.map(|info| info.parameters),
+            "context_length": info.and_then(|info| info.context_length),
+            "tensors": info.map(|info| info.tensor_count),
+            "gguf_version": info.map(|info| info.version),
+        },
+        "settings": {
+            "context_size": params.n_ctx,
+            "threads": params.threads,
+            "threads_batch": params.threads_batch,
+            "batch_size": params.batch_size,
+            "ubatch_size": params.ubatch_size,
+            "gpu_layers": params.gpu_layers,
+            "mmap": params.mmap,
+            "mlock": params.mlock,
+            "flash_attention": params.flash_attention.as_str(),
+            "kv_cache_type": params.kv_cache_type.map(|kind| kind.as_str()),
+        },
+        "performance": {
+            "load_ms": report.load_time.as_millis() as u64,
+            "prompt_tokens": report.perf.prompt_tokens,
+            "prompt_tokens_per_sec": report.perf.prompt_rate(),
+            "generated_tokens": report.perf.generated_tokens,
+            "generation_tokens_per_sec": report.perf.generation_rate(),
+            "threads": report.thread_runs.iter().map(|run| json!({
+                "threads": run.threads,
+                "generation_tokens_per_sec": run.rate,
+            })).collect::<Vec<_>>(),
+        },
+        "memory": {
+            "model_bytes": report.model_bytes,
+            "peak_rss_bytes": report.peak_memory,
+        },
+        "format": {
+            "passed": report.probes.iter().filter(|probe| probe.valid).count(),
+            "total": report.probes.len(),
+            "probes": report.probes.iter().map(|probe| json!({
+                "name": probe.name,
+                "valid": probe.valid,
+                "output": probe.output,
+            })).collect::<Vec<_>>(),
+        },
+        "recommendations": report.recommendations.iter().map(|recommendation| json!({
+            "setting": recommendation.setting.as_ref().map(|(key, _)| key),
+            "value": recommendation.setting.as_ref().map(|(_, value)| value),
+            "reason": recommendation.reason,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::{FlashAttention, ScriptedGenerator};
+
+    fn params(n_ctx: u32, threads: i32) -> EffectiveParams {
+        EffectiveParams {
+            n_ctx,
+            threads,
+            threads_batch: threads,
+            batch_size: 512,
+            ubatch_size: 512,
+            gpu_layers: 0,
+            mmap: true,
+            mlock: false,
+            flash_attention: FlashAttention::Auto,
+            kv_cache_type: None,
+        }
+    }
+
+    fn perf(generated_tokens: usize, generation_ms: f64) -> PerfStats {
+        PerfStats {
+            generated_tokens,
+            generation_ms,
+            ..PerfStats::default()
+        }
+    }
+
+    fn scripted(responses: &[&str]) -> ScriptedGenerator {
+        ScriptedGenerator {
+            context_size: 8192,
+            responses: responses
+                .iter()
+                .map(|response| Ok(response.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn probes_are_scored_like_generated_messages() {
+        let english = Language::english();
+        let (name, patch) = FORMAT_PROBES[0];
+
+        let mut session =
+            scripted(&["<think>pagination</think>\nfix(pager): round the page count up"]);
+        let valid = run_probe(&mut session, name, patch, &english).unwrap();
+        assert!(valid.valid);
+        assert_eq!(valid.output, "fix(pager): round the page count up");
+        assert!(session.prompts[0].contains("src/pager.rs"));
+
+        let mut session = scripted(&["\nSure! Here is a message."; 2]);
+        let invalid = run_probe(&mut session, name, patch, &english).unwrap();
+        assert!(!invalid.valid);
+        assert_eq!(invalid.output, "Sure! Here is a message.");
+        assert_eq!(session.prompts.len(), 2);
+    }
+
+    #[test]
+    fn probe_diffs_parse_like_staged_changes() {
+        for (name, patch) in FORMAT_PROBES {
+            let diff = parse_patch(patch).unwrap_or_else(|err| panic!("{name}: {err}"));
+            assert_eq!(diff.files.len(), 1, "{name}");
+            assert!(diff.files[0].additions > 0, "{name}");
+        }
+    }
+
+    #[test]
+    fn recommends_faster_threads_and_a_trained_context() {
+        let runs = [
+            ThreadRun {
+                threads: 4,
+                rate: Some(20.0),
+            },
+            ThreadRun {
+                threads: 8,
+                rate: Some(12.0),
+            },
+        ];
+        let recommendations = recommend(
+            &params(1024, 8),
+            Some(512),
+            &perf(30, 1000.0),
+            &[],
+            &runs,
+            &Language::english(),
+        );
+        assert_eq!(
+            recommendations
+                .iter()
+                .map(|recommendation| recommendation.setting.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Some(("commit-analyzer.llama.threads", "4".to_string())),
+                Some(("commit-analyzer.llama.contextSize", "512".to_string())),
+            ]
+        );
+
+        // Within the margin, and a slow model is not pushed to a larger context.
+        let close = [
+            ThreadRun {
+                threads: 4,
+                rate: Some(3.2),
+            },
+            ThreadRun {
+                threads: 8,
+                rate: Some(3.0),
+            },
+        ];
+        let recommendations = recommend(
+            &params(1024, 8),
+            Some(8192),
+            &perf(3, 1000.0),
+            &[],
+            &close,
+            &Language::english(),
+        );
+        assert_eq!(recommendations.len(), 1);
+        assert!(recommendations[0].setting.is_none());
+        assert!(recommendations[0].reason.contains("gpuLayers"));
+    }
+}
@@ -0,0 +1,900 @@
+use crate::diff::{ChangeKind, FileDiff, StagedDiff};
+use crate::{Language, COMMIT_TYPES};
+use git2::Config;
+use glob::{MatchOptions, Pattern};
+use std::collections::HashSet;
+
+const CONFIG_PATH_RULE_KEY: &str = "commit-analyzer.fallback.pathRule";
+const CONFIG_KEYWORD_RULE_KEY: &str = "commit-analyzer.fallback.keywordRule";
+const CONFIG_DEFAULT_RULES_KEY: &str = "commit-analyzer.fallback.defaultRules";
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Built-in path rules as `(glob, target)` pairs. The first matching rule wins,
+/// so more specific patterns must come before broader ones.
+const DEFAULT_PATH_RULES: &[(&str, &str)] = &[
+    // Dependency manifests and lockfiles.
+    ("Cargo.toml", "chore(deps)"),
+    ("Cargo.lock", "chore(deps)"),
+    ("package.json", "chore(deps)"),
+    ("package-lock.json", "chore(deps)"),
+    ("npm-shrinkwrap.json", "chore(deps)"),
+    ("pnpm-lock.yaml", "chore(deps)"),
+    ("yarn.lock", "chore(deps)"),
+    ("bun.lockb", "chore(deps)"),
+    ("go.mod", "chore(deps)"),
+    ("go.sum", "chore(deps)"),
+    ("requirements*.txt", "chore(deps)"),
+    ("Pipfile", "chore(deps)"),
+    ("Pipfile.lock", "chore(deps)"),
+    ("poetry.lock", "chore(deps)"),
+    ("uv.lock", "chore(deps)"),
+    ("pyproject.toml", "chore(deps)"),
+    ("Gemfile", "chore(deps)"),
+    ("Gemfile.lock", "chore(deps)"),
+    ("composer.json", "chore(deps)"),
+    ("composer.lock", "chore(deps)"),
+    ("pom.xml", "chore(deps)"),
+    ("build.gradle", "chore(deps)"),
+    ("build.gradle.kts", "chore(deps)"),
+    ("gradle.lockfile", "chore(deps)"),
+    ("Package.swift", "chore(deps)"),
+    ("Package.resolved", "chore(deps)"),
+    ("Podfile", "chore(deps)"),
+    ("Podfile.lock", "chore(deps)"),
+    ("pubspec.yaml", "chore(deps)"),
+    ("pubspec.lock", "chore(deps)"),
+    ("mix.exs", "chore(deps)"),
+    ("mix.lock", "chore(deps)"),
+    // Continuous integration.
+    (".github/workflows/**", "chore(ci)"),
+    (".gitlab-ci.yml", "chore(ci)"),
+    (".circleci/**", "chore(ci)"),
+    (".buildkite/**", "chore(ci)"),
+    (".travis.yml", "chore(ci)"),
+    ("azure-pipelines.yml", "chore(ci)"),
+    ("Jenkinsfile", "chore(ci)"),
+    // Tests.
+    ("**/tests/**", "test"),
+    ("**/test/**", "test"),
+    ("**/__tests__/**", "test"),
+    ("**/spec/**", "test"),
+    ("*_test.go", "test"),
+    ("*_test.py", "test"),
+    ("test_*.py", "test"),
+    ("*.test.[jt]s", "test"),
+    ("*.test.[jt]sx", "test"),
+    ("*.spec.[jt]s", "test"),
+    ("*.spec.[jt]sx", "test"),
+    ("*Test.java", "test"),
+    ("*Test.kt", "test"),
+    ("*Tests.swift", "test"),
+    // Build files that the `*.txt` documentation rule would otherwise claim.
+    ("CMakeLists.txt", "chore(build)"),
+    // Documentation.
+    ("docs/**", "docs"),
+    ("doc/**", "docs"),
+    ("*.md", "docs"),
+    ("*.mdx", "docs"),
+    ("*.rst", "docs"),
+    ("*.adoc", "docs"),
+    ("*.txt", "docs"),
+    ("LICENSE*", "docs"),
+    // Formatter and linter configuration.
+    (".editorconfig", "style"),
+    (".prettierrc*", "style"),
+    (".eslintrc*", "style"),
+    ("rustfmt.toml", "style"),
+    (".rustfmt.toml", "style"),
+    (".clang-format", "style"),
+    // Build and repository housekeeping.
+    ("Dockerfile", "chore(build)"),
+    ("docker-compose*.yml", "chore(build)"),
+    ("Makefile", "chore(build)"),
+    (".gitignore", "chore"),
+    (".gitattributes", "chore"),
+    (".dockerignore", "chore"),
+    ("*.min.js", "chore"),
+    ("*.min.css", "chore"),
+];
+
+/// Built-in keyword rules as `(keyword, type)` pairs, matched as whole words
+/// against lines added to source files.
+const DEFAULT_KEYWORD_RULES: &[(&str, &str)] = &[
+    ("fix", "fix"),
+    ("fixes", "fix"),
+    ("fixed", "fix"),
+    ("bug", "fix"),
+    ("bugfix", "fix"),
+    ("hotfix", "fix"),
+    ("crash", "fix"),
+    ("regression", "fix"),
+    ("workaround", "fix"),
+];
+
+#[derive(Debug, Clone)]
+pub struct PathRule {
+    pattern: Pattern,
+    match_file_name: bool,
+    commit_type: &'static str,
+    scope: Option<String>,
+}
+
+impl PathRule {
+    fn matches(&self, path: &str) -> bool {
+        if self.match_file_name {
+            let file_name = path.rsplit('/').next().unwrap_or(path);
+            self.pattern.matches_with(file_name, GLOB_OPTIONS)
+        } else {
+            self.pattern.matches_with(path, GLOB_OPTIONS)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeywordRule {
+    keyword: String,
+    commit_type: &'static str,
+}
+
+/// Ordered rule table used by the fallback generator.
+///
+/// Path rules map staged files onto a commit type and optional scope; files
+/// without a matching rule are treated as source code. Keyword rules pick a
+/// commit type from words found in lines added to source files.
+#[derive(Debug, Clone, Default)]
+pub struct FallbackRules {
+    path_rules: Vec<PathRule>,
+    keyword_rules: Vec<KeywordRule>,
+}
+
+impl FallbackRules {
+    /// Loads user rules from git config, followed by the built-in defaults
+    /// unless `commit-analyzer.fallback.defaultRules` is false.
+    ///
+    /// Rules are written as `<pattern> <type>[(<scope>)]`, for example
+    /// `git config --add commit-analyzer.fallback.pathRule "proto/** feat(api)"`
+    /// or `git config --add commit-analyzer.fallback.keywordRule "security fix"`.
+    pub fn from_config(config: &Config) -> Self {
+        let mut rules = Self::default();
+
+        for spec in read_multivar(config, CONFIG_PATH_RULE_KEY) {
+            match parse_path_rule(&spec) {
+                Ok(rule) => rules.path_rules.push(rule),
+                Err(err) => eprintln!("[git-ca] warning: ignoring path rule '{spec}': {err}"),
+            }
+        }
+
+        for spec in read_multivar(config, CONFIG_KEYWORD_RULE_KEY) {
+            match parse_keyword_rule(&spec) {
+                Ok(rule) => rules.keyword_rules.push(rule),
+                Err(err) => eprintln!("[git-ca] warning: ignoring keyword rule '{spec}': {err}"),
+            }
+        }
+
+        if config.get_bool(CONFIG_DEFAULT_RULES_KEY).unwrap_or(true) {
+            rules.push_defaults();
+        }
+
+        rules
+    }
+
+    pub(crate) fn push_defaults(&mut self) {
+        for (pattern, target) in DEFAULT_PATH_RULES {
+            let rule = parse_path_rule(&format!("{pattern} {target}"))
+                .expect("built-in path rules are valid");
+            self.path_rules.push(rule);
+        }
+        for (keyword, commit_type) in DEFAULT_KEYWORD_RULES {
+            let rule = parse_keyword_rule(&format!("{keyword} {commit_type}"))
+                .expect("built-in keyword rules are valid");
+            self.keyword_rules.push(rule);
+        }
+    }
+
+    fn match_path(&self, path: &str) -> Option<&PathRule> {
+        self.path_rules.iter().find(|rule| rule.matches(path))
+    }
+
+    fn match_keyword(&self, line: &str) -> Option<usize> {
+        let lower = line.to_lowercase();
+        self.keyword_rules
+            .iter()
+            .position(|rule| contains_word(&lower, &rule.keyword))
+    }
+}
+
+pub(crate) fn read_multivar(config: &Config, key: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    if let Ok(entries) = config.multivar(key, None) {
+        let _ = entries.for_each(|entry| {
+            if let Some(value) = entry.value() {
+                values.push(value.to_string());
+            }
+        });
+    }
+    values
+}
+
+fn split_rule_spec(spec: &str) -> Result<(&str, &str), String> {
+    let spec = spec.trim();
+    let (pattern, target) = spec
+        .rsplit_once(char::is_whitespace)
+        .ok_or_else(|| "expected '<pattern> <type>[(<scope>)]'".to_string())?;
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Err("missing pattern".to_string());
+    }
+    Ok((pattern, target))
+}
+
+fn parse_target(target: &str) -> Result<(&'static str, Option<String>), String> {
+    let (type_name, scope) = match target.split_once('(') {
+        Some((type_name, rest)) => {
+            let scope = rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("unterminated scope in '{target}'"))?;
+            let scope = slugify(scope);
+            (type_name, (!scope.is_empty()).then_some(scope))
+        }
+        None => (target, None),
+    };
+
+    let commit_type = COMMIT_TYPES
+        .iter()
+        .copied()
+        .find(|candidate| candidate.eq_ignore_ascii_case(type_name))
+        .ok_or_else(|| format!("unknown commit type '{type_name}'"))?;
+
+    Ok((commit_type, scope))
+}
+
+fn parse_path_rule(spec: &str) -> Result<PathRule, String> {
+    let (pattern, target) = split_rule_spec(spec)?;
+    let (commit_type, scope) = parse_target(target)?;
+    let pattern = pattern.trim_start_matches("./");
+    let compiled =
+        Pattern::new(pattern).map_err(|e| format!("invalid glob '{pattern}': {}", e.msg))?;
+
+    Ok(PathRule {
+        pattern: compiled,
+        match_file_name: !pattern.contains('/'),
+        commit_type,
+        scope,
+    })
+}
+
+fn parse_keyword_rule(spec: &str) -> Result<KeywordRule, String> {
+    let (keyword, target) = split_rule_spec(spec)?;
+    let (commit_type, _) = parse_target(target)?;
+    Ok(KeywordRule {
+        keyword: keyword.to_lowercase(),
+        commit_type,
+    })
+}
+
+fn contains_word(haystack: &str, needle: &str) -> bool {
+    let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+    haystack.match_indices(needle).any(|(start, _)| {
+        let before = haystack[..start].chars().next_back();
+        let after = haystack[start + needle.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+#[derive(Default)]
+struct DiffSummary {
+    files: Vec<String>,
+    scope_candidates: Vec<String>,
+    docs_files: usize,
+    test_files: usize,
+    dep_files: usize,
+    code_files: usize,
+    asset_files: usize,
+    other_types: Vec<&'static str>,
+    keyword_rule: Option<usize>,
+    new_files: HashSet<String>,
+    submodules: Vec<String>,
+    pure_renames: usize,
+    deletions: usize,
+    mode_changes: usize,
+}
+
+impl DiffSummary {
+    fn has_docs_only(&self) -> bool {
+        self.docs_files > 0 && self.docs_files == self.files.len()
+    }
+
+    fn has_tests_only(&self) -> bool {
+        self.test_files > 0 && self.test_files == self.files.len()
+    }
+
+    fn all_files(&self, count: usize) -> bool {
+        count > 0 && count == self.files.len()
+    }
+}
+
+fn analyze_diff_summary(diff: &StagedDiff, rules: &FallbackRules) -> DiffSummary {
+    let mut summary = DiffSummary::default();
+
+    for file in &diff.files {
+        if summary.files.contains(&file.path) {
+            continue;
+        }
+        summary.files.push(file.path.clone());
+
+        let untouched = file.additions == 0 && file.deletions == 0;
+        match &file.kind {
+            ChangeKind::Added => {
+                summary.new_files.insert(file.path.clone());
+            }
+            ChangeKind::Deleted => summary.deletions += 1,
+            ChangeKind::Renamed { .. } if untouched && !file.binary => summary.pure_renames += 1,
+            ChangeKind::ModeChanged { .. } if untouched => summary.mode_changes += 1,
+            ChangeKind::Submodule { .. } => {
+                summary.submodules.push(file.path.clone());
+                summary.dep_files += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let rule = rules.match_path(&file.path);
+        let scope = rule
+            .and_then(|rule| rule.scope.clone())
+            .unwrap_or_else(|| path_to_scope(&file.path));
+        if !scope.is_empty() && !summary.scope_candidates.contains(&scope) {
+            summary.scope_candidates.push(scope);
+        }
+
+        match rule {
+            None if file.binary => summary.asset_files += 1,
+            None => {
+                summary.code_files += 1;
+                let keyword = file
+                    .lines
+                    .iter()
+                    .filter_map(|line| line.strip_prefix('+'))
+                    .filter_map(|line| rules.match_keyword(line))
+                    .min();
+                if let Some(index) = keyword {
+                    summary.keyword_rule =
+                        Some(summary.keyword_rule.map_or(index, |i| i.min(index)));
+                }
+            }
+            Some(rule) if rule.scope.as_deref() == Some("deps") => summary.dep_files += 1,
+            Some(rule) if rule.commit_type == "docs" => summary.docs_files += 1,
+            Some(rule) if rule.commit_type == "test" => summary.test_files += 1,
+            Some(rule) => {
+                if !summary.other_types.contains(&rule.commit_type) {
+                    summary.other_types.push(rule.commit_type);
+                }
+            }
+        }
+    }
+
+    summary
+}
+
+/// The scope a staged file would be committed under on its own: the scope of
+/// its path rule, `docs` for documentation, otherwise the path-derived scope.
+pub(crate) fn file_scope(file: &FileDiff, rules: &FallbackRules) -> String {
+    if matches!(file.kind, ChangeKind::Submodule { .. }) {
+        return "deps".to_string();
+    }
+    let scope = match rules.match_path(&file.path) {
+        Some(PathRule {
+            scope: Some(scope), ..
+        }) => scope.clone(),
+        Some(rule) if rule.commit_type == "docs" => "docs".to_string(),
+        _ => path_to_scope(&file.path),
+    };
+    if scope.is_empty() {
+        "project".to_string()
+    } else {
+        scope
+    }
+}
+
+pub(crate) fn path_to_scope(path: &str) -> String {
+    const CONTAINER_DIRS: &[&str] = &[
+        "src", "lib", "libs", "pkg", "internal", "cmd", "crates", "packages", "apps", "modules",
+    ];
+
+    let mut trimmed = path.trim_start_matches("./");
+    if trimmed.starts_with("a/") || trimmed.starts_with("b/") {
+        trimmed = &trimmed[2..];
+    }
+    if trimmed.is_empty() {
+        return String::new();
+    }
+    let mut parts = trimmed.split('/');
+    let mut candidate = parts.next().unwrap_or(trimmed);
+    while CONTAINER_DIRS.contains(&candidate) {
+        match parts.next() {
+            Some(next) => candidate = next,
+            None => break,
+        }
+    }
+    let candidate = candidate.split('.').next().unwrap_or(candidate);
+    slugify(candidate)
+}
+
+pub(crate) fn slugify(input: &str) -> String {
+    let mut slug = String::new();
+    let mut last_dash = false;
+    for ch in input.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+            last_dash = false;
+        } else if !last_dash {
+            slug.push('-');
+            last_dash = true;
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+fn humanize_slug(slug: &str) -> String {
+    if slug.eq_ignore_ascii_case("cli") {
+        return "CLI".to_string();
+    }
+    if slug.eq_ignore_ascii_case("ci") {
+        return "CI".to_string();
+    }
+    if slug.eq_ignore_ascii_case("api") {
+        return "API".to_string();
+    }
+    if slug.eq_ignore_ascii_case("deps") {
+        return "Dependencies".to_string();
+    }
+    let parts: Vec<String> = slug
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            if let Some(first) = chars.next() {
+                format!(
+                    "{}{}",
+                    first.to_ascii_uppercase(),
+                    chars.as_str().to_ascii_lowercase()
+                )
+            } else {
+                String::new()
+            }
+        })
+        .collect();
+    if parts.is_empty() {
+        "Project".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+enum SubjectTemplate {
+    BumpSubmodule,
+    BumpSubmodules,
+    RenameScope,
+    RemoveScope,
+    UpdatePermissions,
+    UpdateAssets,
+    SyncDocsAndCode,
+    UpdateDocs,
+    UpdateTests,
+    IntroduceScope,
+    FixScope,
+    RefineScope,
+    UpdateScope,
+    UpdateDeps,
+}
+
+impl SubjectTemplate {
+    fn for_type(commit_type: &str) -> Self {
+        match commit_type {
+            "feat" => SubjectTemplate::IntroduceScope,
+            "fix" => SubjectTemplate::FixScope,
+            "refactor" => SubjectTemplate::RefineScope,
+            "docs" => SubjectTemplate::UpdateDocs,
+            "test" => SubjectTemplate::UpdateTests,
+            _ => SubjectTemplate::UpdateScope,
+        }
+    }
+}
+
+fn build_subject(language: &Language, template: SubjectTemplate, scope: &str) -> String {
+    let template = match template {
+        SubjectTemplate::BumpSubmodule => language.subject_bump_submodule(),
+        SubjectTemplate::BumpSubmodules => language.subject_bump_submodules(),
+        SubjectTemplate::RenameScope => language.subject_rename(),
+        SubjectTemplate::RemoveScope => language.subject_remove(),
+        SubjectTemplate::UpdatePermissions => language.subject_permissions(),
+        SubjectTemplate::UpdateAssets => language.subject_assets(),
+        SubjectTemplate::UpdateDeps => language.subject_deps(),
+        SubjectTemplate::SyncDocsAndCode => language.subject_sync_docs(),
+        SubjectTemplate::UpdateDocs => language.subject_docs(),
+        SubjectTemplate::UpdateTests => language.subject_tests(),
+        SubjectTemplate::IntroduceScope => language.subject_introduce(),
+        SubjectTemplate::FixScope => language.subject_fix(),
+        SubjectTemplate::RefineScope => language.subject_refine(),
+        SubjectTemplate::UpdateScope => language.subject_update(),
+    };
+    template.replace("{scope}", scope)
+}
+
+fn build_scope_readable(scopes: &[String], language: &Language) -> String {
+    if scopes.is_empty() {
+        return language.scope_project().to_string();
+    }
+
+    let words: Vec<String> = scopes.iter().map(|slug| humanize_slug(slug)).collect();
+    match words.len() {
+        1 => words[0].clone(),
+        2 => language
+            .scope_pair()
+            .replace("{first}", &words[0])
+            .replace("{second}", &words[1]),
+        _ => language.scope_more().replace("{first}", &words[0]),
+    }
+}
+
+fn build_scope_slug(scopes: &[String]) -> String {
+    if scopes.is_empty() {
+        return String::new();
+    }
+    if scopes.iter().any(|s| s == "deps") {
+        return "deps".to_string();
+    }
+    if scopes.iter().any(|s| s == "docs") && scopes.len() == 1 {
+        return "docs".to_string();
+    }
+    scopes.iter().take(2).cloned().collect::<Vec<_>>().join("-")
+}
+
+fn compute_scopes(summary: &DiffSummary) -> Vec<String> {
+    fn push_unique(scopes: &mut Vec<String>, value: &str) {
+        if !scopes.iter().any(|s| s == value) {
+            scopes.push(value.to_string());
+        }
+    }
+
+    let mut scopes = Vec::new();
+
+    if summary.has_docs_only() {
+        push_unique(&mut scopes, "docs");
+    }
+    if summary.dep_files > 0 {
+        push_unique(&mut scopes, "deps");
+    }
+
+    for candidate in &summary.scope_candidates {
+        if scopes.len() >= 3 {
+            break;
+        }
+        push_unique(&mut scopes, candidate);
+    }
+
+    if scopes.is_empty() {
+        push_unique(&mut scopes, "project");
+    }
+
+    scopes
+}
+
+pub(crate) fn generate_fallback_commit_message(
+    diff: &StagedDiff,
+    language: &Language,
+    rules: &FallbackRules,
+) -> Option<String> {
+    let summary = analyze_diff_summary(diff, rules);
+    if summary.files.is_empty() {
+        return None;
+    }
+
+    let mut scopes = compute_scopes(&summary);
+    let has_code = summary.code_files > 0;
+    let code_type = if summary.new_files.is_empty() {
+        "refactor"
+    } else {
+        "feat"
+    };
+
+    if summary.all_files(summary.submodules.len()) {
+        let subject = match summary.submodules.as_slice() {
+            [path] => build_subject(language, SubjectTemplate::BumpSubmodule, path),
+            _ => build_subject(language, SubjectTemplate::BumpSubmodules, ""),
+        };
+        return Some(format!("chore(deps): {subject}"));
+    }
+
+    let (commit_type, template) = if summary.all_files(summary.pure_renames) {
+        let commit_type = if summary.has_docs_only() {
+            "docs"
+        } else if has_code {
+            "refactor"
+        } else {
+            "chore"
+        };
+        (commit_type, SubjectTemplate::RenameScope)
+    } else if summary.all_files(summary.deletions) {
+        let commit_type = if summary.has_docs_only() {
+            "docs"
+        } else if summary.has_tests_only() {
+            "test"
+        } else if has_code {
+            "refactor"
+        } else {
+            "chore"
+        };
+        (commit_type, SubjectTemplate::RemoveScope)
+    } else if summary.all_files(summary.mode_changes) {
+        ("chore", SubjectTemplate::UpdatePermissions)
+    } else if let Some(index) = summary.keyword_rule {
+        let commit_type = rules.keyword_rules[index].commit_type;
+        (commit_type, SubjectTemplate::for_type(commit_type))
+    } else if summary.docs_files > 0 && has_code {
+        (code_type, SubjectTemplate::SyncDocsAndCode)
+    } else if summary.has_docs_only() {
+        ("docs", SubjectTemplate::UpdateDocs)
+    } else if summary.has_tests_only() {
+        ("test", SubjectTemplate::UpdateTests)
+    } else if summary.dep_files > 0 && !has_code {
+        ("chore", SubjectTemplate::UpdateDeps)
+    } else if has_code {
+        (code_type, SubjectTemplate::for_type(code_type))
+    } else if summary.all_files(summary.asset_files) {
+        ("chore", SubjectTemplate::UpdateAssets)
+    } else if let [only_type] = summary.other_types.as_slice() {
+        if summary.docs_files + summary.test_files + summary.asset_files == 0 {
+            (*only_type, SubjectTemplate::UpdateScope)
+        } else {
+            ("chore", SubjectTemplate::UpdateScope)
+        }
+    } else {
+        ("chore", SubjectTemplate::UpdateScope)
+    };
+
+    if matches!(template, SubjectTemplate::UpdateDeps) {
+        scopes.clear();
+        scopes.push("deps".to_string());
+    }
+
+    let scope_slug = build_scope_slug(&scopes);
+    let scope_readable = build_scope_readable(&scopes, language);
+    let subject = build_subject(language, template, &scope_readable);
+
+    Some(if scope_slug.is_empty() {
+        format!("{commit_type}: {subject}")
+    } else {
+        format!("{commit_type}({scope_slug}): {subject}")
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::diff::tests::file_diff;
+    use crate::diff::FileDiff;
+
+    /// The built-in rules, without any user rules.
+    pub(crate) fn default_rules() -> FallbackRules {
+        let mut rules = FallbackRules::default();
+        rules.push_defaults();
+        rules
+    }
+
+    fn fallback_with(rules: &FallbackRules, files: Vec<FileDiff>) -> String {
+        let diff = StagedDiff {
+            files,
+            text: String::new(),
+        };
+        generate_fallback_commit_message(&diff, &Language::english(), rules).expect("fallback")
+    }
+
+    fn fallback(files: Vec<FileDiff>) -> String {
+        fallback_with(&default_rules(), files)
+    }
+
+    #[test]
+    fn fallback_generates_for_docs_only() {
+        let message = fallback(vec![file_diff(
+            "AGENTS.md",
+            ChangeKind::Added,
+            &["+# Repository Guidelines"],
+        )]);
+        assert!(message.starts_with("docs("));
+        assert!(message.contains("documentation"));
+    }
+
+    #[test]
+    fn fallback_uses_keyword_rules_for_source_changes() {
+        let message = fallback(vec![file_diff(
+            "src/parser.rs",
+            ChangeKind::Modified,
+            &["+    // Fix crash when the input ends with a newline."],
+        )]);
+        assert_eq!(message, "fix(parser): resolve Parser issues");
+    }
+
+    #[test]
+    fn fix_subjects_do_not_repeat_the_type() {
+        let message = fallback(vec![file_diff(
+            "src/net/retry.rs",
+            ChangeKind::Modified,
+            &["+    // Work around a regression in the proxy handshake."],
+        )]);
+        let (prefix, subject) = message.split_once(": ").expect("subject");
+        assert_eq!(prefix, "fix(net)");
+        assert!(!subject.starts_with("fix"), "{message}");
+    }
+
+    #[test]
+    fn fallback_ignores_keywords_inside_words() {
+        let message = fallback(vec![file_diff(
+            "src/router.rs",
+            ChangeKind::Modified,
+            &["+    let prefix = strip_prefix(path);"],
+        )]);
+        assert_eq!(message, "refactor(router): refine Router");
+    }
+
+    #[test]
+    fn fallback_has_no_project_specific_scopes() {
+        let message = fallback(vec![
+            file_diff(
+                "src/main.rs",
+                ChangeKind::Modified,
+                &["+ println!(\"Retrying with stricter instructions...\");"],
+            ),
+            file_diff(
+                "src/llama.rs",
+                ChangeKind::Added,
+                &["+ llama_kv_self_clear(ctx);"],
+            ),
+        ]);
+        assert_eq!(message, "feat(main-llama): add Main and Llama");
+    }
+
+    #[test]
+    fn fallback_handles_dependency_updates() {
+        let message = fallback(vec![
+            file_diff(
+                "package.json",
+                ChangeKind::Modified,
+                &["+  \"llama-kit\": \"^2.0.0\""],
+            ),
+            file_diff("pnpm-lock.yaml", ChangeKind::Modified, &["+packages:"]),
+        ]);
+        assert_eq!(message, "chore(deps): update dependencies");
+    }
+
+    #[test]
+    fn fallback_detects_test_and_ci_changes() {
+        let tests = fallback(vec![file_diff(
+            "pkg/api/handler_test.go",
+            ChangeKind::Modified,
+            &["+func TestHandler(t *testing.T) {}"],
+        )]);
+        assert_eq!(tests, "test(api): update API tests");
+
+        let ci = fallback(vec![file_diff(
+            ".github/workflows/release.yml",
+            ChangeKind::Modified,
+            &["+      - run: cargo test"],
+        )]);
+        assert_eq!(ci, "chore(ci): update CI");
+    }
+
+    #[test]
+    fn specific_txt_rules_win_over_docs() {
+        let cmake = fallback(vec![file_diff(
+            "CMakeLists.txt",
+            ChangeKind::Modified,
+            &["+add_subdirectory(tools)"],
+        )]);
+        assert!(cmake.starts_with("chore(build): "), "{cmake}");
+
+        let requirements = fallback(vec![file_diff(
+            "requirements-dev.txt",
+            ChangeKind::Modified,
+            &["+pytest==8.0"],
+        )]);
+        assert_eq!(requirements, "chore(deps): update dependencies");
+
+        let notes = fallback(vec![file_diff(
+            "NOTES.txt",
+            ChangeKind::Modified,
+            &["+ todo"],
+        )]);
+        assert!(notes.starts_with("docs"), "{notes}");
+    }
+
+    #[test]
+    fn fallback_describes_submodule_bumps() {
+        let submodule = |path: &str| {
+            file_diff(
+                path,
+                ChangeKind::Submodule {
+                    old: Some("1111111".to_string()),
+                    new: Some("2222222".to_string()),
+                },
+                &[],
+            )
+        };
+        assert_eq!(
+            fallback(vec![submodule("vendor/foo")]),
+            "chore(deps): bump vendor/foo submodule"
+        );
+        assert_eq!(
+            fallback(vec![submodule("vendor/foo"), submodule("vendor/bar")]),
+            "chore(deps): bump submodules"
+        );
+    }
+
+    #[test]
+    fn fallback_describes_renames_mode_changes_and_assets() {
+        let renamed = fallback(vec![file_diff(
+            "src/net/client.rs",
+            ChangeKind::Renamed {
+                from: "src/net/http.rs".to_string(),
+            },
+            &[],
+        )]);
+        assert_eq!(renamed, "refactor(net): rename Net files");
+
+        let mode = fallback(vec![file_diff(
+            "scripts/release.sh",
+            ChangeKind::ModeChanged {
+                old_mode: 0o100644,
+                new_mode: 0o100755,
+            },
+            &[],
+        )]);
+        assert_eq!(mode, "chore(scripts): update Scripts file permissions");
+
+        let mut logo = file_diff("assets/logo.png", ChangeKind::Modified, &[]);
+        logo.binary = true;
+        assert_eq!(
+            fallback(vec![logo]),
+            "chore(assets): update Assets binary files"
+        );
+    }
+
+    #[test]
+    fn user_rules_take_precedence_over_defaults() {
+        let mut rules = FallbackRules::default();
+        rules
+            .path_rules
+            .push(parse_path_rule("proto/** feat(api)").expect("rule"));
+        rules.push_defaults();
+
+        let message = fallback_with(
+            &rules,
+            vec![file_diff(
+                "proto/user.proto",
+                ChangeKind::Modified,
+                &["+  string email = 2;"],
+            )],
+        );
+        assert_eq!(message, "feat(api): update API");
+    }
+
+    #[test]
+    fn parses_rule_specs() {
+        let rule = parse_path_rule("./docs/** docs").expect("path rule");
+        assert_eq!(rule.commit_type, "docs");
+        assert!(rule.matches("docs/guide/intro.md"));
+        assert!(!rule.matches("src/docs.rs"));
+
+        let rule = parse_keyword_rule("security hole fix").expect("keyword rule");
+        assert_eq!(rule.keyword, "security hole");
+        assert_eq!(rule.commit_type, "fix");
+
+        assert!(parse_path_rule("*.md").is_err());
+        assert!(parse_path_rule("*.md build").is_err());
+        assert!(parse_keyword_rule("oops fix(").is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Just enough of the GGUF container format to describe a model file without
+//! loading it: the metadata key/values and the tensor shapes.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+/// Longest string accepted from a file, so a corrupt length cannot make us
+/// allocate gigabytes.
+const MAX_STRING_LEN: u64 = 1 << 24;
+const MAX_TENSOR_DIMS: u32 = 8;
+
+const TYPE_U8: u32 = 0;
+const TYPE_I8: u32 = 1;
+const TYPE_U16: u32 = 2;
+const TYPE_I16: u32 = 3;
+const TYPE_U32: u32 = 4;
+const TYPE_I32: u32 = 5;
+const TYPE_F32: u32 = 6;
+const TYPE_BOOL: u32 = 7;
+const TYPE_STRING: u32 = 8;
+const TYPE_ARRAY: u32 = 9;
+const TYPE_U64: u32 = 10;
+const TYPE_I64: u32 = 11;
+const TYPE_F64: u32 = 12;
+
+/// What `git ca model list` and `model info` show about a model file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GgufInfo {
+    pub version: u32,
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    /// Parameter-count label from the metadata, e.g. `270M`.
+    pub size_label: Option<String>,
+    /// `general.file_type`, llama.cpp's `llama_ftype`.
+    pub file_type: Option<u32>,
+    /// Context length the model was trained with.
+    pub context_length: Option<u64>,
+    /// Tensors in the model, across all shards of a split model.
+    pub tensor_count: u64,
+    /// Total number of weights across all tensors.
+    pub parameters: u64,
+    /// Set for one shard of a split model: `(index, count)`, 1-based index.
+    pub split: Option<(u16, u16)>,
+}
+
+impl GgufInfo {
+    /// The quantization name llama.cpp uses for `file_type`, e.g. `Q4_K_M`.
+    pub fn quantization(&self) -> Option<&'static str> {
+        let name = match self.file_type? {
+            0 => "F32",
+            1 => "F16",
+            2 => "Q4_0",
+            3 => "Q4_1",
+            7 => "Q8_0",
+            8 => "Q5_0",
+            9 => "Q5_1",
+            10 => "Q2_K",
+            11 => "Q3_K_S",
+            12 => "Q3_K_M",
+            13 => "Q3_K_L",
+            14 => "Q4_K_S",
+            15 => "Q4_K_M",
+            16 => "Q5_K_S",
+            17 => "Q5_K_M",
+            18 => "Q6_K",
+            19 => "IQ2_XXS",
+            20 => "IQ2_XS",
+            21 => "Q2_K_S",
+            22 => "IQ3_XS",
+            23 => "IQ3_XXS",
+            24 => "IQ1_S",
+            25 => "IQ4_NL",
+            26 => "IQ3_S",
+            27 => "IQ3_M",
+            28 => "IQ2_S",
+            29 => "IQ2_M",
+            30 => "IQ4_XS",
+            31 => "IQ1_M",
+            32 => "BF16",
+            36 => "TQ1_0",
+            37 => "TQ2_0",
+            38 => "MXFP4_MOE",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+/// Formats a parameter count the way model cards do: `268M`, `7.2B`.
+pub fn format_parameters(count: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [
+        (1_000_000_000_000, "T"),
+        (1_000_000_000, "B"),
+        (1_000_000, "M"),
+    ];
+    for (scale, unit) in UNITS {
+        if count >= scale {
+            let value = count as f64 / scale as f64;
+            return if value >= 100.0 {
+                format!("{value:.0}{unit}")
+            } else {
+                format!("{value:.1}{unit}")
+            };
+        }
+    }
+    count.to_string()
+}
+
+/// Splits a shard file name of the form `<prefix>-00001-of-00003.gguf`, as
+/// written by `llama-gguf-split`, into its prefix, 1-based index and count.
+pub fn split_shard(file_name: &str) -> Option<(&str, u32, u32)> {
+    let stem = file_name
+        .strip_suffix(".gguf")
+        .or_else(|| file_name.strip_suffix(".GGUF"))?;
+    let (rest, count) = stem.rsplit_once("-of-")?;
+    let (prefix, index) = rest.rsplit_once('-')?;
+    let digits = |part: &str| part.len() == 5 && part.bytes().all(|b| b.is_ascii_digit());
+    if !digits(index) || !digits(count) {
+        return None;
+    }
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    (index >= 1 && index <= count).then_some((prefix, index, count))
+}
+
+/// Every file that makes up the model at `path`: the path itself, or all
+/// shards of a split model that exist next to it, in order.
+pub fn model_files(path: &Path) -> Vec<PathBuf> {
+    let shard = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(split_shard);
+    match shard {
+        Some((prefix, _, count)) => (1..=count)
+            .map(|index| path.with_file_name(format!("{prefix}-{index:05}-of-{count:05}.gguf")))
+            .filter(|shard| shard.is_file())
+            .collect(),
+        None => vec![path.to_path_buf()],
+    }
+}
+
+/// Reads the metadata of the model at `path`. For a split model the tensors
+/// of every shard next to it are counted; everything else comes from `path`.
+pub fn read_info(path: &Path) -> io::Result<GgufInfo> {
+    let mut info = read_file_info(path)?;
+    if info.split.is_some() {
+        for shard in model_files(path).iter().filter(|shard| *shard != path) {
+            let shard = read_file_info(shard)?;
+            info.tensor_count = info.tensor_count.saturating_add(shard.tensor_count);
+            info.parameters = info.parameters.saturating_add(shard.parameters);
+        }
+    }
+    Ok(info)
+}
+
+fn read_file_info(path: &Path) -> io::Result<GgufInfo> {
+    let mut reader = GgufReader(BufReader::new(File::open(path)?));
+    reader.read_info()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Text(String),
+    Other,
+}
+
+impl Value {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Unsigned(value) => Some(*value),
+            Value::Signed(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+}
+
+struct GgufReader<R>(R);
+
+impl<R: Read> GgufReader<R> {
+    fn read_info(&mut self) -> io::Result<GgufInfo> {
+        let mut magic = [0u8; 4];
+        self.0.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a GGUF file"));
+        }
+        let version = self.u32()?;
+        if version < 2 {
+            return Err(invalid("unsupported GGUF version"));
+        }
+        let tensor_count = self.u64()?;
+        let kv_count = self.u64()?;
+
+        let mut info = GgufInfo {
+            version,
+            tensor_count,
+            ..GgufInfo::default()
+        };
+        let mut context_lengths = Vec::new();
+        let (mut split_index, mut split_count) = (None, None);
+
+        for _ in 0..kv_count {
+            let key = self.string()?;
+            let kind = self.u32()?;
+            let value = self.value(kind)?;
+            match (key.as_str(), value) {
+                ("general.architecture", Value::Text(text)) => info.architecture = Some(text),
+                ("general.name", Value::Text(text)) => info.name = Some(text),
+                ("general.size_label", Value::Text(text)) => info.size_label = Some(text),
+                ("general.file_type", value) => {
+                    info.file_type = value.as_u64().and_then(|v| u32::try_from(v).ok())
+                }
+                ("split.no", value) => split_index = value.as_u64(),
+                ("split.count", value) => split_count = value.as_u64(),
+                (key, value) if key.ends_with(".context_length") => {
+                    if let Some(length) = value.as_u64() {
+                        context_lengths.push((key.to_string(), length));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        info.context_length = context_lengths
+            .iter()
+            .find(|(key, _)| {
+                info.architecture
+                    .as_deref()
+                    .is_some_and(|arch| key == &format!("{arch}.context_length"))
+            })
+            .or(context_lengths.first())
+            .map(|(_, length)| *length);
+        if let (Some(index), Some(count)) = (split_index, split_count) {
+            if count > 1 {
+                let count =
+                    u16::try_from(count).map_err(|_| invalid("split count out of range"))?;
+                let index = u16::try_from(index)
+                    .ok()
+                    .filter(|&index| index < count)
+                    .ok_or_else(|| invalid("split index out of range"))?;
+                info.split = Some((index + 1, count));
+            }
+        }
+
+        for _ in 0..tensor_count {
+            self.string()?;
+            let dims = self.u32()?;
+            if dims > MAX_TENSOR_DIMS {
+                return Err(invalid("tensor has too many dimensions"));
+            }
+            let mut elements = 1u64;
+            for _ in 0..dims {
+                elements = elements.saturating_mul(self.u64()?);
+            }
+            self.u32()?; // element type
+            self.u64()?; // data offset
+            info.parameters = info.parameters.saturating_add(elements);
+        }
+
+        Ok(info)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buffer = [0u8; N];
+        self.0.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u64()?;
+        if length > MAX_STRING_LEN {
+            return Err(invalid("string too long"));
+        }
+        let mut buffer = vec![0u8; length as usize];
+        self.0.read_exact(&mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    fn value(&mut self, kind: u32) -> io::Result<Value> {
+        Ok(match kind {
+            TYPE_U8 | TYPE_BOOL => Value::Unsigned(u8::from_le_bytes(self.bytes()?) as u64),
+            TYPE_I8 => Value::Signed(i8::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U16 => Value::Unsigned(u16::from_le_bytes(self.bytes()?) as u64),
+            TYPE_I16 => Value::Signed(i16::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U32 => Value::Unsigned(self.u32()? as u64),
+            TYPE_I32 => Value::Signed(i32::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U64 => Value::Unsigned(self.u64()?),
+            TYPE_I64 => Value::Signed(i64::from_le_bytes(self.bytes()?)),
+            TYPE_F32 => {
+                self.bytes::<4>()?;
+                Value::Other
+            }
+            TYPE_F64 => {
+                self.bytes::<8>()?;
+                Value::Other
+            }
+            TYPE_STRING => Value::Text(self.string()?),
+            TYPE_ARRAY => {
+                let element = self.u32()?;
+                if element == TYPE_ARRAY {
+                    return Err(invalid("nested arrays are not supported"));
+                }
+                let count = self.u64()?;
+                for _ in 0..count {
+                    self.value(element)?;
+                }
+                Value::Other
+            }
+            _ => return Err(invalid("unknown metadata value type")),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Builds a small GGUF file in memory for tests.
+    pub(crate) struct GgufBuilder {
+        kvs: Vec<u8>,
+        kv_count: u64,
+        tensors: Vec<u8>,
+        tensor_count: u64,
+    }
+
+    impl GgufBuilder {
+        pub(crate) fn new() -> Self {
+            Self {
+                kvs: Vec::new(),
+                kv_count: 0,
+                tensors: Vec::new(),
+                tensor_count: 0,
+            }
+        }
+
+        fn string(buffer: &mut Vec<u8>, text: &str) {
+            buffer.extend((text.len() as u64).to_le_bytes());
+            buffer.extend(text.as_bytes());
+        }
+
+        pub(crate) fn text(mut self, key: &str, value: &str) -> Self {
+            Self::string(&mut self.kvs, key);
+            self.kvs.extend(TYPE_STRING.to_le_bytes());
+            Self::string(&mut self.kvs, value);
+            self.kv_count += 1;
+            self
+        }
+
+        pub(crate) fn uint(mut self, key: &str, value: u32) -> Self {
+            Self::string(&mut self.kvs, key);
+            self.kvs.extend(TYPE_U32.to_le_bytes());
+            self.kvs.extend(value.to_le_bytes());
+            self.kv_count += 1;
+            self
+        }
+
+        pub(crate) fn tokens(mut self, key: &str, tokens: &[&str]) -> Self {
+            Self::string(&mut self.kvs, key);
+            self.kvs.extend(TYPE_ARRAY.to_le_bytes());
+            self.kvs.extend(TYPE_STRING.to_le_bytes());
+            self.kvs.extend((tokens.len() as u64).to_le_bytes());
+            for token in tokens {
+                Self::string(&mut self.kvs, token);
+            }
+            self.kv_count += 1;
+            self
+        }
+
+        pub(crate) fn tensor(mut self, name: &str, dims: &[u64]) -> Self {
+            Self::string(&mut self.tensors, name);
+            self.tensors.extend((dims.len() as u32).to_le_bytes());
+            for dim in dims {
+                self.tensors.extend(dim.to_le_bytes());
+            }
+            self.tensors.extend(0u32.to_le_bytes());
+            self.tensors.extend(0u64.to_le_bytes());
+            self.tensor_count += 1;
+            self
+        }
+
+        pub(crate) fn build(self) -> Vec<u8> {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(3u32.to_le_bytes());
+            bytes.extend(self.tensor_count.to_le_bytes());
+            bytes.extend(self.kv_count.to_le_bytes());
+            bytes.extend(self.kvs);
+            bytes.extend(self.tensors);
+            bytes
+        }
+    }
+
+    #[test]
+    fn reads_metadata_and_counts_parameters() {
+        let bytes = GgufBuilder::new()
+            .text("general.architecture", "gemma3")
+            .text("general.name", "Gemma 3 270m It")
+            .uint("general.file_type", 15)
+            .tokens("tokenizer.ggml.tokens", &["<pad>", "<eos>", "hello"])
+            .uint("gemma3.context_length", 32768)
+            .tensor("token_embd.weight", &[640, 262144])
+            .tensor("output_norm.weight", &[640])
+            .build();
+
+        let info = GgufReader(bytes.as_slice()).read_info().expect("gguf");
+        assert_eq!(info.architecture.as_deref(), Some("gemma3"));
+        assert_eq!(info.name.as_deref(), Some("Gemma 3 270m It"));
+        assert_eq!(info.quantization(), Some("Q4_K_M"));
+        assert_eq!(info.context_length, Some(32768));
+        assert_eq!(info.tensor_count, 2);
+        assert_eq!(info.parameters, 640 * 262144 + 640);
+        assert_eq!(format_parameters(info.parameters), "168M");
+        assert_eq!(format_parameters(7_241_732_096), "7.2B");
+
+        assert!(GgufReader(&b"GGML"[..]).read_info().is_err());
+        assert!(GgufReader(&bytes[..bytes.len() - 4]).read_info().is_err());
+    }
+
+    #[test]
+    fn counts_parameters_across_shards() {
+        let dir = std::env::temp_dir().join(format!("git-ca-gguf-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let shard = |index: u32, dims: &[u64]| {
+            let bytes = GgufBuilder::new()
+                .text("general.architecture", "llama")
+                .uint("split.no", index)
+                .uint("split.count", 2)
+                .tensor(&format!("blk.{index}.weight"), dims)
+                .build();
+            let path = dir.join(format!("model-{:05}-of-00002.gguf", index + 1));
+            fs::write(&path, bytes).unwrap();
+            path
+        };
+        let first = shard(0, &[1000, 1000]);
+        shard(1, &[500, 1000]);
+
+        let info = read_info(&first).expect("gguf");
+        assert_eq!(info.split, Some((1, 2)));
+        assert_eq!(info.tensor_count, 2);
+        assert_eq!(info.parameters, 1_500_000);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_split_numbers() {
+        let split = |index: u32, count: u32| {
+            let bytes = GgufBuilder::new()
+                .uint("split.no", index)
+                .uint("split.count", count)
+                .build();
+            GgufReader(bytes.as_slice())
+                .read_info()
+                .map(|info| info.split)
+        };
+        assert_eq!(split(2, 3).expect("gguf"), Some((3, 3)));
+        assert_eq!(split(0, 1).expect("gguf"), None);
+        for (index, count) in [(65535, 2), (3, 3), (0, 65536)] {
+            let err = split(index, count).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn recognises_split_shard_names() {
+        assert_eq!(
+            split_shard("Qwen2.5-7B-Instruct-Q8_0-00002-of-00003.gguf"),
+            Some(("Qwen2.5-7B-Instruct-Q8_0", 2, 3))
+        );
+        assert_eq!(split_shard("gemma-3-270m-it-Q4_K_M.gguf"), None);
+        assert_eq!(split_shard("model-1-of-2.gguf"), None);
+        assert_eq!(split_shard("model-00004-of-00003.gguf"), None);
+    }
+}
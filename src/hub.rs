@@ -0,0 +1,727 @@
+//! Fetches GGUF models from the Hugging Face Hub, for `git ca model pull` and
+//! the first-run download of the default model.
+
+use crate::gguf::split_shard;
+use crate::progress::DownloadProgress;
+use crate::{canonical_model_path, models_root_dir, AppError, Language, Result};
+use git2::Config;
+use hf_hub::api::sync::ApiBuilder;
+use hf_hub::{Cache, Repo};
+use indicatif::HumanBytes;
+use ring::digest;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const CONFIG_OFFLINE_KEY: &str = "commit-analyzer.offline";
+const CONFIG_ENDPOINT_KEY: &str = "commit-analyzer.hub.endpoint";
+const CONFIG_PROXY_KEY: &str = "commit-analyzer.hub.proxy";
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+/// Attempts per file; each one resumes from the bytes already written.
+const DOWNLOAD_RETRIES: usize = 3;
+/// Proxy variables ureq reads, in its order of preference.
+const PROXY_VARS: [&str; 6] = [
+    "ALL_PROXY",
+    "all_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+];
+
+/// Quantization pulled when a repository offers several and none was asked
+/// for.
+const PREFERRED_QUANT: &str = "Q4_K_M";
+
+/// Whether models may be downloaded, and from where.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HubSettings {
+    /// Never touch the network; models must be added with `git ca model add`.
+    pub offline: bool,
+    /// Base URL of a Hugging Face mirror, instead of `https://huggingface.co`.
+    pub endpoint: Option<String>,
+    /// Proxy used when none is set in the environment.
+    pub proxy: Option<String>,
+}
+
+impl HubSettings {
+    /// Reads `GIT_CA_OFFLINE`, `HF_HUB_OFFLINE` and `HF_ENDPOINT`, falling
+    /// back to `commit-analyzer.offline` and `commit-analyzer.hub.endpoint`;
+    /// `commit-analyzer.hub.proxy` only applies when no proxy variable is set.
+    pub fn load() -> Self {
+        let config = Config::open_default().ok();
+        Self::resolve(
+            |name| env::var(name).ok(),
+            |key| config.as_ref()?.get_string(key).ok(),
+        )
+    }
+
+    fn resolve(
+        env: impl Fn(&str) -> Option<String>,
+        config: impl Fn(&str) -> Option<String>,
+    ) -> Self {
+        let offline = env("GIT_CA_OFFLINE")
+            .or_else(|| env("HF_HUB_OFFLINE"))
+            .or_else(|| config(CONFIG_OFFLINE_KEY))
+            .is_some_and(|value| is_truthy(&value));
+        let endpoint = env("HF_ENDPOINT")
+            .or_else(|| config(CONFIG_ENDPOINT_KEY))
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty());
+        let proxy = config(CONFIG_PROXY_KEY)
+            .filter(|_| PROXY_VARS.iter().all(|name| env(name).is_none()))
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        HubSettings {
+            offline,
+            endpoint,
+            proxy,
+        }
+    }
+}
+
+/// Git's and huggingface_hub's spellings of true.
+fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+/// The host name in `url`, without scheme, credentials or port.
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    host.split(':').next().unwrap_or(host)
+}
+
+/// Whether `host` is excluded from proxying by a `NO_PROXY` list, using the
+/// usual conventions: `*` for every host, and `example.com` or
+/// `.example.com` for the domain and its subdomains.
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy.split(',').any(|entry| {
+        let entry = entry.trim().to_ascii_lowercase();
+        let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+        let domain = domain.split(':').next().unwrap_or(domain);
+        entry == "*"
+            || (!domain.is_empty() && (host == domain || host.ends_with(&format!(".{domain}"))))
+    })
+}
+
+/// hf-hub's HTTP client picks its proxy from the environment and knows
+/// nothing of `NO_PROXY`, so both are settled here before it is built.
+fn configure_proxy(settings: &HubSettings, endpoint: &str) {
+    let no_proxy = env::var("NO_PROXY").or_else(|_| env::var("no_proxy"));
+    if no_proxy.is_ok_and(|list| bypasses_proxy(&list, url_host(endpoint))) {
+        for name in PROXY_VARS {
+            env::remove_var(name);
+        }
+    } else if let Some(proxy) = &settings.proxy {
+        env::set_var("HTTPS_PROXY", proxy);
+    }
+}
+
+/// What `git ca model pull` was asked to fetch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PullSpec {
+    pub repo: String,
+    /// Branch, tag or commit; the repository's default branch when unset.
+    pub revision: Option<String>,
+    /// A file name from the repository.
+    pub file: Option<String>,
+    /// A quantization to find in the file names, e.g. `Q5_K_M`.
+    pub quant: Option<String>,
+}
+
+impl PullSpec {
+    /// Parses `<repo>[@<revision>]`.
+    pub fn new(spec: &str) -> Self {
+        let (repo, revision) = match spec.split_once('@') {
+            Some((repo, revision)) if !revision.is_empty() => (repo, Some(revision.to_string())),
+            _ => (spec.trim_end_matches('@'), None),
+        };
+        PullSpec {
+            repo: repo.to_string(),
+            revision,
+            ..PullSpec::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RemoteFile {
+    name: String,
+    size: Option<u64>,
+    /// SHA-256 from the file's LFS pointer, in lowercase hex.
+    sha256: Option<String>,
+}
+
+/// One downloadable model: a single GGUF file, or every shard of a split one
+/// in order.
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    files: Vec<RemoteFile>,
+}
+
+impl Candidate {
+    fn name(&self) -> &str {
+        &self.files[0].name
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    fn contains(&self, file_name: &str) -> bool {
+        let wanted = file_name.to_ascii_lowercase();
+        self.files.iter().any(|file| {
+            let name = file.name.to_ascii_lowercase();
+            let base = name.rsplit('/').next().unwrap_or(&name);
+            [name.as_str(), base].iter().any(|candidate| {
+                *candidate == wanted || candidate.strip_suffix(".gguf") == Some(&wanted)
+            })
+        })
+    }
+}
+
+/// Reads the file list from the Hub's `?blobs=true` repository listing.
+fn remote_files(listing: &serde_json::Value) -> Vec<RemoteFile> {
+    let Some(siblings) = listing["siblings"].as_array() else {
+        return Vec::new();
+    };
+    siblings
+        .iter()
+        .filter_map(|sibling| {
+            let name = sibling["rfilename"].as_str()?.to_string();
+            let size = sibling["size"]
+                .as_u64()
+                .or_else(|| sibling["lfs"]["size"].as_u64());
+            let sha256 = sibling["lfs"]["sha256"]
+                .as_str()
+                .map(str::to_ascii_lowercase);
+            Some(RemoteFile { name, size, sha256 })
+        })
+        .collect()
+}
+
+/// Groups the GGUF files of a repository into models. Vision projectors
+/// (`mmproj`) are left out since they cannot generate text on their own.
+fn candidates(files: Vec<RemoteFile>) -> Vec<Candidate> {
+    let mut models: BTreeMap<String, Vec<(u32, RemoteFile)>> = BTreeMap::new();
+    for file in files {
+        let lower = file.name.to_ascii_lowercase();
+        if !lower.ends_with(".gguf") || lower.contains("mmproj") {
+            continue;
+        }
+        let (key, index) = match split_shard(&file.name) {
+            Some((prefix, index, count)) => (format!("{prefix}-of-{count}"), index),
+            None => (file.name.clone(), 1),
+        };
+        models.entry(key).or_default().push((index, file));
+    }
+
+    models
+        .into_values()
+        .map(|mut shards| {
+            shards.sort_by_key(|(index, _)| *index);
+            Candidate {
+                files: shards.into_iter().map(|(_, file)| file).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Whether `name` carries the quantization tag `quant` as a whole word, so
+/// `Q4_K` does not match `Q4_K_M` and `Q4_XS` does not match `IQ4_XS`.
+fn has_quant(name: &str, quant: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    let quant = quant.to_ascii_uppercase();
+    if quant.is_empty() {
+        return false;
+    }
+    name.match_indices(&quant).any(|(start, _)| {
+        let before = name[..start].chars().next_back();
+        let after = name[start + quant.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric())
+            && !after.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Picks the model to download. On failure returns the models to offer
+/// instead: the ones that matched when several did, otherwise all of them.
+fn select<'a>(
+    candidates: &'a [Candidate],
+    spec: &PullSpec,
+) -> std::result::Result<&'a Candidate, Vec<&'a Candidate>> {
+    let all = || candidates.iter().collect::<Vec<_>>();
+    if let Some(file) = &spec.file {
+        return candidates
+            .iter()
+            .find(|candidate| candidate.contains(file))
+            .ok_or_else(all);
+    }
+
+    if let Some(quant) = &spec.quant {
+        let matches: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|candidate| has_quant(candidate.name(), quant))
+            .collect();
+        return match matches.as_slice() {
+            [single] => Ok(single),
+            [] => Err(all()),
+            _ => Err(matches),
+        };
+    }
+
+    if let [single] = candidates {
+        return Ok(single);
+    }
+    candidates
+        .iter()
+        .find(|candidate| has_quant(candidate.name(), PREFERRED_QUANT))
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|candidate| candidate.name().to_ascii_lowercase().contains("q4"))
+        })
+        .ok_or_else(all)
+}
+
+fn choice_error(message: String, options: &[&Candidate]) -> AppError {
+    let mut message = message;
+    for option in options {
+        message.push_str("\n  ");
+        message.push_str(option.name());
+        if let Some(size) = option.size() {
+            message.push_str(&format!("  {}", HumanBytes(size)));
+        }
+        if option.files.len() > 1 {
+            message.push_str(&format!("  ({} parts)", option.files.len()));
+        }
+    }
+    AppError::Usage(message)
+}
+
+fn file_name_of(remote_name: &str) -> &str {
+    remote_name.rsplit('/').next().unwrap_or(remote_name)
+}
+
+/// Name a downloaded file gets in the models directory. The repository (and
+/// pinned revision) go first so shards of a split model keep the
+/// `-00001-of-0000N.gguf` suffix llama.cpp looks for.
+fn local_file_name(spec: &PullSpec, remote_name: &str) -> String {
+    let base = file_name_of(remote_name);
+    let sanitize = |text: &str| text.replace(['/', '\\'], "__");
+    match &spec.revision {
+        Some(revision) => format!("{}@{}__{base}", sanitize(&spec.repo), sanitize(revision)),
+        None => format!("{}__{base}", sanitize(&spec.repo)),
+    }
+}
+
+/// Lowercase hex SHA-256 of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// The `sha256sum`-style file next to a pulled model that records the
+/// checksum the Hub published for it.
+pub fn checksum_path(model: &Path) -> PathBuf {
+    let mut name = model.file_name().unwrap_or_default().to_os_string();
+    name.push(".sha256");
+    model.with_file_name(name)
+}
+
+pub fn recorded_checksum(model: &Path) -> Option<String> {
+    let contents = fs::read_to_string(checksum_path(model)).ok()?;
+    let sha256 = contents.split_whitespace().next()?;
+    (sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| sha256.to_ascii_lowercase())
+}
+
+pub fn record_checksum(model: &Path, sha256: &str) -> io::Result<()> {
+    let name = model.file_name().unwrap_or_default().to_string_lossy();
+    fs::write(checksum_path(model), format!("{sha256}  {name}\n"))
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_source: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Makes `source` available at `dest` without a second copy where possible:
+/// a hard link, else (if `allow_symlink`) a symlink, else a copy. It is
+/// assembled under a temporary name and renamed into place, so an
+/// interrupted run never leaves a truncated model behind.
+pub fn install(source: &Path, dest: &Path, allow_symlink: bool) -> io::Result<()> {
+    // hf-hub's snapshot entries are themselves symlinks to blobs.
+    let source = fs::canonicalize(source)?;
+    let mut partial = dest.as_os_str().to_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = fs::remove_file(&partial);
+
+    let linked = fs::hard_link(&source, &partial).is_ok()
+        || (allow_symlink && symlink(&source, &partial).is_ok());
+    if !linked {
+        if let Err(err) = fs::copy(&source, &partial) {
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+    }
+    fs::rename(&partial, dest)
+}
+
+/// Checks a downloaded file against the Hub's checksum. A corrupt file is
+/// deleted from the hf-hub cache so the next pull fetches it again.
+fn verify_download(file: &RemoteFile, path: &Path) -> Result<()> {
+    let Some(expected) = &file.sha256 else {
+        return Ok(());
+    };
+    let actual = sha256_file(path)?;
+    if actual == *expected {
+        return Ok(());
+    }
+    if let Ok(blob) = fs::canonicalize(path) {
+        let _ = fs::remove_file(blob);
+    }
+    let _ = fs::remove_file(path);
+    Err(AppError::Download(format!(
+        "Checksum mismatch for '{}': expected {expected}, got {actual}",
+        file.name
+    )))
+}
+
+/// Downloads the model `spec` describes into the models directory and
+/// returns its path; for a split model, the path of the first shard.
+pub fn download(spec: &PullSpec, language: &Language) -> Result<PathBuf> {
+    let repo_id = spec.repo.as_str();
+    let settings = HubSettings::load();
+    if settings.offline {
+        return Err(AppError::Download(
+            language.offline_download_refused().replace("{}", repo_id),
+        ));
+    }
+    let endpoint = settings
+        .endpoint
+        .clone()
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+    configure_proxy(&settings, &endpoint);
+    let api = ApiBuilder::from_env()
+        .with_endpoint(endpoint)
+        .with_retries(DOWNLOAD_RETRIES)
+        .build()
+        .map_err(|e| {
+            AppError::Download(format!("Failed to initialize Hugging Face client: {e}"))
+        })?;
+    let repo_spec = match &spec.revision {
+        Some(revision) => Repo::with_revision(
+            repo_id.to_string(),
+            hf_hub::RepoType::Model,
+            revision.clone(),
+        ),
+        None => Repo::model(repo_id.to_string()),
+    };
+    let cache = Cache::from_env().repo(repo_spec.clone());
+    let repo = api.repo(repo_spec);
+    let listing: serde_json::Value = repo
+        .info_request()
+        .query("blobs", "true")
+        .call()
+        .map_err(|e| e.to_string())
+        .and_then(|response| response.into_json().map_err(|e| e.to_string()))
+        .map_err(|e| {
+            AppError::Download(format!(
+                "Failed to fetch repository '{repo_id}' metadata: {e}"
+            ))
+        })?;
+
+    let candidates = candidates(remote_files(&listing));
+    if candidates.is_empty() {
+        return Err(AppError::Download(format!(
+            "No GGUF files found in repository '{repo_id}'"
+        )));
+    }
+    let chosen = select(&candidates, spec).map_err(|options| {
+        let message = if options.len() < candidates.len() {
+            language.pull_ambiguous().replace("{}", repo_id)
+        } else {
+            language.pull_no_match().replace("{}", repo_id)
+        };
+        choice_error(message, &options)
+    })?;
+
+    println!(
+        "{}",
+        language
+            .downloading_model()
+            .replace("{}", &format!("{repo_id}/{}", chosen.name()))
+    );
+    let dest_dir = models_root_dir()?;
+    fs::create_dir_all(&dest_dir)?;
+
+    let mut first = None;
+    let parts = chosen.files.len();
+    for (index, file) in chosen.files.iter().enumerate() {
+        let dest_path = dest_dir.join(local_file_name(spec, &file.name));
+        let up_to_date = dest_path.is_file()
+            && match &file.sha256 {
+                Some(expected) => sha256_file(&dest_path)? == *expected,
+                None => true,
+            };
+        if !up_to_date {
+            let label = if parts > 1 {
+                format!("[{}/{parts}] {}", index + 1, file_name_of(&file.name))
+            } else {
+                file_name_of(&file.name).to_string()
+            };
+            // hf-hub resumes a file left incomplete by an earlier attempt.
+            let source_path = match cache.get(&file.name) {
+                Some(path) => path,
+                None => repo
+                    .download_with_progress(&file.name, DownloadProgress::new(label, language))
+                    .map_err(|e| {
+                        AppError::Download(format!(
+                            "Failed to download '{}' from '{}': {e}",
+                            file.name, repo_id
+                        ))
+                    })?,
+            };
+            verify_download(file, &source_path)?;
+            install(&source_path, &dest_path, true)?;
+        }
+        if let Some(sha256) = &file.sha256 {
+            record_checksum(&dest_path, sha256)?;
+        }
+        first.get_or_insert(dest_path);
+    }
+
+    let dest_path = first.expect("a candidate has at least one file");
+    let canonical = canonical_model_path(&dest_path);
+    println!(
+        "{}",
+        language
+            .download_completed()
+            .replace("{}", &canonical.to_string_lossy())
+    );
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[(&str, u64)]) -> Vec<RemoteFile> {
+        names
+            .iter()
+            .map(|(name, size)| RemoteFile {
+                name: name.to_string(),
+                size: Some(*size),
+                sha256: None,
+            })
+            .collect()
+    }
+
+    fn spec(file: Option<&str>, quant: Option<&str>) -> PullSpec {
+        PullSpec {
+            repo: "org/model-GGUF".to_string(),
+            file: file.map(str::to_string),
+            quant: quant.map(str::to_string),
+            ..PullSpec::default()
+        }
+    }
+
+    #[test]
+    fn parses_repo_and_revision() {
+        let spec = PullSpec::new("unsloth/gemma-3-270m-it-GGUF@v1.0");
+        assert_eq!(spec.repo, "unsloth/gemma-3-270m-it-GGUF");
+        assert_eq!(spec.revision.as_deref(), Some("v1.0"));
+        assert_eq!(PullSpec::new("org/model").revision, None);
+    }
+
+    #[test]
+    fn matches_quantization_as_a_whole_word() {
+        assert!(has_quant("model-Q4_K_M.gguf", "q4_k_m"));
+        assert!(!has_quant("model-Q4_K_M.gguf", "Q4_K"));
+        assert!(!has_quant("model-IQ4_XS.gguf", "Q4_XS"));
+        assert!(has_quant("model.Q8_0.gguf", "Q8_0"));
+    }
+
+    #[test]
+    fn groups_shards_and_skips_projectors() {
+        let models = candidates(files(&[
+            ("README.md", 10),
+            ("Q8_0/model-Q8_0-00002-of-00002.gguf", 200),
+            ("Q8_0/model-Q8_0-00001-of-00002.gguf", 300),
+            ("model-Q4_K_M.gguf", 100),
+            ("mmproj-F16.gguf", 50),
+        ]));
+        assert_eq!(models.len(), 2);
+        let split = &models[0];
+        assert_eq!(split.name(), "Q8_0/model-Q8_0-00001-of-00002.gguf");
+        assert_eq!(split.files.len(), 2);
+        assert_eq!(split.size(), Some(500));
+    }
+
+    #[test]
+    fn selects_by_file_quant_or_preference() {
+        let models = candidates(files(&[
+            ("model-Q4_0.gguf", 1),
+            ("model-Q4_K_M.gguf", 2),
+            ("model-Q8_0-00001-of-00002.gguf", 3),
+            ("model-Q8_0-00002-of-00002.gguf", 3),
+        ]));
+
+        let pick = |file, quant| select(&models, &spec(file, quant)).map(Candidate::name);
+        assert_eq!(pick(None, None), Ok("model-Q4_K_M.gguf"));
+        assert_eq!(pick(Some("model-Q4_0"), None), Ok("model-Q4_0.gguf"));
+        assert_eq!(
+            pick(Some("model-Q8_0-00002-of-00002.gguf"), None),
+            Ok("model-Q8_0-00001-of-00002.gguf")
+        );
+        assert_eq!(
+            pick(None, Some("q8_0")),
+            Ok("model-Q8_0-00001-of-00002.gguf")
+        );
+
+        let offered = |result: std::result::Result<&Candidate, Vec<&Candidate>>| {
+            result.map(|_| ()).unwrap_err().len()
+        };
+        assert_eq!(offered(select(&models, &spec(None, Some("Q6_K")))), 3);
+        assert_eq!(
+            offered(select(&models, &spec(Some("missing.gguf"), None))),
+            3
+        );
+    }
+
+    #[test]
+    fn keeps_shard_suffix_in_local_names() {
+        let mut spec = PullSpec::new("org/model-GGUF@main");
+        assert_eq!(
+            local_file_name(&spec, "Q8_0/model-00001-of-00002.gguf"),
+            "org__model-GGUF@main__model-00001-of-00002.gguf"
+        );
+        spec.revision = None;
+        assert_eq!(
+            local_file_name(&spec, "model.gguf"),
+            "org__model-GGUF__model.gguf"
+        );
+    }
+
+    #[test]
+    fn installs_atomically_and_records_checksums() {
+        let dir = std::env::temp_dir().join(format!("git-ca-hub-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("blob");
+        fs::write(&source, b"abc").unwrap();
+        let dest = dir.join("org__model__model.gguf");
+
+        install(&source, &dest, false).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"abc");
+        assert!(!dir.join("org__model__model.gguf.partial").exists());
+
+        let sha256 = sha256_file(&dest).unwrap();
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        record_checksum(&dest, &sha256).unwrap();
+        assert_eq!(recorded_checksum(&dest), Some(sha256.clone()));
+
+        let file = RemoteFile {
+            name: "model.gguf".to_string(),
+            size: Some(3),
+            sha256: Some("0".repeat(64)),
+        };
+        assert!(verify_download(&file, &source).is_err());
+        assert!(!source.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn offline_and_endpoint_come_from_env_before_config() {
+        let settings = |env: &[(&str, &str)], config: &[(&str, &str)]| {
+            let lookup = |pairs: &[(&str, &str)], key: &str| {
+                pairs
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.to_string())
+            };
+            HubSettings::resolve(|name| lookup(env, name), |key| lookup(config, key))
+        };
+
+        assert_eq!(settings(&[], &[]), HubSettings::default());
+        assert!(settings(&[("HF_HUB_OFFLINE", "1")], &[]).offline);
+        assert!(settings(&[], &[(CONFIG_OFFLINE_KEY, "true")]).offline);
+        assert!(!settings(&[("GIT_CA_OFFLINE", "0")], &[(CONFIG_OFFLINE_KEY, "true")]).offline);
+
+        let mirrored = settings(
+            &[],
+            &[(CONFIG_ENDPOINT_KEY, "https://hf-mirror.example.com/")],
+        );
+        assert_eq!(
+            mirrored.endpoint.as_deref(),
+            Some("https://hf-mirror.example.com")
+        );
+        let from_env = settings(
+            &[("HF_ENDPOINT", "http://localhost:8080")],
+            &[(CONFIG_ENDPOINT_KEY, "https://hf-mirror.example.com")],
+        );
+        assert_eq!(from_env.endpoint.as_deref(), Some("http://localhost:8080"));
+
+        let proxy = [(CONFIG_PROXY_KEY, "http://proxy.internal:3128")];
+        assert_eq!(
+            settings(&[], &proxy).proxy.as_deref(),
+            Some("http://proxy.internal:3128")
+        );
+        assert_eq!(
+            settings(&[("HTTPS_PROXY", "http://other:3128")], &proxy).proxy,
+            None
+        );
+    }
+
+    #[test]
+    fn honours_no_proxy_for_the_endpoint_host() {
+        assert_eq!(url_host("https://huggingface.co"), "huggingface.co");
+        assert_eq!(
+            url_host("http://user:pw@mirror.internal:8080/hf/"),
+            "mirror.internal"
+        );
+
+        assert!(bypasses_proxy("localhost, .internal", "mirror.internal"));
+        assert!(bypasses_proxy("huggingface.co", "huggingface.co"));
+        assert!(bypasses_proxy("*", "huggingface.co"));
+        assert!(bypasses_proxy("*.example.com:443", "hf.example.com"));
+        assert!(!bypasses_proxy("face.co", "huggingface.co"));
+        assert!(!bypasses_proxy("", "huggingface.co"));
+    }
+}
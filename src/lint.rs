@@ -0,0 +1,903 @@
+use crate::{find_git_repository, get_message_language, AppError, Language, Result};
+use git2::{Config, Repository, Sort};
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Rules are set one per key, in commitlint's `<level> [always|never]
+/// [value]` form, e.g.
+/// `git config commit-analyzer.lint.header-max-length "2 always 72"` or
+/// `git config commit-analyzer.lint.scope-enum "2 always cli,api,docs"`.
+const CONFIG_RULE_PREFIX: &str = "commit-analyzer.lint.";
+const CONFIG_DEFAULT_IGNORES_KEY: &str = "commit-analyzer.lint.defaultIgnores";
+
+/// commitlint's `config-conventional` settings, with the types `git ca`
+/// generates. `subject-case` is switched off for languages whose subjects
+/// do not start in lower case.
+const DEFAULT_RULES: &[(Rule, &str)] = &[
+    (Rule::TypeEmpty, "2 never"),
+    (
+        Rule::TypeEnum,
+        "2 always feat,fix,docs,style,refactor,test,chore",
+    ),
+    (Rule::TypeCase, "2 always lower-case"),
+    (Rule::ScopeEmpty, "0"),
+    (Rule::ScopeEnum, "0"),
+    (Rule::ScopeCase, "0"),
+    (Rule::SubjectEmpty, "2 never"),
+    (
+        Rule::SubjectCase,
+        "2 never sentence-case,start-case,pascal-case,upper-case",
+    ),
+    (Rule::SubjectFullStop, "2 never ."),
+    (Rule::HeaderMaxLength, "2 always 100"),
+    (Rule::BodyLeadingBlank, "1 always"),
+    (Rule::BodyMaxLineLength, "2 always 100"),
+    (Rule::FooterLeadingBlank, "1 always"),
+    (Rule::FooterMaxLineLength, "2 always 100"),
+];
+
+const CASES: &[&str] = &[
+    "lower-case",
+    "upper-case",
+    "camel-case",
+    "kebab-case",
+    "pascal-case",
+    "sentence-case",
+    "snake-case",
+    "start-case",
+];
+
+/// Headers commitlint skips by default: merges, reverts and autosquash
+/// commits.
+const IGNORED_PREFIXES: &[&str] = &[
+    "Merge ",
+    "Merged ",
+    "Revert ",
+    "revert ",
+    "fixup! ",
+    "squash! ",
+    "amend! ",
+    "Automatic merge",
+    "Auto-merged ",
+];
+
+const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    TypeEmpty,
+    TypeEnum,
+    TypeCase,
+    ScopeEmpty,
+    ScopeEnum,
+    ScopeCase,
+    SubjectEmpty,
+    SubjectCase,
+    SubjectFullStop,
+    HeaderMaxLength,
+    BodyLeadingBlank,
+    BodyMaxLineLength,
+    FooterLeadingBlank,
+    FooterMaxLineLength,
+}
+
+/// What a rule's value holds.
+enum ValueKind {
+    None,
+    List,
+    Cases,
+    Length,
+    Text,
+}
+
+impl Rule {
+    fn name(self) -> &'static str {
+        match self {
+            Rule::TypeEmpty => "type-empty",
+            Rule::TypeEnum => "type-enum",
+            Rule::TypeCase => "type-case",
+            Rule::ScopeEmpty => "scope-empty",
+            Rule::ScopeEnum => "scope-enum",
+            Rule::ScopeCase => "scope-case",
+            Rule::SubjectEmpty => "subject-empty",
+            Rule::SubjectCase => "subject-case",
+            Rule::SubjectFullStop => "subject-full-stop",
+            Rule::HeaderMaxLength => "header-max-length",
+            Rule::BodyLeadingBlank => "body-leading-blank",
+            Rule::BodyMaxLineLength => "body-max-line-length",
+            Rule::FooterLeadingBlank => "footer-leading-blank",
+            Rule::FooterMaxLineLength => "footer-max-line-length",
+        }
+    }
+
+    fn value_kind(self) -> ValueKind {
+        match self {
+            Rule::TypeEmpty
+            | Rule::ScopeEmpty
+            | Rule::SubjectEmpty
+            | Rule::BodyLeadingBlank
+            | Rule::FooterLeadingBlank => ValueKind::None,
+            Rule::TypeEnum | Rule::ScopeEnum => ValueKind::List,
+            Rule::TypeCase | Rule::ScopeCase | Rule::SubjectCase => ValueKind::Cases,
+            Rule::HeaderMaxLength | Rule::BodyMaxLineLength | Rule::FooterMaxLineLength => {
+                ValueKind::Length
+            }
+            Rule::SubjectFullStop => ValueKind::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Disabled,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Setting {
+    level: Level,
+    /// `always` (true) or `never` (false).
+    always: bool,
+    values: Vec<String>,
+}
+
+/// The rule set `git ca lint` checks, in commitlint's order.
+pub struct LintRules {
+    settings: Vec<(Rule, Setting)>,
+    default_ignores: bool,
+}
+
+impl LintRules {
+    /// The defaults for commit messages in `language`.
+    fn defaults(language: &Language) -> Self {
+        let settings = DEFAULT_RULES
+            .iter()
+            .map(|(rule, spec)| {
+                let mut setting =
+                    parse_setting(*rule, spec).expect("built-in lint rules are valid");
+                if *rule == Rule::SubjectCase && !language.lowercase_subject() {
+                    setting.level = Level::Disabled;
+                }
+                (*rule, setting)
+            })
+            .collect();
+        Self {
+            settings,
+            default_ignores: true,
+        }
+    }
+
+    /// The defaults, with every `commit-analyzer.lint.<rule>` set in git
+    /// config replacing the built-in setting for that rule.
+    pub fn from_config(config: &Config, language: &Language) -> Self {
+        let mut rules = Self::defaults(language);
+        for (rule, setting) in &mut rules.settings {
+            let key = format!("{CONFIG_RULE_PREFIX}{}", rule.name());
+            let Ok(spec) = config.get_string(&key) else {
+                continue;
+            };
+            match parse_setting(*rule, &spec) {
+                Ok(parsed) => *setting = parsed,
+                Err(err) => eprintln!("[git-ca] warning: ignoring {key} '{spec}': {err}"),
+            }
+        }
+        rules.default_ignores = config.get_bool(CONFIG_DEFAULT_IGNORES_KEY).unwrap_or(true);
+        rules
+    }
+}
+
+fn parse_setting(rule: Rule, spec: &str) -> std::result::Result<Setting, String> {
+    let mut parts = spec.split_whitespace();
+    let level = match parts.next() {
+        Some("0") => Level::Disabled,
+        Some("1") => Level::Warning,
+        Some("2") => Level::Error,
+        Some(other) => return Err(format!("level must be 0, 1 or 2, not '{other}'")),
+        None => return Err("missing level".to_string()),
+    };
+    let always = match parts.next() {
+        None | Some("always") => true,
+        Some("never") => false,
+        Some(other) => return Err(format!("expected 'always' or 'never', not '{other}'")),
+    };
+    let values: Vec<String> = parts
+        .next()
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if parts.next().is_some() {
+        return Err("separate list items with commas, not spaces".to_string());
+    }
+
+    if level != Level::Disabled {
+        match rule.value_kind() {
+            ValueKind::None | ValueKind::List => {}
+            ValueKind::Cases => {
+                if values.is_empty() {
+                    return Err("expected a case such as lower-case".to_string());
+                }
+                if let Some(case) = values.iter().find(|case| !CASES.contains(&case.as_str())) {
+                    return Err(format!("unknown case '{case}'"));
+                }
+            }
+            ValueKind::Length => {
+                if values.len() != 1 || values[0].parse::<usize>().is_err() {
+                    return Err("expected a length in characters".to_string());
+                }
+            }
+            ValueKind::Text => {
+                if values.len() != 1 {
+                    return Err("expected one character".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Setting {
+        level,
+        always,
+        values,
+    })
+}
+
+/// A commit message split the way commitlint's conventional parser does.
+#[derive(Debug, Default, PartialEq)]
+struct ParsedMessage<'a> {
+    header: &'a str,
+    commit_type: Option<&'a str>,
+    scope: Option<&'a str>,
+    subject: Option<&'a str>,
+    /// Lines between the header and the footer, blank ones included.
+    body: Vec<&'a str>,
+    /// Trailers such as `Refs: #12` or `BREAKING CHANGE: ...` at the end.
+    footer: Vec<&'a str>,
+}
+
+impl ParsedMessage<'_> {
+    fn has_body(&self) -> bool {
+        self.body.iter().any(|line| !line.trim().is_empty())
+    }
+}
+
+fn parse_message(message: &str) -> ParsedMessage<'_> {
+    let lines: Vec<&str> = message.trim().lines().map(str::trim_end).collect();
+    let Some((&header, rest)) = lines.split_first() else {
+        return ParsedMessage::default();
+    };
+
+    let footer_start = (0..rest.len())
+        .find(|&start| {
+            is_trailer(rest[start])
+                && rest[start..]
+                    .iter()
+                    .all(|line| is_trailer(line) || line.starts_with(char::is_whitespace))
+        })
+        .unwrap_or(rest.len());
+
+    let (commit_type, scope, subject) = match parse_header(header) {
+        Some((commit_type, scope, subject)) => (Some(commit_type), scope, Some(subject)),
+        None => (None, None, None),
+    };
+
+    ParsedMessage {
+        header,
+        commit_type,
+        scope,
+        subject,
+        body: rest[..footer_start].to_vec(),
+        footer: rest[footer_start..].to_vec(),
+    }
+}
+
+/// Splits `<type>[(<scope>)][!]: <subject>` into its parts, accepting any
+/// word as the type so `type-enum` can report it.
+fn parse_header(header: &str) -> Option<(&str, Option<&str>, &str)> {
+    let type_end = header
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(header.len());
+    let commit_type = &header[..type_end];
+    let mut rest = &header[type_end..];
+
+    let mut scope = None;
+    if let Some(after_paren) = rest.strip_prefix('(') {
+        let end = after_paren.find(')')?;
+        scope = Some(&after_paren[..end]).filter(|scope| !scope.is_empty());
+        rest = &after_paren[end + 1..];
+    }
+    rest = rest.strip_prefix('!').unwrap_or(rest);
+    let subject = rest.strip_prefix(": ")?.trim();
+
+    if commit_type.is_empty() || subject.is_empty() {
+        return None;
+    }
+    Some((commit_type, scope, subject))
+}
+
+/// `Token: value`, `Token #value` or `BREAKING CHANGE: value`.
+fn is_trailer(line: &str) -> bool {
+    if line.starts_with("BREAKING CHANGE: ") {
+        return true;
+    }
+    let Some(token_end) = line.find([':', ' ']) else {
+        return false;
+    };
+    let token = &line[..token_end];
+    let separated = line[token_end..].starts_with(": ") || line[token_end..].starts_with(" #");
+    separated
+        && token.starts_with(|c: char| c.is_ascii_alphabetic())
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_ignored(message: &str) -> bool {
+    let header = message.trim_start().lines().next().unwrap_or("");
+    IGNORED_PREFIXES
+        .iter()
+        .any(|prefix| header.starts_with(prefix))
+}
+
+/// Whether `text` is already written in `case`. Text that starts with a
+/// digit or has no cased letters passes every check, as in commitlint.
+fn matches_case(text: &str, case: &str) -> bool {
+    let cased = |c: char| c.is_lowercase() || c.is_uppercase();
+    if text.starts_with(|c: char| c.is_ascii_digit()) || !text.chars().any(cased) {
+        return true;
+    }
+    let starts_upper = |word: &str| word.chars().next().is_some_and(|c| !c.is_lowercase());
+    let is_lower = |word: &str| word == word.to_lowercase();
+    match case {
+        "lower-case" => is_lower(text),
+        "upper-case" => text == text.to_uppercase(),
+        "camel-case" => {
+            text.chars().all(char::is_alphanumeric) && !text.starts_with(char::is_uppercase)
+        }
+        "pascal-case" => text.chars().all(char::is_alphanumeric) && starts_upper(text),
+        "kebab-case" => is_lower(text) && text.chars().all(|c| c.is_alphanumeric() || c == '-'),
+        "snake-case" => is_lower(text) && text.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        "sentence-case" => {
+            let first = text.split_whitespace().next().unwrap_or("");
+            let mut chars = first.chars();
+            chars.next().is_some_and(|c| !c.is_lowercase()) && is_lower(chars.as_str())
+        }
+        "start-case" => text.split_whitespace().all(|word| {
+            let mut chars = word.chars();
+            chars.next().is_some_and(|c| !c.is_lowercase()) && is_lower(chars.as_str())
+        }),
+        _ => false,
+    }
+}
+
+fn scope_parts(scope: &str) -> impl Iterator<Item = &str> {
+    scope
+        .split(['/', '\\', ','])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+}
+
+/// Lines longer than `max` characters, not counting lines that are a bare
+/// URL.
+fn has_long_line(lines: &[&str], max: usize) -> bool {
+    lines.iter().any(|line| {
+        let bare_url = line.contains("://") && !line.trim().contains(char::is_whitespace);
+        !bare_url && line.chars().count() > max
+    })
+}
+
+/// One failed rule.
+#[derive(Debug, PartialEq)]
+struct Problem {
+    rule: &'static str,
+    level: Level,
+    message: String,
+}
+
+/// Checks `message` against every enabled rule.
+fn lint_message(message: &str, rules: &LintRules) -> Vec<Problem> {
+    let parsed = parse_message(message);
+    rules
+        .settings
+        .iter()
+        .filter(|(_, setting)| setting.level != Level::Disabled)
+        .filter_map(|(rule, setting)| {
+            check_rule(*rule, setting, &parsed).map(|message| Problem {
+                rule: rule.name(),
+                level: setting.level,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// The complaint when `parsed` breaks `rule`, worded as commitlint does.
+fn check_rule(rule: Rule, setting: &Setting, parsed: &ParsedMessage) -> Option<String> {
+    let always = setting.always;
+    let values = &setting.values;
+    let not = if always { "" } else { "not " };
+    let list = values.join(", ");
+    let length = || values.first().and_then(|value| value.parse::<usize>().ok());
+
+    let empty = |field: &str, value: Option<&str>| {
+        let is_empty = value.is_none();
+        (is_empty != always).then(|| {
+            if always {
+                format!("{field} must be empty")
+            } else {
+                format!("{field} may not be empty")
+            }
+        })
+    };
+    let case = |field: &str, value: &str| {
+        let matches = values.iter().any(|case| matches_case(value, case));
+        (matches != always).then(|| format!("{field} must {not}be {list}"))
+    };
+
+    match rule {
+        Rule::TypeEmpty => empty("type", parsed.commit_type),
+        Rule::ScopeEmpty => empty("scope", parsed.scope),
+        Rule::SubjectEmpty => empty("subject", parsed.subject),
+        Rule::TypeEnum => {
+            let commit_type = parsed.commit_type?;
+            (values.iter().any(|value| value == commit_type) != always)
+                .then(|| format!("type must {not}be one of [{list}]"))
+        }
+        Rule::ScopeEnum => {
+            let mut parts = scope_parts(parsed.scope?);
+            let listed = |part: &str| values.iter().any(|value| value == part);
+            let valid = if always {
+                parts.all(listed)
+            } else {
+                !parts.any(listed)
+            };
+            (!valid).then(|| format!("scope must {not}be one of [{list}]"))
+        }
+        Rule::TypeCase => case("type", parsed.commit_type?),
+        Rule::ScopeCase => {
+            let mut parts = scope_parts(parsed.scope?);
+            let valid = parts.all(|part| values.iter().any(|case| matches_case(part, case)));
+            (valid != always).then(|| format!("scope must {not}be {list}"))
+        }
+        Rule::SubjectCase => case("subject", parsed.subject?),
+        Rule::SubjectFullStop => {
+            let stop = values.first()?;
+            let ends = parsed.subject?.ends_with(stop.as_str());
+            (ends != always).then(|| {
+                if always {
+                    "subject must end with full stop".to_string()
+                } else {
+                    "subject may not end with full stop".to_string()
+                }
+            })
+        }
+        Rule::HeaderMaxLength => {
+            let max = length()?;
+            let current = parsed.header.chars().count();
+            (current > max).then(|| {
+                format!(
+                    "header must not be longer than {max} characters, current length is {current}"
+                )
+            })
+        }
+        Rule::BodyMaxLineLength => {
+            let max = length()?;
+            has_long_line(&parsed.body, max)
+                .then(|| format!("body's lines must not be longer than {max} characters"))
+        }
+        Rule::FooterMaxLineLength => {
+            let max = length()?;
+            has_long_line(&parsed.footer, max)
+                .then(|| format!("footer's lines must not be longer than {max} characters"))
+        }
+        Rule::BodyLeadingBlank => {
+            if !parsed.has_body() {
+                return None;
+            }
+            let blank = parsed.body[0].trim().is_empty();
+            (blank != always).then(|| format!("body must {not}have leading blank line"))
+        }
+        Rule::FooterLeadingBlank => {
+            if parsed.footer.is_empty() {
+                return None;
+            }
+            let blank = parsed
+                .body
+                .last()
+                .is_some_and(|line| line.trim().is_empty());
+            (blank != always).then(|| format!("footer must {not}have leading blank line"))
+        }
+    }
+}
+
+/// The outcome for one message.
+struct LintResult {
+    /// Commit id, file path, or `-` for standard input.
+    source: String,
+    input: String,
+    ignored: bool,
+    problems: Vec<Problem>,
+}
+
+impl LintResult {
+    fn count(&self, level: Level) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.level == level)
+            .count()
+    }
+}
+
+/// Drops the comment lines and the `git commit -v` diff that git leaves in
+/// a commit message file.
+fn strip_comments(text: &str, comment_char: char) -> String {
+    let mut kept = Vec::new();
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix(comment_char) {
+            if comment.trim() == SCISSORS {
+                break;
+            }
+            continue;
+        }
+        kept.push(line);
+    }
+    kept.join("\n")
+}
+
+/// `core.commentChar`; with `auto` git picks one per message, but starts
+/// with `#`.
+fn comment_char(config: Option<&Config>) -> char {
+    config
+        .and_then(|config| config.get_string("core.commentChar").ok())
+        .filter(|value| value != "auto")
+        .and_then(|value| value.chars().next())
+        .unwrap_or('#')
+}
+
+/// `git ca lint [<file> | - | <commit> | <range>]`: checks commit messages
+/// against commitlint's conventional rules. A file or `-` is read as a
+/// message being written, as in a `commit-msg` hook; otherwise the
+/// argument names commits, `HEAD` by default, or a range such as
+/// `origin/main..HEAD`.
+pub fn run_lint(args: &[String], language: &Language, json: bool) -> Result<()> {
+    if args.len() > 1 || args.first().is_some_and(|arg| arg.starts_with("--")) {
+        return Err(AppError::Usage(language.lint_usage().to_string()));
+    }
+
+    let repo = env::current_dir()
+        .ok()
+        .and_then(|dir| find_git_repository(&dir))
+        .and_then(|root| Repository::open(root).ok());
+    let config = repo
+        .as_ref()
+        .and_then(|repo| repo.config().ok())
+        .or_else(|| Config::open_default().ok());
+    let rules = match &config {
+        Some(config) => LintRules::from_config(config, &get_message_language()),
+        None => LintRules::defaults(&get_message_language()),
+    };
+
+    let target = args.first().map_or("HEAD", String::as_str);
+    let messages = if target == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        vec![(
+            "-".to_string(),
+            strip_comments(&text, comment_char(config.as_ref())),
+        )]
+    } else if Path::new(target).is_file() {
+        let text = fs::read_to_string(target)?;
+        vec![(
+            target.to_string(),
+            strip_comments(&text, comment_char(config.as_ref())),
+        )]
+    } else {
+        let repo = repo.ok_or_else(|| {
+            AppError::NotInRepository(language.not_in_git_repository().to_string())
+        })?;
+        commit_messages(&repo, target)?
+    };
+
+    let results: Vec<LintResult> = messages
+        .into_iter()
+        .map(|(source, input)| {
+            let ignored = rules.default_ignores && is_ignored(&input);
+            let problems = if ignored {
+                Vec::new()
+            } else {
+                lint_message(&input, &rules)
+            };
+            LintResult {
+                source,
+                input,
+                ignored,
+                problems,
+            }
+        })
+        .collect();
+
+    let errors: usize = results
+        .iter()
+        .map(|result| result.count(Level::Error))
+        .sum();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report_json(&results)).unwrap_or_default()
+        );
+    } else {
+        print_report(&results, language);
+    }
+
+    if errors > 0 {
+        return Err(AppError::LintFailed);
+    }
+    Ok(())
+}
+
+/// `(short id, message)` for one commit, or for every commit in a `..`
+/// range, oldest first.
+fn commit_messages(repo: &Repository, spec: &str) -> Result<Vec<(String, String)>> {
+    let describe = |commit: &git2::Commit| {
+        let id = commit.id().to_string();
+        (
+            id[..id.len().min(7)].to_string(),
+            commit.message().unwrap_or_default().to_string(),
+        )
+    };
+
+    if !spec.contains("..") {
+        let commit = repo.revparse_single(spec)?.peel_to_commit()?;
+        return Ok(vec![describe(&commit)]);
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push_range(spec)?;
+    let mut messages = Vec::new();
+    for id in walk {
+        messages.push(describe(&repo.find_commit(id?)?));
+    }
+    Ok(messages)
+}
+
+fn print_report(results: &[LintResult], language: &Language) {
+    let error_label = language.lint_error_label();
+    let warning_label = language.lint_warning_label();
+    let width = error_label
+        .chars()
+        .count()
+        .max(warning_label.chars().count());
+    let mut errors = 0;
+    let mut warnings = 0;
+    for result in results.iter().filter(|result| !result.problems.is_empty()) {
+        let header = result.input.trim().lines().next().unwrap_or("");
+        println!("{}: {header}", result.source);
+        for problem in &result.problems {
+            let label = match problem.level {
+                Level::Error => error_label,
+                _ => warning_label,
+            };
+            println!("  {label:<width$} {} [{}]", problem.message, problem.rule);
+        }
+        errors += result.count(Level::Error);
+        warnings += result.count(Level::Warning);
+    }
+    if errors + warnings > 0 {
+        println!(
+            "\n{}",
+            language
+                .lint_summary()
+                .replace("{errors}", &errors.to_string())
+                .replace("{warnings}", &warnings.to_string())
+                .replace("{messages}", &results.len().to_string())
+        );
+    }
+}
+
+fn report_json(results: &[LintResult]) -> Value {
+    let problems = |result: &LintResult, level: Level| -> Vec<Value> {
+        result
+            .problems
+            .iter()
+            .filter(|problem| problem.level == level)
+            .map(|problem| json!({ "rule": problem.rule, "message": problem.message }))
+            .collect()
+    };
+    let error_count: usize = results
+        .iter()
+        .map(|result| result.count(Level::Error))
+        .sum();
+    let warning_count: usize = results
+        .iter()
+        .map(|result| result.count(Level::Warning))
+        .sum();
+    json!({
+        "valid": error_count == 0,
+        "error_count": error_count,
+        "warning_count": warning_count,
+        "results": results
+            .iter()
+            .map(|result| json!({
+                "source": result.source,
+                "input": result.input,
+                "valid": result.count(Level::Error) == 0,
+                "ignored": result.ignored,
+                "errors": problems(result, Level::Error),
+                "warnings": problems(result, Level::Warning),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english_rules() -> LintRules {
+        LintRules::defaults(&Language::english())
+    }
+
+    fn rules_failed(message: &str, rules: &LintRules) -> Vec<&'static str> {
+        lint_message(message, rules)
+            .into_iter()
+            .map(|problem| problem.rule)
+            .collect()
+    }
+
+    #[test]
+    fn default_types_match_generated_types() {
+        let rules = english_rules();
+        let (_, type_enum) = rules
+            .settings
+            .iter()
+            .find(|(rule, _)| *rule == Rule::TypeEnum)
+            .expect("type-enum");
+        assert_eq!(type_enum.values, crate::COMMIT_TYPES);
+    }
+
+    #[test]
+    fn parses_header_body_and_footer() {
+        let parsed = parse_message(
+            "feat(cli)!: add lint command\n\nChecks messages.\n\nRefs: #12\nBREAKING CHANGE: new exit code\n",
+        );
+        assert_eq!(parsed.commit_type, Some("feat"));
+        assert_eq!(parsed.scope, Some("cli"));
+        assert_eq!(parsed.subject, Some("add lint command"));
+        assert_eq!(parsed.body, vec!["", "Checks messages.", ""]);
+        assert_eq!(
+            parsed.footer,
+            vec!["Refs: #12", "BREAKING CHANGE: new exit code"]
+        );
+
+        let parsed = parse_message("update things");
+        assert_eq!(parsed.commit_type, None);
+        assert_eq!(parsed.subject, None);
+        assert!(parsed.footer.is_empty());
+    }
+
+    #[test]
+    fn accepts_conventional_messages() {
+        let rules = english_rules();
+        assert!(rules_failed("fix(cli): handle empty diffs", &rules).is_empty());
+        assert!(rules_failed(
+            "docs: describe lint\n\nLong explanation.\n\nSigned-off-by: A <a@example.com>",
+            &rules
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn reports_header_problems() {
+        let rules = english_rules();
+        assert_eq!(
+            rules_failed("update things", &rules),
+            vec!["type-empty", "subject-empty"]
+        );
+        assert_eq!(
+            rules_failed("Feature(cli): Add lint.", &rules),
+            vec![
+                "type-enum",
+                "type-case",
+                "subject-case",
+                "subject-full-stop"
+            ]
+        );
+        let long = format!("feat: {}", "x".repeat(100));
+        assert_eq!(rules_failed(&long, &rules), vec!["header-max-length"]);
+    }
+
+    #[test]
+    fn reports_missing_blank_lines_as_warnings() {
+        let problems = lint_message(
+            "fix: handle empty diffs\nbody text\nRefs: #3",
+            &english_rules(),
+        );
+        let failed: Vec<_> = problems
+            .iter()
+            .map(|problem| (problem.rule, problem.level))
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                ("body-leading-blank", Level::Warning),
+                ("footer-leading-blank", Level::Warning)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_configured_settings() {
+        assert_eq!(
+            parse_setting(Rule::ScopeEnum, "2 always cli, api"),
+            Err("separate list items with commas, not spaces".to_string())
+        );
+        let setting = parse_setting(Rule::ScopeEnum, "1 never cli,api").expect("valid");
+        assert_eq!(setting.level, Level::Warning);
+        assert!(!setting.always);
+        assert_eq!(setting.values, vec!["cli", "api"]);
+        assert!(parse_setting(Rule::HeaderMaxLength, "2 always long").is_err());
+        assert!(parse_setting(Rule::SubjectCase, "2 always shouting").is_err());
+        assert!(parse_setting(Rule::SubjectCase, "0").is_ok());
+
+        let mut rules = english_rules();
+        for (rule, setting) in &mut rules.settings {
+            if *rule == Rule::ScopeEnum {
+                *setting = parse_setting(*rule, "2 always cli,api").expect("valid");
+            }
+        }
+        assert!(rules_failed("feat(cli,api): add flags", &rules).is_empty());
+        assert_eq!(
+            rules_failed("feat(cli/docs): add flags", &rules),
+            vec!["scope-enum"]
+        );
+    }
+
+    #[test]
+    fn checks_cases() {
+        assert!(matches_case("add lint", "lower-case"));
+        assert!(matches_case("Add lint", "sentence-case"));
+        assert!(matches_case("Add Lint", "start-case"));
+        assert!(matches_case("AddLint", "pascal-case"));
+        assert!(matches_case("addLint", "camel-case"));
+        assert!(matches_case("add-lint", "kebab-case"));
+        assert!(matches_case("add_lint", "snake-case"));
+        assert!(matches_case("ADD LINT", "upper-case"));
+        assert!(!matches_case("add lint", "sentence-case"));
+        assert!(!matches_case("Add lint", "lower-case"));
+        // Digits first, or no cased letters, pass any case.
+        assert!(matches_case("2fa support", "upper-case"));
+        assert!(matches_case("更新依赖", "sentence-case"));
+    }
+
+    #[test]
+    fn follows_language_subject_case() {
+        let german = Language::from_str("de").expect("de");
+        let rules = LintRules::defaults(&german);
+        assert!(rules_failed("docs: Installationsanleitung aktualisieren", &rules).is_empty());
+        assert_eq!(
+            rules_failed("docs: Update install guide", &english_rules()),
+            vec!["subject-case"]
+        );
+    }
+
+    #[test]
+    fn strips_comments_and_verbose_diff() {
+        let text = "feat: add lint\n# Please enter the commit message\n\nBody\n# ------------------------ >8 ------------------------\ndiff --git a/x b/x\n";
+        assert_eq!(strip_comments(text, '#'), "feat: add lint\n\nBody");
+    }
+
+    #[test]
+    fn ignores_merges_and_autosquash() {
+        assert!(is_ignored("Merge branch 'main' into topic"));
+        assert!(is_ignored("fixup! feat: add lint"));
+        assert!(is_ignored("Revert \"feat: add lint\""));
+        assert!(!is_ignored("revert: feat: add lint"));
+    }
+}
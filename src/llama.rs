@@ -1,18 +1,33 @@
+use crate::cancel::{AbortReason, AbortSignal};
 use llama_cpp_sys_2::{
-    ggml_log_level, llama_backend_free, llama_backend_init, llama_batch_free, llama_batch_init,
-    llama_context_default_params, llama_decode, llama_free, llama_free_model, llama_get_logits,
-    llama_get_memory, llama_load_model_from_file, llama_log_set, llama_memory_clear, llama_model,
-    llama_model_default_params, llama_model_get_vocab, llama_n_vocab, llama_new_context_with_model,
-    llama_set_n_threads, llama_token, llama_token_eos, llama_token_to_piece, llama_tokenize,
-    llama_vocab, GGML_LOG_LEVEL_ERROR,
+    ggml_log_level, ggml_type, llama_backend_free, llama_backend_init, llama_batch_free,
+    llama_batch_init, llama_context_default_params, llama_decode, llama_flash_attn_type,
+    llama_free, llama_free_model, llama_get_logits_ith, llama_get_memory,
+    llama_load_model_from_file, llama_log_set, llama_memory_clear, llama_memory_seq_rm,
+    llama_model, llama_model_default_params, llama_model_get_vocab, llama_model_n_ctx_train,
+    llama_model_size, llama_n_vocab, llama_new_context_with_model, llama_perf_context,
+    llama_perf_context_reset, llama_sampler, llama_sampler_accept, llama_sampler_apply,
+    llama_sampler_chain_add, llama_sampler_chain_default_params, llama_sampler_chain_init,
+    llama_sampler_free, llama_sampler_init_dist, llama_sampler_init_dry,
+    llama_sampler_init_grammar, llama_sampler_init_min_p, llama_sampler_init_mirostat,
+    llama_sampler_init_mirostat_v2, llama_sampler_init_penalties, llama_sampler_init_temp,
+    llama_sampler_init_top_k, llama_sampler_init_top_p, llama_sampler_init_typical,
+    llama_set_abort_callback, llama_set_n_threads, llama_state_load_file, llama_state_save_file,
+    llama_token, llama_token_data, llama_token_data_array, llama_token_eos, llama_token_to_piece,
+    llama_tokenize, llama_vocab, GGML_LOG_LEVEL_ERROR, GGML_TYPE_BF16, GGML_TYPE_F16,
+    GGML_TYPE_F32, GGML_TYPE_Q4_0, GGML_TYPE_Q4_1, GGML_TYPE_Q5_0, GGML_TYPE_Q5_1, GGML_TYPE_Q8_0,
+    LLAMA_DEFAULT_SEED, LLAMA_FLASH_ATTN_TYPE_AUTO, LLAMA_FLASH_ATTN_TYPE_DISABLED,
+    LLAMA_FLASH_ATTN_TYPE_ENABLED,
 };
-use rand::prelude::*;
-use std::cmp::Ordering;
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::os::raw::{c_char, c_void};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::sync::Once;
+use std::time::UNIX_EPOCH;
 
 const MAX_SEQ_ID: i32 = 1;
 const PROMPT_CHUNK_SIZE: usize = 256;
@@ -20,6 +35,16 @@ const SAMPLING_TEMPERATURE: f32 = 0.8;
 const SAMPLING_TOP_K: usize = 40;
 const SAMPLING_TOP_P: f32 = 0.9;
 const SAMPLING_MIN_P: f32 = 0.0;
+const SAMPLING_REPEAT_PENALTY: f32 = 1.1;
+const SAMPLING_PENALTY_LAST_N: usize = 64;
+const SAMPLING_TYPICAL_P: f32 = 1.0;
+const SAMPLING_MIROSTAT_TAU: f32 = 5.0;
+const SAMPLING_MIROSTAT_ETA: f32 = 0.1;
+const SAMPLING_DRY_BASE: f32 = 1.75;
+const SAMPLING_DRY_ALLOWED_LENGTH: i32 = 2;
+/// Sequence breakers llama.cpp's own tools use for the DRY sampler.
+const SAMPLING_DRY_BREAKERS: [&str; 4] = ["\n", ":", "\"", "*"];
+const GRAMMAR_ROOT: &str = "root";
 const TOKEN_PIECE_INITIAL: usize = 64;
 const TOKEN_PIECE_MAX: usize = 8192;
 
@@ -37,26 +62,699 @@ unsafe extern "C" fn llama_log_filter(level: ggml_log_level, text: *const c_char
     }
 }
 
+/// Default upper bound for `n_batch`/`n_ubatch`; larger values only cost
+/// memory since prompts are decoded in chunks anyway.
+const DEFAULT_BATCH_SIZE: u32 = 512;
+
+/// Whether llama.cpp uses flash attention; `Auto` lets it decide per backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashAttention {
+    Auto,
+    Enabled,
+    Disabled,
+}
+
+impl FlashAttention {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(Self::Auto),
+            "on" | "true" | "yes" | "enabled" => Some(Self::Enabled),
+            "off" | "false" | "no" | "disabled" => Some(Self::Disabled),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Enabled => "on",
+            Self::Disabled => "off",
+        }
+    }
+
+    fn to_raw(self) -> llama_flash_attn_type {
+        match self {
+            Self::Auto => LLAMA_FLASH_ATTN_TYPE_AUTO,
+            Self::Enabled => LLAMA_FLASH_ATTN_TYPE_ENABLED,
+            Self::Disabled => LLAMA_FLASH_ATTN_TYPE_DISABLED,
+        }
+    }
+
+    fn from_raw(raw: llama_flash_attn_type) -> Self {
+        match raw {
+            LLAMA_FLASH_ATTN_TYPE_ENABLED => Self::Enabled,
+            LLAMA_FLASH_ATTN_TYPE_DISABLED => Self::Disabled,
+            _ => Self::Auto,
+        }
+    }
+}
+
+/// Element type of the KV cache. Quantized types save memory at some cost in
+/// quality; llama.cpp needs flash attention for a quantized V cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvCacheType {
+    F32,
+    F16,
+    Bf16,
+    Q8_0,
+    Q5_1,
+    Q5_0,
+    Q4_1,
+    Q4_0,
+}
+
+impl KvCacheType {
+    const ALL: [Self; 8] = [
+        Self::F32,
+        Self::F16,
+        Self::Bf16,
+        Self::Q8_0,
+        Self::Q5_1,
+        Self::Q5_0,
+        Self::Q4_1,
+        Self::Q4_0,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|kind| kind.as_str() == value)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::F16 => "f16",
+            Self::Bf16 => "bf16",
+            Self::Q8_0 => "q8_0",
+            Self::Q5_1 => "q5_1",
+            Self::Q5_0 => "q5_0",
+            Self::Q4_1 => "q4_1",
+            Self::Q4_0 => "q4_0",
+        }
+    }
+
+    fn to_raw(self) -> ggml_type {
+        match self {
+            Self::F32 => GGML_TYPE_F32,
+            Self::F16 => GGML_TYPE_F16,
+            Self::Bf16 => GGML_TYPE_BF16,
+            Self::Q8_0 => GGML_TYPE_Q8_0,
+            Self::Q5_1 => GGML_TYPE_Q5_1,
+            Self::Q5_0 => GGML_TYPE_Q5_0,
+            Self::Q4_1 => GGML_TYPE_Q4_1,
+            Self::Q4_0 => GGML_TYPE_Q4_0,
+        }
+    }
+
+    fn from_raw(raw: ggml_type) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.to_raw() == raw)
+    }
+}
+
+/// Mirostat mode; when enabled it replaces top-k, typical-p, top-p and min-p.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirostat {
+    Disabled,
+    V1,
+    V2,
+}
+
+impl Mirostat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "0" | "off" | "false" => Some(Self::Disabled),
+            "1" | "v1" => Some(Self::V1),
+            "2" | "v2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+/// How the next token is picked from the logits. Each setting maps onto one
+/// sampler of llama.cpp's sampler chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingOptions {
+    /// `0` or below picks the most likely token.
+    pub temperature: f32,
+    /// `0` disables top-k.
+    pub top_k: usize,
+    pub top_p: f32,
+    pub min_p: f32,
+    /// Locally typical sampling; `1.0` disables it.
+    pub typical_p: f32,
+    /// Divides positive logits (multiplies negative ones) of tokens seen in
+    /// the penalty window; `1.0` disables it.
+    pub repeat_penalty: f32,
+    /// Subtracted from a token's logit once per occurrence in the window.
+    pub frequency_penalty: f32,
+    /// Subtracted once from the logit of every token in the window.
+    pub presence_penalty: f32,
+    /// Number of most recently generated tokens the penalties look at.
+    pub penalty_last_n: usize,
+    pub mirostat: Mirostat,
+    pub mirostat_tau: f32,
+    pub mirostat_eta: f32,
+    /// Strength of the DRY ("don't repeat yourself") penalty on repeated
+    /// sequences; `0.0` disables it.
+    pub dry_multiplier: f32,
+    pub dry_base: f32,
+    /// Repeated sequences up to this length go unpenalised.
+    pub dry_allowed_length: i32,
+    /// Tokens DRY looks back over; `-1` means the whole context.
+    pub dry_penalty_last_n: i32,
+    /// GBNF grammar the output must follow, with `root` as start symbol.
+    pub grammar: Option<String>,
+    /// Fixed seed for reproducible output; `None` picks a random one.
+    pub seed: Option<u32>,
+    /// Generation ends right before the first occurrence of any of these.
+    pub stop: Vec<String>,
+}
+
+impl Default for SamplingOptions {
+    fn default() -> Self {
+        Self {
+            temperature: SAMPLING_TEMPERATURE,
+            top_k: SAMPLING_TOP_K,
+            top_p: SAMPLING_TOP_P,
+            min_p: SAMPLING_MIN_P,
+            typical_p: SAMPLING_TYPICAL_P,
+            repeat_penalty: SAMPLING_REPEAT_PENALTY,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            penalty_last_n: SAMPLING_PENALTY_LAST_N,
+            mirostat: Mirostat::Disabled,
+            mirostat_tau: SAMPLING_MIROSTAT_TAU,
+            mirostat_eta: SAMPLING_MIROSTAT_ETA,
+            dry_multiplier: 0.0,
+            dry_base: SAMPLING_DRY_BASE,
+            dry_allowed_length: SAMPLING_DRY_ALLOWED_LENGTH,
+            dry_penalty_last_n: -1,
+            grammar: None,
+            seed: None,
+            stop: Vec::new(),
+        }
+    }
+}
+
+/// One sampler of the chain, in the order they are applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SamplerStage {
+    Grammar,
+    Penalties,
+    Dry,
+    TopK(i32),
+    Typical(f32),
+    TopP(f32),
+    MinP(f32),
+    Temperature(f32),
+    Mirostat(Mirostat),
+    Distribution,
+}
+
+/// The chain llama.cpp's own tools build for `options`: constraints and
+/// penalties first, then truncation, temperature and the final draw.
+fn sampler_stages(options: &SamplingOptions) -> Vec<SamplerStage> {
+    let mut stages = Vec::new();
+    if options.grammar.is_some() {
+        stages.push(SamplerStage::Grammar);
+    }
+    let penalised = options.repeat_penalty != 1.0
+        || options.frequency_penalty != 0.0
+        || options.presence_penalty != 0.0;
+    if options.penalty_last_n > 0 && penalised {
+        stages.push(SamplerStage::Penalties);
+    }
+    if options.dry_multiplier > 0.0 {
+        stages.push(SamplerStage::Dry);
+    }
+
+    if options.mirostat != Mirostat::Disabled {
+        stages.push(SamplerStage::Temperature(options.temperature));
+        stages.push(SamplerStage::Mirostat(options.mirostat));
+        return stages;
+    }
+
+    if options.top_k > 0 {
+        stages.push(SamplerStage::TopK(
+            options.top_k.min(i32::MAX as usize) as i32
+        ));
+    }
+    if options.typical_p < 1.0 {
+        stages.push(SamplerStage::Typical(options.typical_p));
+    }
+    if options.top_p < 1.0 {
+        stages.push(SamplerStage::TopP(options.top_p));
+    }
+    if options.min_p > 0.0 {
+        stages.push(SamplerStage::MinP(options.min_p));
+    }
+    stages.push(SamplerStage::Temperature(options.temperature));
+    stages.push(SamplerStage::Distribution);
+    stages
+}
+
+/// An owned llama.cpp sampler chain.
+struct SamplerChain(*mut llama_sampler);
+
+impl Drop for SamplerChain {
+    fn drop(&mut self) {
+        unsafe {
+            llama_sampler_free(self.0);
+        }
+    }
+}
+
+/// Builds the sampler chain for `options`. `vocab` and `model` are only read
+/// by the grammar, DRY and mirostat v1 samplers.
+unsafe fn build_sampler_chain(
+    options: &SamplingOptions,
+    vocab: *const llama_vocab,
+    model: *const llama_model,
+) -> Result<SamplerChain, LlamaError> {
+    let seed = options.seed.unwrap_or(LLAMA_DEFAULT_SEED);
+    let chain = SamplerChain(llama_sampler_chain_init(
+        llama_sampler_chain_default_params(),
+    ));
+    if chain.0.is_null() {
+        return Err(LlamaError::Inference(
+            "Failed to create the sampler chain".to_string(),
+        ));
+    }
+
+    for stage in sampler_stages(options) {
+        let sampler = match stage {
+            SamplerStage::Grammar => {
+                let grammar =
+                    CString::new(options.grammar.clone().unwrap_or_default()).map_err(|_| {
+                        LlamaError::InvalidOptions(
+                            "Sampling grammar contains null bytes".to_string(),
+                        )
+                    })?;
+                let root = CString::new(GRAMMAR_ROOT).expect("static root name");
+                let sampler = llama_sampler_init_grammar(vocab, grammar.as_ptr(), root.as_ptr());
+                if sampler.is_null() {
+                    return Err(LlamaError::InvalidOptions(
+                        "Failed to parse the sampling grammar".to_string(),
+                    ));
+                }
+                sampler
+            }
+            SamplerStage::Penalties => llama_sampler_init_penalties(
+                options.penalty_last_n.min(i32::MAX as usize) as i32,
+                options.repeat_penalty,
+                options.frequency_penalty,
+                options.presence_penalty,
+            ),
+            SamplerStage::Dry => {
+                let breakers: Vec<CString> = SAMPLING_DRY_BREAKERS
+                    .iter()
+                    .map(|breaker| CString::new(*breaker).expect("static breaker"))
+                    .collect();
+                let mut pointers: Vec<*const c_char> =
+                    breakers.iter().map(|breaker| breaker.as_ptr()).collect();
+                llama_sampler_init_dry(
+                    vocab,
+                    llama_model_n_ctx_train(model),
+                    options.dry_multiplier,
+                    options.dry_base,
+                    options.dry_allowed_length,
+                    options.dry_penalty_last_n,
+                    pointers.as_mut_ptr(),
+                    pointers.len(),
+                )
+            }
+            SamplerStage::TopK(k) => llama_sampler_init_top_k(k),
+            SamplerStage::Typical(p) => llama_sampler_init_typical(p, 1),
+            SamplerStage::TopP(p) => llama_sampler_init_top_p(p, 1),
+            SamplerStage::MinP(p) => llama_sampler_init_min_p(p, 1),
+            SamplerStage::Temperature(t) => llama_sampler_init_temp(t),
+            SamplerStage::Mirostat(Mirostat::V2) => {
+                llama_sampler_init_mirostat_v2(seed, options.mirostat_tau, options.mirostat_eta)
+            }
+            SamplerStage::Mirostat(_) => llama_sampler_init_mirostat(
+                llama_n_vocab(vocab),
+                seed,
+                options.mirostat_tau,
+                options.mirostat_eta,
+                100,
+            ),
+            SamplerStage::Distribution => llama_sampler_init_dist(seed),
+        };
+        if sampler.is_null() {
+            return Err(LlamaError::Inference(format!(
+                "Failed to create the {stage:?} sampler"
+            )));
+        }
+        llama_sampler_chain_add(chain.0, sampler);
+    }
+
+    Ok(chain)
+}
+
+/// Runs `chain` over one logit per vocabulary entry and records the pick in
+/// its history, as `llama_sampler_sample` does. The end-of-sequence token is
+/// masked out until `allow_eos`.
+unsafe fn sample_from_logits(
+    chain: &SamplerChain,
+    logits: &[f32],
+    eos_token: llama_token,
+    allow_eos: bool,
+) -> llama_token {
+    let mut candidates: Vec<llama_token_data> = logits
+        .iter()
+        .enumerate()
+        .map(|(id, &logit)| llama_token_data {
+            id: id as llama_token,
+            logit,
+            p: 0.0,
+        })
+        .collect();
+    if !allow_eos {
+        if let Some(eos) = usize::try_from(eos_token)
+            .ok()
+            .and_then(|index| candidates.get_mut(index))
+        {
+            eos.logit = f32::NEG_INFINITY;
+        }
+    }
+
+    let mut array = llama_token_data_array {
+        data: candidates.as_mut_ptr(),
+        size: candidates.len(),
+        selected: -1,
+        sorted: false,
+    };
+    llama_sampler_apply(chain.0, &mut array);
+    let token = usize::try_from(array.selected)
+        .ok()
+        .filter(|&index| index < array.size)
+        .map_or(eos_token, |index| (*array.data.add(index)).id);
+    llama_sampler_accept(chain.0, token);
+    token
+}
+
+/// Load-time settings for [`LlamaSession::new`]. `None` keeps git-ca's
+/// default: all cores, batches of at most 512 tokens, and llama.cpp's own
+/// defaults for everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionOptions {
+    pub n_ctx: i32,
+    pub threads: Option<i32>,
+    pub threads_batch: Option<i32>,
+    pub batch_size: Option<u32>,
+    pub ubatch_size: Option<u32>,
+    pub gpu_layers: Option<i32>,
+    pub mmap: Option<bool>,
+    pub mlock: Option<bool>,
+    pub flash_attention: Option<FlashAttention>,
+    pub kv_cache_type: Option<KvCacheType>,
+    pub sampling: SamplingOptions,
+}
+
+impl SessionOptions {
+    pub fn new(n_ctx: i32) -> Self {
+        Self {
+            n_ctx,
+            threads: None,
+            threads_batch: None,
+            batch_size: None,
+            ubatch_size: None,
+            gpu_layers: None,
+            mmap: None,
+            mlock: None,
+            flash_attention: None,
+            kv_cache_type: None,
+            sampling: SamplingOptions::default(),
+        }
+    }
+}
+
+/// The values a session was actually created with, after defaults.
+#[derive(Debug, Clone)]
+pub struct EffectiveParams {
+    pub n_ctx: u32,
+    pub threads: i32,
+    pub threads_batch: i32,
+    pub batch_size: u32,
+    pub ubatch_size: u32,
+    pub gpu_layers: i32,
+    pub mmap: bool,
+    pub mlock: bool,
+    pub flash_attention: FlashAttention,
+    pub kv_cache_type: Option<KvCacheType>,
+}
+
+/// Token counts and timings llama.cpp collected for a context.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PerfStats {
+    pub prompt_tokens: usize,
+    pub prompt_ms: f64,
+    pub generated_tokens: usize,
+    pub generation_ms: f64,
+}
+
+impl PerfStats {
+    /// Prompt tokens evaluated per second.
+    pub fn prompt_rate(&self) -> Option<f64> {
+        rate(self.prompt_tokens, self.prompt_ms)
+    }
+
+    /// Tokens generated per second.
+    pub fn generation_rate(&self) -> Option<f64> {
+        rate(self.generated_tokens, self.generation_ms)
+    }
+}
+
+fn rate(tokens: usize, ms: f64) -> Option<f64> {
+    (tokens > 0 && ms > 0.0).then(|| tokens as f64 * 1000.0 / ms)
+}
+
+/// Why loading a model or generating text failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LlamaError {
+    ModelNotFound(PathBuf),
+    /// The backend, model or context could not be set up.
+    Load(String),
+    /// Session or sampling settings llama.cpp rejected.
+    InvalidOptions(String),
+    /// The prompt does not fit in the tokens available for it.
+    PromptTooLong {
+        tokens: usize,
+        available: usize,
+    },
+    /// Tokenizing, decoding or sampling failed, or the daemon went away.
+    Inference(String),
+    Aborted(AbortReason),
+    /// A prompt state file could not be written.
+    State(String),
+}
+
+impl fmt::Display for LlamaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlamaError::ModelNotFound(path) => {
+                write!(f, "Model file not found at {}", path.display())
+            }
+            LlamaError::Load(msg) | LlamaError::InvalidOptions(msg) => write!(f, "{msg}"),
+            LlamaError::PromptTooLong { tokens, available } => write!(
+                f,
+                "Prompt needs {tokens} tokens but only {available} are available; increase the context size"
+            ),
+            LlamaError::Inference(msg) | LlamaError::State(msg) => write!(f, "{msg}"),
+            LlamaError::Aborted(reason) => write!(f, "{}", reason.message()),
+        }
+    }
+}
+
+impl std::error::Error for LlamaError {}
+
+/// Text generation as the prompt pipeline uses it, served either by an
+/// in-process [`LlamaSession`] or by the background daemon.
+pub trait Generator {
+    /// Size of the context window in tokens.
+    fn context_size(&self) -> usize;
+
+    /// Number of tokens `text` occupies when submitted as a prompt, including
+    /// the BOS token the model adds.
+    fn count_tokens(&self, text: &str) -> Result<usize, LlamaError>;
+
+    /// Generates up to `max_tokens` tokens after `prompt`, handing every
+    /// generated piece of text to `on_token` as soon as it is sampled. The
+    /// prompt must fit in the context window; callers are expected to budget
+    /// it with [`Generator::count_tokens`] rather than rely on truncation.
+    fn infer_streaming(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String, LlamaError>;
+
+    /// Like [`Generator::infer_streaming`], without the live output.
+    fn infer(&mut self, prompt: &str, max_tokens: usize) -> Result<String, LlamaError> {
+        self.infer_streaming(prompt, max_tokens, &mut |_| {})
+    }
+
+    /// Makes sure the start of upcoming prompts, `prefix`, is already
+    /// evaluated so only the rest of each prompt needs decoding.
+    fn prime_prefix(&mut self, _prefix: &str) -> Result<(), LlamaError> {
+        Ok(())
+    }
+
+    /// Stops prompt decoding and generation once `signal` fires; the aborted
+    /// call returns an error and [`Generator::abort_reason`] says why.
+    fn set_abort_signal(&mut self, signal: AbortSignal);
+
+    fn abort_reason(&self) -> Option<AbortReason>;
+}
+
+/// A [`Generator`] for tests: every byte of a prompt is one token, and
+/// prompts are answered with `responses` in order. A scripted
+/// [`LlamaError::Aborted`] also sets the abort reason, as a real abort would.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ScriptedGenerator {
+    pub context_size: usize,
+    pub responses: std::collections::VecDeque<Result<String, LlamaError>>,
+    pub prompts: Vec<String>,
+    pub abort: Option<AbortReason>,
+}
+
+#[cfg(test)]
+impl Generator for ScriptedGenerator {
+    fn context_size(&self) -> usize {
+        self.context_size
+    }
+
+    fn count_tokens(&self, text: &str) -> Result<usize, LlamaError> {
+        Ok(text.len())
+    }
+
+    fn infer_streaming(
+        &mut self,
+        prompt: &str,
+        _max_tokens: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String, LlamaError> {
+        self.prompts.push(prompt.to_string());
+        let response = self
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| Err(LlamaError::Inference("No scripted response".to_string())));
+        match &response {
+            Ok(text) => on_token(text),
+            Err(LlamaError::Aborted(reason)) => self.abort = Some(*reason),
+            Err(_) => {}
+        }
+        response
+    }
+
+    fn set_abort_signal(&mut self, _signal: AbortSignal) {
+        self.abort = None;
+    }
+
+    fn abort_reason(&self) -> Option<AbortReason> {
+        self.abort
+    }
+}
+
 #[derive(Debug)]
 pub struct LlamaSession {
     model: *mut llama_model,
     ctx: *mut llama_cpp_sys_2::llama_context,
     vocab: *const llama_vocab,
     n_ctx: i32,
+    params: EffectiveParams,
+    sampling: SamplingOptions,
+    /// Boxed so llama.cpp can hold a stable pointer to it for its abort callback.
+    abort: Box<AbortSignal>,
+    /// Tokens whose KV entries currently sit in the context, in position order.
+    /// A new prompt only decodes what follows its common prefix with these.
+    cached: Vec<llama_token>,
+    model_path: PathBuf,
+    /// Directory for saved prefix states, with the model fingerprint they are
+    /// keyed by; `None` keeps primed prefixes in memory only.
+    state_cache: Option<(PathBuf, u64)>,
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+/// Bytes of the model file hashed for its fingerprint: the GGUF header and
+/// metadata live at the start.
+const FINGERPRINT_HEAD_BYTES: u64 = 1 << 20;
+const MAX_STATE_FILES: usize = 8;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Cheap identity for a model file: its size, modification time and leading
+/// bytes, so replacing or re-downloading the model changes it.
+fn model_fingerprint(path: &Path) -> io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let mut head = Vec::new();
+    File::open(path)?
+        .take(FINGERPRINT_HEAD_BYTES)
+        .read_to_end(&mut head)?;
+
+    let hash = fnv1a(FNV_OFFSET, &metadata.len().to_le_bytes());
+    let hash = fnv1a(hash, &modified.to_le_bytes());
+    Ok(fnv1a(hash, &head))
+}
+
+fn state_file_name(model: u64, tokens: &[llama_token], n_ctx: i32) -> String {
+    let hash = fnv1a(FNV_OFFSET, &n_ctx.to_le_bytes());
+    let hash = tokens
+        .iter()
+        .fold(hash, |hash, token| fnv1a(hash, &token.to_le_bytes()));
+    format!("{model:016x}-{hash:016x}.state")
+}
+
+/// Drops all but the most recently written state files.
+fn prune_state_files(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "state"))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, path) in files.into_iter().skip(MAX_STATE_FILES) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+unsafe extern "C" fn abort_requested(data: *mut c_void) -> bool {
+    !data.is_null() && (*(data as *const AbortSignal)).is_aborted()
 }
 
 impl LlamaSession {
-    pub fn new(model_path: &Path, n_ctx: i32) -> Result<Self, String> {
+    pub fn new(model_path: &Path, options: &SessionOptions) -> Result<Self, LlamaError> {
         if !model_path.exists() {
-            return Err(format!("Model file not found at {}", model_path.display()));
+            return Err(LlamaError::ModelNotFound(model_path.to_path_buf()));
         }
 
-        let model_path_cstr = CString::new(
-            model_path
-                .to_str()
-                .ok_or_else(|| "Model path contains invalid UTF-8 characters".to_string())?,
-        )
-        .map_err(|_| "Model path contains interior null bytes".to_string())?;
+        let model_path_cstr = CString::new(model_path.to_str().ok_or_else(|| {
+            LlamaError::Load("Model path contains invalid UTF-8 characters".to_string())
+        })?)
+        .map_err(|_| LlamaError::Load("Model path contains interior null bytes".to_string()))?;
+
+        let n_ctx = options.n_ctx;
+        if n_ctx <= 0 {
+            return Err(LlamaError::InvalidOptions(format!(
+                "Context size must be positive, got {n_ctx}"
+            )));
+        }
 
         unsafe {
             LOG_INITIALIZED.call_once(|| {
@@ -64,80 +762,197 @@ impl LlamaSession {
             });
             llama_backend_init();
 
-            let model_params = llama_model_default_params();
+            let mut model_params = llama_model_default_params();
+            if let Some(layers) = options.gpu_layers {
+                model_params.n_gpu_layers = layers;
+            }
+            if let Some(mmap) = options.mmap {
+                model_params.use_mmap = mmap;
+            }
+            if let Some(mlock) = options.mlock {
+                model_params.use_mlock = mlock;
+            }
+
             let model = llama_load_model_from_file(model_path_cstr.as_ptr(), model_params);
             if model.is_null() {
                 llama_backend_free();
-                return Err("Failed to load GGUF model".to_string());
+                return Err(LlamaError::Load("Failed to load GGUF model".to_string()));
             }
 
             let vocab = llama_model_get_vocab(model);
             if vocab.is_null() {
                 llama_free_model(model);
                 llama_backend_free();
-                return Err("Failed to resolve model vocabulary".to_string());
+                return Err(LlamaError::Load(
+                    "Failed to resolve model vocabulary".to_string(),
+                ));
             }
 
             let mut ctx_params = llama_context_default_params();
             ctx_params.n_ctx = n_ctx as u32;
-            ctx_params.n_batch = n_ctx as u32;
-            ctx_params.n_ubatch = n_ctx as u32;
+            ctx_params.n_batch = options
+                .batch_size
+                .unwrap_or(DEFAULT_BATCH_SIZE)
+                .clamp(1, n_ctx as u32);
+            ctx_params.n_ubatch = options
+                .ubatch_size
+                .unwrap_or(DEFAULT_BATCH_SIZE)
+                .clamp(1, ctx_params.n_batch);
             ctx_params.n_seq_max = MAX_SEQ_ID as u32;
 
-            let threads = std::thread::available_parallelism()
-                .map(|n| n.get() as i32)
-                .unwrap_or(4)
+            let threads = options
+                .threads
+                .unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|n| n.get() as i32)
+                        .unwrap_or(4)
+                })
                 .max(1);
+            let threads_batch = options.threads_batch.unwrap_or(threads).max(1);
             ctx_params.n_threads = threads;
-            ctx_params.n_threads_batch = threads;
+            ctx_params.n_threads_batch = threads_batch;
+            // Cheap to collect, and `git ca doctor` reports them.
+            ctx_params.no_perf = false;
+
+            if let Some(flash_attention) = options.flash_attention {
+                ctx_params.flash_attn_type = flash_attention.to_raw();
+            }
+            if let Some(kv_cache_type) = options.kv_cache_type {
+                ctx_params.type_k = kv_cache_type.to_raw();
+                ctx_params.type_v = kv_cache_type.to_raw();
+            }
 
             let ctx = llama_new_context_with_model(model, ctx_params);
             if ctx.is_null() {
                 llama_free_model(model);
                 llama_backend_free();
-                return Err("Failed to create llama.cpp context".to_string());
+                return Err(LlamaError::Load(
+                    "Failed to create llama.cpp context".to_string(),
+                ));
             }
 
-            llama_set_n_threads(ctx, threads, threads);
+            llama_set_n_threads(ctx, threads, threads_batch);
+
+            let mut abort = Box::<AbortSignal>::default();
+            llama_set_abort_callback(
+                ctx,
+                Some(abort_requested),
+                (&mut *abort as *mut AbortSignal).cast(),
+            );
+
+            let params = EffectiveParams {
+                n_ctx: ctx_params.n_ctx,
+                threads,
+                threads_batch,
+                batch_size: ctx_params.n_batch,
+                ubatch_size: ctx_params.n_ubatch,
+                gpu_layers: model_params.n_gpu_layers,
+                mmap: model_params.use_mmap,
+                mlock: model_params.use_mlock,
+                flash_attention: FlashAttention::from_raw(ctx_params.flash_attn_type),
+                kv_cache_type: KvCacheType::from_raw(ctx_params.type_k),
+            };
 
             Ok(Self {
                 model,
                 ctx,
                 vocab,
                 n_ctx,
+                params,
+                sampling: options.sampling.clone(),
+                abort,
+                cached: Vec::new(),
+                model_path: model_path.to_path_buf(),
+                state_cache: None,
             })
         }
     }
 
-    pub fn infer(&mut self, prompt: &str, max_tokens: usize) -> Result<String, String> {
-        let prompt_cstr = CString::new(prompt).map_err(|_| {
-            "Prompt contains interior null bytes which cannot be processed".to_string()
-        })?;
+    /// The settings this session was created with.
+    pub fn params(&self) -> &EffectiveParams {
+        &self.params
+    }
 
-        unsafe {
-            let memory = llama_get_memory(self.ctx);
-            if !memory.is_null() {
-                llama_memory_clear(memory, true);
-            }
+    /// Size of the loaded weights in bytes.
+    pub fn model_size(&self) -> u64 {
+        unsafe { llama_model_size(self.model) }
+    }
+
+    /// Prompt and generation timings since the session started or
+    /// [`LlamaSession::reset_perf`] was last called.
+    pub fn perf(&self) -> PerfStats {
+        let data = unsafe { llama_perf_context(self.ctx) };
+        PerfStats {
+            prompt_tokens: data.n_p_eval.max(0) as usize,
+            prompt_ms: data.t_p_eval_ms,
+            generated_tokens: data.n_eval.max(0) as usize,
+            generation_ms: data.t_eval_ms,
         }
+    }
+
+    pub fn reset_perf(&mut self) {
+        unsafe { llama_perf_context_reset(self.ctx) };
+    }
+
+    /// Replaces the sampling settings from the next generation on.
+    pub fn set_sampling(&mut self, sampling: SamplingOptions) {
+        self.sampling = sampling;
+    }
+
+    /// Switches the number of threads used for generation and for prompt
+    /// batches from the next decode on.
+    pub fn set_threads(&mut self, threads: i32, threads_batch: i32) {
+        let (threads, threads_batch) = (threads.max(1), threads_batch.max(1));
+        unsafe { llama_set_n_threads(self.ctx, threads, threads_batch) };
+        self.params.threads = threads;
+        self.params.threads_batch = threads_batch;
+    }
+
+    /// Saves evaluated prompt prefixes under `dir` and restores them on later
+    /// runs instead of evaluating them again. Files are keyed by a fingerprint
+    /// of the model file and a hash of the prefix tokens, so a changed model or
+    /// prompt template simply misses the cache.
+    pub fn with_state_cache(mut self, dir: PathBuf) -> Self {
+        match model_fingerprint(&self.model_path) {
+            Ok(fingerprint) => self.state_cache = Some((dir, fingerprint)),
+            Err(err) => eprintln!("[git-ca] warning: prompt state cache disabled: {err}"),
+        }
+        self
+    }
+
+    fn check_abort(&self) -> Result<(), LlamaError> {
+        match self.abort.reason() {
+            Some(reason) => Err(LlamaError::Aborted(reason)),
+            None => Ok(()),
+        }
+    }
+
+    fn tokenize(&self, text: &str) -> Result<Vec<llama_token>, LlamaError> {
+        let text_cstr = CString::new(text).map_err(|_| {
+            LlamaError::Inference(
+                "Prompt contains interior null bytes which cannot be processed".to_string(),
+            )
+        })?;
+        let text_len = i32::try_from(text.len()).map_err(|_| {
+            LlamaError::Inference("Prompt length exceeds supported limits".to_string())
+        })?;
 
         let mut tokens: Vec<llama_token> = Vec::new();
-        let mut capacity = prompt.len().max(1) + 8;
+        let mut capacity = text.len().max(1) + 8;
 
         loop {
             if capacity > i32::MAX as usize {
-                return Err("Prompt too long for llama.cpp tokenizer".to_string());
+                return Err(LlamaError::Inference(
+                    "Prompt too long for llama.cpp tokenizer".to_string(),
+                ));
             }
 
             tokens.resize(capacity, 0);
 
-            let text_len = i32::try_from(prompt.len())
-                .map_err(|_| "Prompt length exceeds supported limits".to_string())?;
-
             let n_tokens = unsafe {
                 llama_tokenize(
                     self.vocab,
-                    prompt_cstr.as_ptr(),
+                    text_cstr.as_ptr(),
                     text_len,
                     tokens.as_mut_ptr(),
                     capacity as i32,
@@ -148,95 +963,128 @@ impl LlamaSession {
 
             if n_tokens >= 0 {
                 tokens.truncate(n_tokens as usize);
-                break;
+                return Ok(tokens);
             }
 
-            capacity = capacity.saturating_mul(2);
+            capacity = (n_tokens.unsigned_abs() as usize).max(capacity + 1);
         }
+    }
 
-        if tokens.len() >= self.n_ctx.saturating_sub(32) as usize {
-            let max_tokens = self.n_ctx.saturating_sub(32).max(1) as usize;
-            if tokens.len() > max_tokens {
-                let drop_count = tokens.len() - max_tokens;
-                tokens.drain(0..drop_count);
+    fn clear_memory(&mut self) {
+        unsafe {
+            let memory = llama_get_memory(self.ctx);
+            if !memory.is_null() {
+                llama_memory_clear(memory, true);
             }
         }
+        self.cached.clear();
+    }
 
-        unsafe {
-            self.decode_sequence(&tokens, 0)?;
+    /// Loads a saved state and keeps it only if it holds exactly `tokens`;
+    /// unreadable or stale files (e.g. from another llama.cpp build) are
+    /// ignored and later overwritten.
+    fn restore_state(&mut self, path: &Path, tokens: &[llama_token]) -> bool {
+        if !path.is_file() {
+            return false;
         }
+        let Some(path_cstr) = path.to_str().and_then(|path| CString::new(path).ok()) else {
+            return false;
+        };
+
+        self.clear_memory();
+        let mut restored = vec![0 as llama_token; tokens.len()];
+        let mut n_restored = 0usize;
+        let loaded = unsafe {
+            llama_state_load_file(
+                self.ctx,
+                path_cstr.as_ptr(),
+                restored.as_mut_ptr(),
+                restored.len(),
+                &mut n_restored,
+            )
+        };
+        if loaded && restored.get(..n_restored) == Some(tokens) {
+            self.cached = tokens.to_vec();
+            true
+        } else {
+            self.clear_memory();
+            false
+        }
+    }
 
-        let mut n_past = tokens.len() as i32;
-        let mut generated = String::new();
-        let eos_token = unsafe { llama_token_eos(self.vocab) };
-        let vocab_size = unsafe { llama_n_vocab(self.vocab) } as usize;
-
-        let mut decode_batch = unsafe { llama_batch_init(1, 0, MAX_SEQ_ID) };
-        let mut decode_error: Option<String> = None;
-        let mut has_meaningful_text = false;
-
-        for _ in 0..max_tokens {
-            let allow_eos = has_meaningful_text;
-            let next_token = unsafe { self.sample_next_token(vocab_size, eos_token, allow_eos) };
-            if next_token == eos_token {
-                break;
-            }
-
-            let token_text = unsafe { self.token_to_string(next_token) };
-            generated.push_str(&token_text);
-            if !token_text.trim().is_empty() {
-                has_meaningful_text = true;
-            }
-
-            unsafe {
-                decode_batch.n_tokens = 1;
-                (*decode_batch.token) = next_token;
-                (*decode_batch.pos) = n_past;
-                (*decode_batch.n_seq_id) = 1;
-                let seq_ptr = *decode_batch.seq_id;
-                seq_ptr.write(0);
-                (*decode_batch.logits) = 1;
-
-                if llama_decode(self.ctx, decode_batch) != 0 {
-                    if generated.trim().is_empty() {
-                        decode_error =
-                            Some("Model evaluation failed during generation".to_string());
-                    }
-                    break;
-                }
-            }
+    /// Writes the current state next to `path` and renames it into place, so
+    /// concurrent runs never read a half-written file.
+    fn save_state(&self, path: &Path) -> Result<(), LlamaError> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| LlamaError::State("State file has no parent directory".to_string()))?;
+        fs::create_dir_all(dir).map_err(|err| LlamaError::State(err.to_string()))?;
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        let partial_cstr = partial
+            .to_str()
+            .and_then(|partial| CString::new(partial).ok())
+            .ok_or_else(|| LlamaError::State("State path is not valid UTF-8".to_string()))?;
+
+        let saved = unsafe {
+            llama_state_save_file(
+                self.ctx,
+                partial_cstr.as_ptr(),
+                self.cached.as_ptr(),
+                self.cached.len(),
+            )
+        };
+        if !saved {
+            let _ = fs::remove_file(&partial);
+            return Err(LlamaError::State(
+                "llama.cpp failed to write the state file".to_string(),
+            ));
+        }
+        fs::rename(&partial, path).map_err(|err| LlamaError::State(err.to_string()))?;
+        prune_state_files(dir);
+        Ok(())
+    }
 
-            n_past += 1;
+    /// Keeps the KV entries for the longest prefix `tokens` shares with the
+    /// previous evaluation (typically the fixed instruction block) and drops
+    /// the rest. Returns how many leading tokens need no decoding.
+    fn reuse_cached_prefix(&mut self, tokens: &[llama_token]) -> usize {
+        let keep = reusable_prefix(&self.cached, tokens);
 
-            if generated.trim().is_empty() {
-                continue;
+        unsafe {
+            let memory = llama_get_memory(self.ctx);
+            if memory.is_null() {
+                self.cached.clear();
+                return 0;
             }
-
-            if generated.ends_with('\n') && generated.lines().count() >= 2 {
-                break;
+            // Some memory types cannot drop a partial range; start over then.
+            if keep == 0 || !llama_memory_seq_rm(memory, 0, keep as i32, -1) {
+                llama_memory_clear(memory, true);
+                self.cached.clear();
+                return 0;
             }
         }
 
-        unsafe {
-            llama_batch_free(decode_batch);
-        }
-
-        if let Some(err) = decode_error {
-            return Err(err);
-        }
-
-        Ok(generated)
+        self.cached.truncate(keep);
+        keep
     }
 
-    unsafe fn decode_sequence(&self, tokens: &[llama_token], start_pos: i32) -> Result<(), String> {
+    unsafe fn decode_sequence(
+        &self,
+        tokens: &[llama_token],
+        start_pos: i32,
+    ) -> Result<(), LlamaError> {
         if tokens.is_empty() {
             return Ok(());
         }
 
-        let mut chunk_size = PROMPT_CHUNK_SIZE.max(1);
+        let mut chunk_size = PROMPT_CHUNK_SIZE
+            .min(self.params.batch_size as usize)
+            .max(1);
         let mut offset = 0usize;
 
         while offset < tokens.len() {
+            self.check_abort()?;
+
             let remaining = tokens.len() - offset;
             let current = remaining.min(chunk_size);
             let mut batch = llama_batch_init(current as i32, 0, MAX_SEQ_ID);
@@ -257,11 +1105,14 @@ impl LlamaSession {
             llama_batch_free(batch);
 
             if status != 0 {
+                self.check_abort()?;
                 if chunk_size > 1 {
                     chunk_size = chunk_size.saturating_div(2).max(1);
                     continue;
                 } else {
-                    return Err("Model evaluation failed during prompt ingestion".to_string());
+                    return Err(LlamaError::Inference(
+                        "Model evaluation failed during prompt ingestion".to_string(),
+                    ));
                 }
             }
 
@@ -271,145 +1122,233 @@ impl LlamaSession {
         Ok(())
     }
 
+    /// Builds a fresh sampler chain for one generation, so penalties and
+    /// mirostat state do not carry over between prompts.
+    unsafe fn sampler_chain(&self) -> Result<SamplerChain, LlamaError> {
+        build_sampler_chain(&self.sampling, self.vocab, self.model)
+    }
+
+    /// Samples from the logits of the last decoded token. The end-of-sequence
+    /// token is masked out until `allow_eos`.
     unsafe fn sample_next_token(
         &self,
-        vocab_size: usize,
+        chain: &SamplerChain,
         eos_token: llama_token,
         allow_eos: bool,
     ) -> llama_token {
-        let logits_ptr = llama_get_logits(self.ctx);
-        if logits_ptr.is_null() {
+        let logits = llama_get_logits_ith(self.ctx, -1);
+        if logits.is_null() {
             return eos_token;
         }
+        let n_vocab = llama_n_vocab(self.vocab).max(0) as usize;
+        sample_from_logits(
+            chain,
+            std::slice::from_raw_parts(logits, n_vocab),
+            eos_token,
+            allow_eos,
+        )
+    }
+
+    unsafe fn token_to_string(&self, token: llama_token) -> String {
+        let mut size = TOKEN_PIECE_INITIAL;
+        let mut buffer: Vec<i8> = Vec::new();
 
-        let logits = std::slice::from_raw_parts(logits_ptr, vocab_size);
-        let mut candidates: Vec<(llama_token, f32)> = logits
-            .iter()
-            .enumerate()
-            .map(|(idx, &logit)| (idx as llama_token, logit))
-            .collect();
+        loop {
+            if size > TOKEN_PIECE_MAX {
+                return String::new();
+            }
 
-        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+            buffer.resize(size, 0);
+            let written =
+                llama_token_to_piece(self.vocab, token, buffer.as_mut_ptr(), size as i32, 0, true);
 
-        let top_k = SAMPLING_TOP_K.max(1).min(candidates.len());
-        candidates.truncate(top_k);
-        let best_non_eos = candidates
-            .iter()
-            .find_map(|(token, _)| (*token != eos_token).then_some(*token));
+            if written < 0 {
+                size = size.saturating_mul(2);
+                continue;
+            }
 
-        let temperature = SAMPLING_TEMPERATURE.max(1e-5);
-        let mut scaled = Vec::with_capacity(candidates.len());
-        let mut max_logit = f32::NEG_INFINITY;
-        for &(token, logit) in &candidates {
-            let scaled_logit = logit / temperature;
-            if scaled_logit > max_logit {
-                max_logit = scaled_logit;
+            let written = written as usize;
+            if written >= size {
+                size = size.saturating_mul(2);
+                continue;
             }
-            scaled.push((token, scaled_logit));
+
+            let bytes: Vec<u8> = buffer[..written].iter().map(|b| *b as u8).collect();
+            return String::from_utf8(bytes).unwrap_or_default();
         }
+    }
+}
 
-        let mut weights = Vec::with_capacity(scaled.len());
-        let mut weight_sum = 0.0f32;
-        for (token, logit) in scaled {
-            let weight = (logit - max_logit).exp();
-            if weight.is_finite() && weight > 0.0 {
-                weight_sum += weight;
-                weights.push((token, weight));
-            }
+/// Byte offset of the earliest stop string in `text`, if any occurs.
+fn find_stop(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|stop| !stop.is_empty())
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+/// How many leading `tokens` can be kept from the `cached` evaluation: their
+/// common prefix, short of the last prompt token, which is always decoded
+/// again so fresh logits exist.
+fn reusable_prefix(cached: &[llama_token], tokens: &[llama_token]) -> usize {
+    let common = cached
+        .iter()
+        .zip(tokens)
+        .take_while(|(cached, token)| cached == token)
+        .count();
+    common.min(tokens.len().saturating_sub(1))
+}
+
+impl Generator for LlamaSession {
+    fn context_size(&self) -> usize {
+        self.n_ctx.max(0) as usize
+    }
+
+    fn count_tokens(&self, text: &str) -> Result<usize, LlamaError> {
+        self.tokenize(text).map(|tokens| tokens.len())
+    }
+
+    fn infer_streaming(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String, LlamaError> {
+        let tokens = self.tokenize(prompt)?;
+
+        if tokens.len() >= self.context_size() {
+            return Err(LlamaError::PromptTooLong {
+                tokens: tokens.len(),
+                available: self.context_size(),
+            });
         }
+        let max_tokens = max_tokens.min(self.context_size() - tokens.len());
 
-        if weights.is_empty() {
-            return best_non_eos
-                .or_else(|| candidates.first().map(|(token, _)| *token))
-                .unwrap_or(eos_token);
+        let reused = self.reuse_cached_prefix(&tokens);
+        self.cached.clear();
+        unsafe {
+            self.decode_sequence(&tokens[reused..], reused as i32)?;
         }
+        self.cached = tokens.clone();
 
-        weights.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        let mut n_past = tokens.len() as i32;
+        let mut generated = String::new();
+        let eos_token = unsafe { llama_token_eos(self.vocab) };
+        let chain = unsafe { self.sampler_chain()? };
 
-        let mut filtered = Vec::new();
-        let mut cumulative = 0.0;
-        for &(token, weight) in &weights {
-            let prob = weight / weight_sum;
-            cumulative += prob;
-            filtered.push((token, weight));
-            if SAMPLING_TOP_P < 1.0 && cumulative >= SAMPLING_TOP_P {
+        let mut decode_batch = unsafe { llama_batch_init(1, 0, MAX_SEQ_ID) };
+        let mut decode_error: Option<LlamaError> = None;
+        let mut has_meaningful_text = false;
+
+        for _ in 0..max_tokens {
+            if let Err(err) = self.check_abort() {
+                decode_error = Some(err);
                 break;
             }
-        }
 
-        if filtered.is_empty() {
-            filtered.push(weights[0]);
-        }
+            let allow_eos = has_meaningful_text;
+            let next_token = unsafe { self.sample_next_token(&chain, eos_token, allow_eos) };
+            if next_token == eos_token {
+                break;
+            }
 
-        if SAMPLING_MIN_P > 0.0 {
-            let max_weight = filtered
-                .iter()
-                .map(|(_, weight)| *weight)
-                .fold(f32::NEG_INFINITY, f32::max);
-            let threshold = max_weight * SAMPLING_MIN_P;
-            filtered.retain(|(_, weight)| *weight >= threshold);
-            if filtered.is_empty() {
-                filtered.push(weights[0]);
+            let token_text = unsafe { self.token_to_string(next_token) };
+            let streamed = generated.len();
+            generated.push_str(&token_text);
+            // Searched in full, since a stop string may straddle tokens.
+            if let Some(cut) = find_stop(&generated, &self.sampling.stop) {
+                if cut > streamed {
+                    on_token(&generated[streamed..cut]);
+                }
+                generated.truncate(cut);
+                break;
+            }
+            on_token(&token_text);
+            if !token_text.trim().is_empty() {
+                has_meaningful_text = true;
             }
-        }
 
-        if !allow_eos {
-            filtered.retain(|(token, _)| *token != eos_token);
-        }
+            unsafe {
+                decode_batch.n_tokens = 1;
+                (*decode_batch.token) = next_token;
+                (*decode_batch.pos) = n_past;
+                (*decode_batch.n_seq_id) = 1;
+                let seq_ptr = *decode_batch.seq_id;
+                seq_ptr.write(0);
+                (*decode_batch.logits) = 1;
+
+                if llama_decode(self.ctx, decode_batch) != 0 {
+                    self.cached.clear();
+                    if let Err(err) = self.check_abort() {
+                        decode_error = Some(err);
+                    } else if generated.trim().is_empty() {
+                        decode_error = Some(LlamaError::Inference(
+                            "Model evaluation failed during generation".to_string(),
+                        ));
+                    }
+                    break;
+                }
+            }
+
+            self.cached.push(next_token);
+            n_past += 1;
+
+            if generated.trim().is_empty() {
+                continue;
+            }
 
-        if filtered.is_empty() {
-            return best_non_eos
-                .or_else(|| candidates.first().map(|(token, _)| *token))
-                .unwrap_or(eos_token);
+            if generated.ends_with('\n') && generated.lines().count() >= 2 {
+                break;
+            }
         }
 
-        let total_weight: f32 = filtered.iter().map(|(_, weight)| *weight).sum();
-        if total_weight <= 0.0 {
-            return filtered[0].0;
+        unsafe {
+            llama_batch_free(decode_batch);
         }
 
-        let mut rng = rand::rng();
-        let mut sample = rng.random::<f32>() * total_weight;
-        for (token, weight) in &filtered {
-            sample -= *weight;
-            if sample <= 0.0 {
-                return *token;
-            }
+        if let Some(err) = decode_error {
+            return Err(err);
         }
 
-        filtered
-            .last()
-            .map(|(token, _)| *token)
-            .unwrap_or(eos_token)
+        Ok(generated)
     }
 
-    unsafe fn token_to_string(&self, token: llama_token) -> String {
-        let mut size = TOKEN_PIECE_INITIAL;
-        let mut buffer: Vec<i8> = Vec::new();
+    fn prime_prefix(&mut self, prefix: &str) -> Result<(), LlamaError> {
+        let tokens = self.tokenize(prefix)?;
+        if tokens.is_empty() || self.cached.starts_with(&tokens) {
+            return Ok(());
+        }
 
-        loop {
-            if size > TOKEN_PIECE_MAX {
-                return String::new();
+        let state_file = self
+            .state_cache
+            .as_ref()
+            .map(|(dir, model)| dir.join(state_file_name(*model, &tokens, self.n_ctx)));
+        if let Some(path) = &state_file {
+            if self.restore_state(path, &tokens) {
+                return Ok(());
             }
+        }
 
-            buffer.resize(size, 0);
-            let written =
-                llama_token_to_piece(self.vocab, token, buffer.as_mut_ptr(), size as i32, 0, true);
+        self.clear_memory();
+        unsafe {
+            self.decode_sequence(&tokens, 0)?;
+        }
+        self.cached = tokens;
 
-            if written < 0 {
-                size = size.saturating_mul(2);
-                continue;
+        if let Some(path) = &state_file {
+            if let Err(err) = self.save_state(path) {
+                eprintln!("[git-ca] warning: could not save prompt state: {err}");
             }
+        }
+        Ok(())
+    }
 
-            let written = written as usize;
-            if written >= size {
-                size = size.saturating_mul(2);
-                continue;
-            }
+    fn set_abort_signal(&mut self, signal: AbortSignal) {
+        *self.abort = signal;
+    }
 
-            let bytes: Vec<u8> = buffer[..written].iter().map(|b| *b as u8).collect();
-            return String::from_utf8(bytes).unwrap_or_default();
-        }
+    fn abort_reason(&self) -> Option<AbortReason> {
+        self.abort.reason()
     }
 }
 
@@ -430,3 +1369,207 @@ impl Drop for LlamaSession {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_chain_penalises_truncates_then_draws() {
+        use SamplerStage::*;
+        assert_eq!(
+            sampler_stages(&SamplingOptions::default()),
+            vec![
+                Penalties,
+                TopK(40),
+                TopP(0.9),
+                Temperature(0.8),
+                Distribution
+            ]
+        );
+
+        let plain = SamplingOptions {
+            repeat_penalty: 1.0,
+            top_k: 0,
+            top_p: 1.0,
+            typical_p: 0.95,
+            min_p: 0.05,
+            ..SamplingOptions::default()
+        };
+        assert_eq!(
+            sampler_stages(&plain),
+            vec![Typical(0.95), MinP(0.05), Temperature(0.8), Distribution]
+        );
+    }
+
+    #[test]
+    fn mirostat_replaces_truncation_samplers() {
+        let options = SamplingOptions {
+            mirostat: Mirostat::V2,
+            dry_multiplier: 0.8,
+            grammar: Some("root ::= \"feat\"".to_string()),
+            temperature: 0.5,
+            ..SamplingOptions::default()
+        };
+        assert_eq!(
+            sampler_stages(&options),
+            vec![
+                SamplerStage::Grammar,
+                SamplerStage::Penalties,
+                SamplerStage::Dry,
+                SamplerStage::Temperature(0.5),
+                SamplerStage::Mirostat(Mirostat::V2)
+            ]
+        );
+        assert_eq!(Mirostat::parse("v1"), Some(Mirostat::V1));
+        assert_eq!(Mirostat::parse("3"), None);
+    }
+
+    /// Builds a chain that has already seen `history` and runs it over
+    /// `logits`, returning the candidates as `(id, logit)` in id order.
+    fn apply_chain(
+        options: &SamplingOptions,
+        history: &[llama_token],
+        logits: &[f32],
+    ) -> Vec<(llama_token, f32)> {
+        unsafe {
+            let chain = build_sampler_chain(options, ptr::null(), ptr::null()).unwrap();
+            for &token in history {
+                llama_sampler_accept(chain.0, token);
+            }
+            let mut candidates: Vec<llama_token_data> = logits
+                .iter()
+                .enumerate()
+                .map(|(id, &logit)| llama_token_data {
+                    id: id as llama_token,
+                    logit,
+                    p: 0.0,
+                })
+                .collect();
+            let mut array = llama_token_data_array {
+                data: candidates.as_mut_ptr(),
+                size: candidates.len(),
+                selected: -1,
+                sorted: false,
+            };
+            llama_sampler_apply(chain.0, &mut array);
+            let mut applied: Vec<(llama_token, f32)> = (0..array.size)
+                .map(|index| {
+                    let candidate = *array.data.add(index);
+                    (candidate.id, candidate.logit)
+                })
+                .collect();
+            applied.sort_by_key(|&(id, _)| id);
+            applied
+        }
+    }
+
+    /// Samples one token from `logits` with a chain that has seen `history`.
+    fn pick(
+        options: &SamplingOptions,
+        history: &[llama_token],
+        logits: &[f32],
+        eos_token: llama_token,
+        allow_eos: bool,
+    ) -> llama_token {
+        unsafe {
+            let chain = build_sampler_chain(options, ptr::null(), ptr::null()).unwrap();
+            for &token in history {
+                llama_sampler_accept(chain.0, token);
+            }
+            sample_from_logits(&chain, logits, eos_token, allow_eos)
+        }
+    }
+
+    fn greedy() -> SamplingOptions {
+        SamplingOptions {
+            top_k: 1,
+            repeat_penalty: 1.0,
+            ..SamplingOptions::default()
+        }
+    }
+
+    #[test]
+    fn penalties_follow_llama_cpp_semantics() {
+        let options = SamplingOptions {
+            repeat_penalty: 2.0,
+            frequency_penalty: 0.5,
+            presence_penalty: 0.25,
+            top_k: 0,
+            top_p: 1.0,
+            temperature: 1.0,
+            ..SamplingOptions::default()
+        };
+        let applied = apply_chain(&options, &[0, 1, 0, 7], &[4.0, -1.0, 3.0]);
+        assert_eq!(applied, vec![(0, 0.75), (1, -2.75), (2, 3.0)]);
+    }
+
+    #[test]
+    fn penalties_steer_sampling_away_from_repeats() {
+        let logits = [5.0, 4.8, 1.0, 0.0];
+        assert_eq!(pick(&greedy(), &[0, 0], &logits, 3, true), 0);
+
+        let repeat = SamplingOptions {
+            repeat_penalty: 1.1,
+            ..greedy()
+        };
+        assert_eq!(pick(&repeat, &[0], &logits, 3, true), 1);
+
+        let frequency = SamplingOptions {
+            frequency_penalty: 0.15,
+            ..greedy()
+        };
+        assert_eq!(pick(&frequency, &[0], &logits, 3, true), 0);
+        assert_eq!(pick(&frequency, &[0, 0], &logits, 3, true), 1);
+
+        let presence = SamplingOptions {
+            presence_penalty: 0.5,
+            ..greedy()
+        };
+        assert_eq!(pick(&presence, &[0, 1], &logits, 3, true), 0);
+        assert_eq!(pick(&presence, &[0], &logits, 3, true), 1);
+    }
+
+    #[test]
+    fn eos_is_skipped_until_allowed() {
+        let logits = [1.0, 0.5, 9.0];
+        assert_eq!(pick(&greedy(), &[], &logits, 2, false), 0);
+        assert_eq!(pick(&greedy(), &[], &logits, 2, true), 2);
+    }
+
+    #[test]
+    fn reuses_the_common_prefix_up_to_the_last_prompt_token() {
+        assert_eq!(reusable_prefix(&[1, 2, 3, 4], &[1, 2, 9, 4, 5]), 2);
+        assert_eq!(reusable_prefix(&[1, 2], &[1, 2, 3, 4]), 2);
+        assert_eq!(reusable_prefix(&[7, 2, 3], &[1, 2, 3]), 0);
+        assert_eq!(reusable_prefix(&[], &[1, 2, 3]), 0);
+        // A repeated prompt keeps all but its last token.
+        assert_eq!(reusable_prefix(&[1, 2, 3], &[1, 2, 3]), 2);
+        assert_eq!(reusable_prefix(&[1, 2, 3, 4], &[1, 2, 3]), 2);
+        assert_eq!(reusable_prefix(&[1], &[1]), 0);
+    }
+
+    #[test]
+    fn finds_the_earliest_stop_string() {
+        let stop = vec![
+            "\n\n".to_string(),
+            "Explanation:".to_string(),
+            String::new(),
+        ];
+        assert_eq!(find_stop("fix: a\nExplanation: b\n\n", &stop), Some(7));
+        assert_eq!(find_stop("fix: a\n\nExplanation:", &stop), Some(6));
+        assert_eq!(find_stop("fix: a", &stop), None);
+        assert_eq!(find_stop("fix: a", &[]), None);
+    }
+
+    #[test]
+    fn state_file_names_track_model_prefix_and_context() {
+        let name = state_file_name(0xabc, &[1, 2, 3], 1024);
+        assert!(name.starts_with("0000000000000abc-"));
+        assert!(name.ends_with(".state"));
+        assert_eq!(name, state_file_name(0xabc, &[1, 2, 3], 1024));
+        assert_ne!(name, state_file_name(0xabd, &[1, 2, 3], 1024));
+        assert_ne!(name, state_file_name(0xabc, &[1, 2, 4], 1024));
+        assert_ne!(name, state_file_name(0xabc, &[1, 2, 3], 2048));
+    }
+}
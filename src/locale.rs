@@ -0,0 +1,570 @@
+use crate::home_dir;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Catalogs compiled into the binary, in the order `git ca language` lists
+/// them. English comes first and fills in messages other catalogs lack.
+const EMBEDDED: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("zh", include_str!("../locales/zh.json")),
+    ("es", include_str!("../locales/es.json")),
+    ("fr", include_str!("../locales/fr.json")),
+    ("ja", include_str!("../locales/ja.json")),
+    ("de", include_str!("../locales/de.json")),
+];
+
+/// The messages of one locale, read from `locales/<code>.json`.
+#[derive(Debug)]
+struct Catalog {
+    code: String,
+    name: String,
+    /// Other names `commit-analyzer.language` may use, e.g. `english`.
+    aliases: Vec<String>,
+    /// Whether commit subjects must be plain ASCII, so a model answering in
+    /// the wrong language is caught.
+    ascii_subject: bool,
+    /// Whether commit subjects start in lower case, so `git ca lint` checks
+    /// their case by default. Off for German nouns and for scripts without
+    /// case.
+    lowercase_subject: bool,
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    fn parse(code: &str, text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        Self::from_value(code, &value)
+    }
+
+    fn from_value(code: &str, value: &Value) -> Result<Self, String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| "expected a JSON object".to_string())?;
+        let strings = |key: &str| -> Vec<String> {
+            object
+                .get(key)
+                .and_then(Value::as_array)
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item.as_str().map(str::to_lowercase))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let mut messages = HashMap::new();
+        if let Some(entries) = object.get("messages").and_then(Value::as_object) {
+            for (key, value) in entries {
+                let text = value
+                    .as_str()
+                    .ok_or_else(|| format!("message '{key}' is not a string"))?;
+                messages.insert(key.clone(), text.to_string());
+            }
+        }
+
+        Ok(Self {
+            code: code.to_lowercase(),
+            name: object
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or(code)
+                .to_string(),
+            aliases: strings("aliases"),
+            ascii_subject: object
+                .get("ascii_subject")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            lowercase_subject: object
+                .get("lowercase_subject")
+                .and_then(Value::as_bool)
+                .unwrap_or(true),
+            messages,
+        })
+    }
+
+    /// Applies an override read from disk: its messages replace ours, and
+    /// its name and aliases are used when it sets them.
+    fn merge(&mut self, other: Catalog, value: &Value) {
+        if value.get("name").is_some() {
+            self.name = other.name;
+        }
+        if value.get("ascii_subject").is_some() {
+            self.ascii_subject = other.ascii_subject;
+        }
+        if value.get("lowercase_subject").is_some() {
+            self.lowercase_subject = other.lowercase_subject;
+        }
+        self.aliases.extend(other.aliases);
+        self.messages.extend(other.messages);
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.trim().to_lowercase();
+        self.code == name || self.aliases.contains(&name)
+    }
+}
+
+/// Where catalogs that override or add to the embedded ones are read from.
+/// Tests only see the embedded catalogs, whatever is installed locally.
+fn override_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    home_dir().map(|home| home.join(".cache/git-ca/locales"))
+}
+
+fn catalogs() -> &'static [Catalog] {
+    static CATALOGS: OnceLock<Vec<Catalog>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        let mut catalogs: Vec<Catalog> = EMBEDDED
+            .iter()
+            .map(|(code, text)| Catalog::parse(code, text).expect("embedded catalog"))
+            .collect();
+        if let Some(dir) = override_dir() {
+            load_overrides(&mut catalogs, &dir);
+        }
+        catalogs
+    })
+}
+
+/// Reads every `<code>.json` in `dir`. A file for a known locale replaces
+/// single messages; any other code adds a locale. Broken files are skipped
+/// with a warning.
+fn load_overrides(catalogs: &mut Vec<Catalog>, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let Some(code) = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        let parsed = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| serde_json::from_str::<Value>(&text).map_err(|err| err.to_string()))
+            .and_then(|value| Ok((Catalog::from_value(&code, &value)?, value)));
+        let (catalog, value) = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                eprintln!(
+                    "[git-ca] warning: ignoring locale file {}: {err}",
+                    path.display()
+                );
+                continue;
+            }
+        };
+        match catalogs
+            .iter_mut()
+            .find(|existing| existing.code == catalog.code)
+        {
+            Some(existing) => existing.merge(catalog, &value),
+            None => catalogs.push(catalog),
+        }
+    }
+}
+
+/// The language for interface text, prompts and fallback messages, backed
+/// by one message catalog.
+#[derive(Clone)]
+pub struct Language {
+    catalog: &'static Catalog,
+}
+
+impl Language {
+    pub fn english() -> Self {
+        Self {
+            catalog: &catalogs()[0],
+        }
+    }
+
+    /// Every available language, embedded ones first.
+    pub fn all() -> impl Iterator<Item = Language> {
+        catalogs().iter().map(|catalog| Language { catalog })
+    }
+
+    /// Looks a language up by code (`en`) or alias (`english`).
+    pub fn from_str(s: &str) -> Option<Self> {
+        catalogs()
+            .iter()
+            .find(|catalog| catalog.matches(s))
+            .map(|catalog| Language { catalog })
+    }
+
+    /// The language a POSIX locale such as `zh_CN.UTF-8` names, if there is
+    /// a catalog for it. `C` and `POSIX` name none.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let tag = locale.split(['.', '@']).next()?.trim();
+        if tag.is_empty() || tag == "C" || tag == "POSIX" {
+            return None;
+        }
+        Self::from_str(tag).or_else(|| Self::from_str(tag.split(['_', '-']).next()?))
+    }
+
+    /// The language of the user's locale: the first of `LC_ALL`,
+    /// `LC_MESSAGES` and `LANG` that is set, as the C library picks it.
+    pub fn from_environment() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+            .and_then(|locale| Self::from_locale(&locale))
+    }
+
+    /// The code stored in `commit-analyzer.language`.
+    pub fn to_string(&self) -> &'static str {
+        &self.catalog.code
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        &self.catalog.name
+    }
+
+    pub fn ascii_subject(&self) -> bool {
+        self.catalog.ascii_subject
+    }
+
+    pub fn lowercase_subject(&self) -> bool {
+        self.catalog.lowercase_subject
+    }
+
+    /// The message `key`, taken from English when this catalog lacks it.
+    fn message(&self, key: &str) -> &'static str {
+        self.catalog
+            .messages
+            .get(key)
+            .or_else(|| catalogs()[0].messages.get(key))
+            .map_or("", String::as_str)
+    }
+}
+
+impl PartialEq for Language {
+    fn eq(&self, other: &Self) -> bool {
+        self.catalog.code == other.catalog.code
+    }
+}
+
+impl fmt::Debug for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Language({})", self.catalog.code)
+    }
+}
+
+/// Declares an accessor per catalog key, so call sites stay checked by the
+/// compiler while the text lives in the catalogs.
+macro_rules! messages {
+    ($($(#[$attr:meta])* $name:ident,)*) => {
+        impl Language {
+            $(
+                $(#[$attr])*
+                pub fn $name(&self) -> &'static str {
+                    self.message(stringify!($name))
+                }
+            )*
+        }
+
+        #[cfg(test)]
+        const MESSAGE_KEYS: &[&str] = &[$(stringify!($name)),*];
+    };
+}
+
+messages! {
+    generating_commit_message,
+    this_may_take_moment,
+    processing_response,
+    commit_message_generated,
+    available_languages,
+    select_language_prompt,
+    invalid_selection,
+    language_set_to,
+    message_language_set_to,
+    language_usage,
+    lint_usage,
+    lint_error_label,
+    lint_warning_label,
+    lint_summary,
+    fetching_models,
+    available_models,
+    select_model_prompt,
+    model_set_as_default,
+    no_default_model,
+    no_changes_staged,
+    use_edit_cancel_prompt,
+    enter_commit_message,
+    commit_cancelled,
+    invalid_choice,
+    enter_name_prompt,
+    enter_email_prompt,
+    changes_committed,
+    commit_message_label,
+    model_retrying_invalid_output,
+    model_failed_generate,
+    fallback_commit_generated,
+    truncated_diff_notice,
+    prompt_trimmed_notice,
+    changed_files_heading,
+    file_omitted_notice,
+    change_added,
+    change_deleted,
+    change_renamed_from,
+    change_copied_from,
+    change_mode,
+    change_binary,
+    change_submodule,
+    file_snippet_heading,
+    truncated_body_notice,
+    summarizing_chunk,
+    summary_call_cap_reached,
+    change_notes_heading,
+    split_single_scope,
+    split_grouping,
+    split_grouping_fallback,
+    split_generating,
+    split_plan_heading,
+    split_commit_heading,
+    split_confirm_prompt,
+    split_edit_message_prompt,
+    split_non_interactive,
+    split_commits_created,
+    waiting_for_first_token,
+    generation_stats,
+    generation_cancelled,
+    generation_timed_out,
+    using_daemon,
+    daemon_starting,
+    daemon_start_failed,
+    daemon_already_running,
+    daemon_running,
+    daemon_idle_remaining,
+    daemon_not_running,
+    daemon_stopped,
+    daemon_usage,
+    #[cfg(not(unix))]
+    daemon_unsupported,
+    no_models_found,
+    enter_model_path_hint,
+    model_file_missing,
+    model_extension_warning,
+    download_model_prompt,
+    downloading_model,
+    download_completed,
+    auto_downloading_default,
+    model_pull_hint,
+    model_use_usage,
+    confirm_default_download,
+    default_download_declined,
+    model_add_hint,
+    offline_download_refused,
+    model_add_usage,
+    model_add_none_found,
+    model_add_unusable,
+    model_add_missing_parts,
+    model_add_conflict,
+    model_added,
+    model_already_added,
+    model_pull_usage,
+    pull_ambiguous,
+    pull_no_match,
+    model_usage,
+    model_list_heading,
+    model_unreadable,
+    model_no_match,
+    model_ambiguous,
+    model_verify_failed,
+    model_info_unreadable,
+    model_info_unknown,
+    model_info_path,
+    model_info_size,
+    model_info_name,
+    model_info_architecture,
+    model_info_quantization,
+    model_info_file_type,
+    model_info_parameters,
+    model_info_size_label,
+    model_info_context_length,
+    model_info_tensors,
+    model_info_gguf_version,
+    model_info_split,
+    model_info_default_yes,
+    model_info_default_no,
+    model_verify_unreadable,
+    model_verify_ok,
+    model_verify_mismatch,
+    model_verify_no_checksum,
+    doctor_running,
+    doctor_using_model,
+    doctor_section_model,
+    doctor_section_settings,
+    doctor_section_performance,
+    doctor_trained_context,
+    doctor_threads,
+    doctor_batch_size,
+    doctor_gpu_layers,
+    doctor_memory_mapping,
+    doctor_flash_attention,
+    doctor_kv_cache_type,
+    doctor_kv_cache_other,
+    doctor_load_time,
+    doctor_prompt_rate,
+    doctor_generation_rate,
+    doctor_thread_rate,
+    doctor_model_weights,
+    doctor_peak_memory,
+    doctor_rate,
+    doctor_rate_unavailable,
+    doctor_commit_format,
+    doctor_probe_ok,
+    doctor_probe_failed,
+    doctor_recommendations,
+    doctor_no_recommendations,
+    doctor_recommend_threads,
+    doctor_recommend_trained_context,
+    doctor_recommend_larger_context,
+    doctor_recommend_better_model,
+    doctor_recommend_gpu_layers,
+    doctor_recommend_smaller_model,
+    model_remove_unmanaged,
+    model_confirm_remove,
+    model_removed,
+    model_default_cleared,
+    not_in_git_repository,
+    home_dir_unknown,
+    non_interactive_commit,
+    download_resumed,
+    // Commit prompt with a `{diff}` placeholder, and the line appended when
+    // the first answer was unusable.
+    commit_prompt,
+    commit_prompt_retry,
+    // Per-file summary prompt with a `{changes}` placeholder.
+    note_prompt,
+    // Split grouping prompt with `{changes}` and `{groups}` placeholders.
+    split_grouping_prompt,
+    // Fallback subjects; `{scope}` is the readable scope.
+    subject_bump_submodule,
+    subject_bump_submodules,
+    subject_rename,
+    subject_remove,
+    subject_permissions,
+    subject_assets,
+    subject_deps,
+    subject_sync_docs,
+    subject_docs,
+    subject_tests,
+    subject_introduce,
+    subject_fix,
+    subject_refine,
+    subject_update,
+    subject_split_fallback,
+    scope_project,
+    scope_pair,
+    scope_more,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn embedded_catalogs_are_complete() {
+        let keys: BTreeSet<&str> = MESSAGE_KEYS.iter().copied().collect();
+        for (code, text) in EMBEDDED {
+            let catalog = Catalog::parse(code, text).expect("catalog parses");
+            let found: BTreeSet<&str> = catalog.messages.keys().map(String::as_str).collect();
+            assert_eq!(found, keys, "keys of {code}.json");
+
+            let english = Catalog::parse("en", EMBEDDED[0].1).expect("english");
+            for (key, text) in &english.messages {
+                let placeholders = |text: &str| {
+                    text.match_indices('{')
+                        .filter_map(|(start, _)| {
+                            let end = text[start..].find('}')?;
+                            Some(text[start..start + end + 1].to_string())
+                        })
+                        .collect::<BTreeSet<_>>()
+                };
+                assert_eq!(
+                    placeholders(&catalog.messages[key]),
+                    placeholders(text),
+                    "placeholders of {key} in {code}.json"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn overrides_replace_messages_and_add_locales() {
+        let dir = std::env::temp_dir().join(format!("git-ca-locales-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("temp dir");
+        fs::write(
+            dir.join("en.json"),
+            r#"{"messages": {"commit_cancelled": "Nothing committed."}}"#,
+        )
+        .expect("override");
+        fs::write(
+            dir.join("pt.json"),
+            r#"{"name": "Português", "aliases": ["Portuguese"], "messages": {"commit_cancelled": "Commit cancelado."}}"#,
+        )
+        .expect("new locale");
+        fs::write(dir.join("it.json"), "{ not json").expect("broken locale");
+
+        let mut catalogs = vec![Catalog::parse("en", EMBEDDED[0].1).expect("english")];
+        load_overrides(&mut catalogs, &dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(catalogs.len(), 2);
+        assert_eq!(
+            catalogs[0].messages["commit_cancelled"],
+            "Nothing committed."
+        );
+        assert_eq!(catalogs[0].name, "English");
+        assert!(catalogs[0].ascii_subject);
+        assert!(catalogs[1].matches("portuguese"));
+        assert_eq!(catalogs[1].name, "Português");
+    }
+
+    #[test]
+    fn maps_posix_locales_to_languages() {
+        let code = |locale: &str| Language::from_locale(locale).map(|l| l.to_string());
+        assert_eq!(code("zh_CN.UTF-8"), Some("zh"));
+        assert_eq!(code("de_DE@euro"), Some("de"));
+        assert_eq!(code("ja"), Some("ja"));
+        assert_eq!(code("fr-CA"), Some("fr"));
+        assert_eq!(code("C.UTF-8"), None);
+        assert_eq!(code("POSIX"), None);
+        assert_eq!(code("sv_SE.UTF-8"), None);
+    }
+
+    #[test]
+    fn finds_languages_by_code_or_alias() {
+        assert_eq!(Language::from_str("EN"), Some(Language::english()));
+        assert_eq!(
+            Language::from_str("中文").map(|l| l.to_string()),
+            Some("zh")
+        );
+        assert_eq!(
+            Language::from_str("Deutsch").map(|l| l.to_string()),
+            Some("de")
+        );
+        assert_eq!(
+            Language::from_str("german").map(|l| l.to_string()),
+            Some("de")
+        );
+        assert!(Language::from_str("klingon").is_none());
+        assert_eq!(
+            Language::from_str("ja").map(|l| l.commit_cancelled()),
+            Some("コミットをキャンセルしました。")
+        );
+    }
+}
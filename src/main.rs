@@ -1,361 +1,154 @@
+mod cancel;
+#[cfg(unix)]
+mod daemon;
+mod diff;
+mod doctor;
+mod fallback;
+mod gguf;
+mod hub;
+mod lint;
 mod llama;
-
-use crate::llama::LlamaSession;
-use git2::{Commit, Config, ErrorCode, Repository, Signature};
-use hf_hub::api::sync::Api;
+mod locale;
+mod models;
+mod options;
+mod progress;
+mod split;
+mod summarize;
+
+use crate::cancel::{AbortReason, AbortSignal, InterruptGuard};
+use crate::diff::{staged_diff, ChangeKind, FileDiff, StagedDiff};
+use crate::doctor::run_doctor;
+use crate::fallback::{generate_fallback_commit_message, FallbackRules};
+use crate::gguf::split_shard;
+use crate::hub::{HubSettings, PullSpec};
+use crate::lint::run_lint;
+use crate::llama::{Generator, LlamaError, LlamaSession, SessionOptions};
+use crate::locale::Language;
+use crate::options::{session_options, LlamaFlags};
+use crate::progress::GenerationProgress;
+use crate::split::run_split;
+use crate::summarize::summarize_changes;
+use git2::{Commit, Config, ConfigLevel, ErrorCode, Repository, Signature};
 use std::collections::HashSet;
 use std::env;
 use std::fmt;
 use std::fs;
 use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 const CONFIG_LANGUAGE_KEY: &str = "commit-analyzer.language";
+const CONFIG_UI_LANGUAGE_KEY: &str = "commit-analyzer.uiLanguage";
+const CONFIG_TIMEOUT_KEY: &str = "commit-analyzer.timeout";
 const COMMIT_TYPES: &[&str] = &["feat", "fix", "docs", "style", "refactor", "test", "chore"];
 const DEFAULT_MODEL_REPO: &str = "unsloth/gemma-3-270m-it-GGUF";
 const DEFAULT_CONTEXT_SIZE: i32 = 1024;
-
-#[derive(Debug, Clone, PartialEq)]
-enum Language {
-    English,
-    Chinese,
-}
-
-impl Language {
-    fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "en" | "english" => Some(Language::English),
-            "zh" | "chinese" | "中文" => Some(Language::Chinese),
-            _ => None,
-        }
-    }
-
-    fn to_string(&self) -> &'static str {
-        match self {
-            Language::English => "en",
-            Language::Chinese => "zh",
-        }
-    }
-
-    fn display_name(&self) -> &'static str {
-        match self {
-            Language::English => "English",
-            Language::Chinese => "简体中文",
-        }
-    }
-
-    fn generating_commit_message(&self) -> &'static str {
-        match self {
-            Language::English => "Generating commit message...",
-            Language::Chinese => "正在生成提交信息...",
-        }
-    }
-
-    fn this_may_take_moment(&self) -> &'static str {
-        match self {
-            Language::English => "This may take a moment depending on your model and system...",
-            Language::Chinese => "这可能需要一些时间，取决于您的模型和系统配置...",
-        }
-    }
-
-    fn processing_response(&self) -> &'static str {
-        match self {
-            Language::English => "Processing response...",
-            Language::Chinese => "正在处理响应...",
-        }
-    }
-
-    fn commit_message_generated(&self) -> &'static str {
-        match self {
-            Language::English => "\n\nCommit message generated.",
-            Language::Chinese => "\n\n提交信息已生成。",
-        }
-    }
-
-    fn available_languages(&self) -> &'static str {
-        match self {
-            Language::English => "Available languages:",
-            Language::Chinese => "可选语言：",
-        }
-    }
-
-    fn select_language_prompt(&self) -> &'static str {
-        match self {
-            Language::English => "\nSelect a language by number: ",
-            Language::Chinese => "\n请输入语言编号：",
-        }
-    }
-
-    fn invalid_selection(&self) -> &'static str {
-        match self {
-            Language::English => "Invalid selection. Please try again.",
-            Language::Chinese => "无效选择，请重试。",
-        }
-    }
-
-    fn language_set_to(&self) -> &'static str {
-        match self {
-            Language::English => "Language set to: {}",
-            Language::Chinese => "语言已设置为：{}",
-        }
-    }
-
-    fn fetching_models(&self) -> &'static str {
-        match self {
-            Language::English => "Searching for local GGUF models...",
-            Language::Chinese => "正在搜索本地 GGUF 模型...",
-        }
-    }
-
-    fn available_models(&self) -> &'static str {
-        match self {
-            Language::English => "\nDetected GGUF models:",
-            Language::Chinese => "\n检测到的 GGUF 模型：",
-        }
-    }
-
-    fn select_model_prompt(&self) -> &'static str {
-        match self {
-            Language::English => "\nEnter a model number or provide a full GGUF path: ",
-            Language::Chinese => "\n输入模型编号或直接提供 GGUF 文件路径：",
-        }
-    }
-
-    fn model_set_as_default(&self) -> &'static str {
-        match self {
-            Language::English => "Model path ready: {}",
-            Language::Chinese => "模型路径已就绪：{}",
-        }
-    }
-
-    fn no_default_model(&self) -> &'static str {
-        match self {
-            Language::English => "No model path available. Please select a GGUF file.",
-            Language::Chinese => "当前没有可用的模型路径，请选择一个 GGUF 文件。",
-        }
-    }
-
-    fn no_changes_staged(&self) -> &'static str {
-        match self {
-            Language::English => "No changes staged for commit.",
-            Language::Chinese => "没有暂存的更改可提交。",
-        }
-    }
-
-    fn use_edit_cancel_prompt(&self) -> &'static str {
-        match self {
-            Language::English => {
-                "\nDo you want to (u)se this message, (e)dit it, or (c)ancel? [u/e/c]: "
-            }
-            Language::Chinese => "\n您想要 (u) 使用此信息，(e) 编辑它，还是 (c) 取消？[u/e/c]：",
-        }
-    }
-
-    fn enter_commit_message(&self) -> &'static str {
-        match self {
-            Language::English => "Enter your commit message (use multiple lines if needed, end with an empty line):\n",
-            Language::Chinese => "请输入您的提交信息（如需要可使用多行，以空行结束）：\n",
-        }
-    }
-
-    fn commit_cancelled(&self) -> &'static str {
-        match self {
-            Language::English => "Commit cancelled.",
-            Language::Chinese => "提交已取消。",
-        }
-    }
-
-    fn invalid_choice(&self) -> &'static str {
-        match self {
-            Language::English => "Invalid choice. Please try again.",
-            Language::Chinese => "无效选择，请重试。",
-        }
-    }
-
-    fn enter_name_prompt(&self) -> &'static str {
-        match self {
-            Language::English => "Enter your name: ",
-            Language::Chinese => "请输入您的姓名：",
-        }
-    }
-
-    fn enter_email_prompt(&self) -> &'static str {
-        match self {
-            Language::English => "Enter your email: ",
-            Language::Chinese => "请输入您的邮箱：",
-        }
-    }
-
-    fn changes_committed(&self) -> &'static str {
-        match self {
-            Language::English => "\nChanges committed successfully.",
-            Language::Chinese => "\n更改已成功提交。",
-        }
-    }
-
-    fn commit_message_label(&self) -> &'static str {
-        match self {
-            Language::English => "Commit message:\n{}",
-            Language::Chinese => "提交信息：\n{}",
-        }
-    }
-
-    fn model_retrying_invalid_output(&self) -> &'static str {
-        match self {
-            Language::English => {
-                "Model response was invalid. Retrying with stricter instructions..."
-            }
-            Language::Chinese => "模型输出无效，正在使用更严格的提示重试...",
-        }
-    }
-
-    fn model_failed_generate(&self) -> &'static str {
-        match self {
-            Language::English => {
-                "Model could not produce a valid commit message. Please enter one manually."
-            }
-            Language::Chinese => "模型未能生成有效的提交信息，请手动输入。",
-        }
-    }
-
-    fn fallback_commit_generated(&self) -> &'static str {
-        match self {
-            Language::English => "\n\nGenerated a fallback commit message.",
-            Language::Chinese => "\n\n已生成备用提交信息。",
-        }
-    }
-
-    fn truncated_diff_notice(&self) -> &'static str {
-        match self {
-            Language::English => "[Diff truncated to reduce context size.]",
-            Language::Chinese => "[为控制上下文长度，diff 已被截断。]",
-        }
-    }
-
-    fn changed_files_heading(&self) -> &'static str {
-        match self {
-            Language::English => "Changed files:",
-            Language::Chinese => "变更文件：",
-        }
-    }
-
-    fn file_omitted_notice(&self) -> &'static str {
-        match self {
-            Language::English => "(content omitted)",
-            Language::Chinese => "（内容已省略）",
-        }
-    }
-
-    fn file_snippet_heading(&self) -> &'static str {
-        match self {
-            Language::English => "File:",
-            Language::Chinese => "文件：",
-        }
-    }
-
-    fn truncated_body_notice(&self) -> &'static str {
-        match self {
-            Language::English => "[Additional hunks truncated]",
-            Language::Chinese => "[更多变更已截断]",
-        }
-    }
-
-    fn no_models_found(&self) -> &'static str {
-        match self {
-            Language::English => "No GGUF models found in default locations. Download a model first or provide its path manually.",
-            Language::Chinese => "在默认位置未找到 GGUF 模型。请先下载模型或手动提供其路径。",
-        }
-    }
-
-    fn enter_model_path_hint(&self) -> &'static str {
-        match self {
-            Language::English => "Hint: place models under ./models or ~/Library/Application Support/git-ca/models (macOS) or ~/.cache/git-ca/models.",
-            Language::Chinese => "提示：可将模型放在 ./models、~/Library/Application Support/git-ca/models（macOS）或 ~/.cache/git-ca/models 等目录。",
-        }
-    }
-
-    fn model_file_missing(&self) -> &'static str {
-        match self {
-            Language::English => "Model file missing: {}",
-            Language::Chinese => "模型文件缺失：{}",
-        }
-    }
-
-    fn model_extension_warning(&self) -> &'static str {
-        match self {
-            Language::English => "The file must have a .gguf extension.",
-            Language::Chinese => "文件必须为 .gguf 扩展名。",
-        }
-    }
-
-    fn download_model_prompt(&self) -> &'static str {
-        match self {
-            Language::English => "Download a GGUF model (for example from https://huggingface.co/collections/ggml-org/gguf) and retry.",
-            Language::Chinese => "请先下载 GGUF 模型（例如来自 https://huggingface.co/collections/ggml-org/gguf），然后重试。",
-        }
-    }
-
-    fn downloading_model(&self) -> &'static str {
-        match self {
-            Language::English => "Downloading model '{}' from Hugging Face...",
-            Language::Chinese => "正在从 Hugging Face 下载模型'{}'...",
-        }
-    }
-
-    fn download_completed(&self) -> &'static str {
-        match self {
-            Language::English => "Model downloaded to: {}",
-            Language::Chinese => "模型已下载至：{}",
-        }
-    }
-
-    fn auto_downloading_default(&self) -> &'static str {
-        match self {
-            Language::English => "No local models found. Downloading default model '{}'...",
-            Language::Chinese => "未找到本地模型，正在下载默认模型'{}'...",
-        }
-    }
-
-    fn model_pull_hint(&self) -> &'static str {
-        match self {
-            Language::English => {
-                "Tip: run 'git ca model pull <repo>' to download from Hugging Face."
-            }
-            Language::Chinese => {
-                "提示：运行 'git ca model pull <仓库>' 可从 Hugging Face 下载模型。"
-            }
-        }
-    }
-
-    fn model_pull_usage(&self) -> &'static str {
-        match self {
-            Language::English => "Usage: git ca model pull <repo>",
-            Language::Chinese => "用法：git ca model pull <仓库>",
-        }
-    }
-
-    fn not_in_git_repository(&self) -> &'static str {
-        match self {
-            Language::English => "Not in a git repository",
-            Language::Chinese => "不在 git 仓库中",
-        }
-    }
+const MAX_GENERATION_TOKENS: usize = 256;
+/// Wall-clock limit for one generation run, in seconds; `0` disables it.
+const DEFAULT_TIMEOUT_SECS: i64 = 120;
+/// Generous ceiling on characters per token, used to pre-trim diff text before
+/// it is measured exactly with the model tokenizer.
+const MAX_CHARS_PER_TOKEN: usize = 8;
+
+/// Set by `--yes`: answer yes to confirmations such as the first-run model
+/// download.
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
 }
 
+/// Everything that can end a run early. Each kind has a stable exit code
+/// (see `git ca --help`) so scripts can tell them apart.
 #[derive(Debug)]
 enum AppError {
     Git(git2::Error),
     Io(io::Error),
     InputClosed,
+    /// Invalid command-line arguments.
+    Usage(String),
+    NotInRepository(String),
+    /// Reported to the user already; only the exit code is left to set.
+    NoStagedChanges,
+    ModelNotFound(String),
+    Llama(LlamaError),
+    Download(String),
+    /// The user declined at a prompt; reported already.
+    Cancelled,
+    /// `git ca lint` found errors; reported already.
+    LintFailed,
     Custom(String),
 }
 
+impl AppError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Custom(_) | AppError::Git(_) | AppError::Io(_) => 1,
+            AppError::Usage(_) => 2,
+            AppError::NotInRepository(_) => 3,
+            AppError::NoStagedChanges => 4,
+            AppError::ModelNotFound(_) | AppError::Llama(LlamaError::ModelNotFound(_)) => 5,
+            AppError::Llama(LlamaError::Load(_) | LlamaError::InvalidOptions(_)) => 6,
+            AppError::Llama(
+                LlamaError::Inference(_) | LlamaError::PromptTooLong { .. } | LlamaError::State(_),
+            ) => 7,
+            AppError::Download(_) => 8,
+            AppError::InputClosed => 9,
+            AppError::Cancelled => 10,
+            AppError::LintFailed => 11,
+            AppError::Llama(LlamaError::Aborted(AbortReason::TimedOut)) => 124,
+            AppError::Llama(LlamaError::Aborted(AbortReason::Interrupted)) => 130,
+        }
+    }
+
+    /// Stable identifier for `--json` output.
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::Git(_) => "git",
+            AppError::Io(_) => "io",
+            AppError::InputClosed => "input_closed",
+            AppError::Usage(_) => "usage",
+            AppError::NotInRepository(_) => "not_in_repository",
+            AppError::NoStagedChanges => "no_staged_changes",
+            AppError::ModelNotFound(_) | AppError::Llama(LlamaError::ModelNotFound(_)) => {
+                "model_not_found"
+            }
+            AppError::Llama(LlamaError::Load(_) | LlamaError::InvalidOptions(_)) => "model_load",
+            AppError::Llama(
+                LlamaError::Inference(_) | LlamaError::PromptTooLong { .. } | LlamaError::State(_),
+            ) => "inference",
+            AppError::Llama(LlamaError::Aborted(AbortReason::TimedOut)) => "timed_out",
+            AppError::Llama(LlamaError::Aborted(AbortReason::Interrupted)) => "interrupted",
+            AppError::Download(_) => "download",
+            AppError::Cancelled => "cancelled",
+            AppError::LintFailed => "lint_failed",
+            AppError::Custom(_) => "error",
+        }
+    }
+
+    /// Whether the failure was already explained on stdout.
+    fn already_reported(&self) -> bool {
+        matches!(
+            self,
+            AppError::NoStagedChanges | AppError::Cancelled | AppError::LintFailed
+        )
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             AppError::Git(e) => write!(f, "Git error: {e}"),
             AppError::Io(e) => write!(f, "IO error: {e}"),
             AppError::InputClosed => write!(f, "Input stream closed"),
+            AppError::Usage(msg) => write!(f, "{msg}"),
+            AppError::NotInRepository(msg) => write!(f, "{msg}"),
+            AppError::NoStagedChanges => write!(f, "No changes staged for commit"),
+            AppError::ModelNotFound(msg) => write!(f, "{msg}"),
+            AppError::Llama(e) => write!(f, "{e}"),
+            AppError::Download(msg) => write!(f, "{msg}"),
+            AppError::Cancelled => write!(f, "Cancelled by user"),
+            AppError::LintFailed => write!(f, "Commit message does not pass lint"),
             AppError::Custom(msg) => write!(f, "{msg}"),
         }
     }
@@ -375,9 +168,15 @@ impl From<io::Error> for AppError {
     }
 }
 
+impl From<LlamaError> for AppError {
+    fn from(err: LlamaError) -> Self {
+        AppError::Llama(err)
+    }
+}
+
 impl From<hf_hub::api::sync::ApiError> for AppError {
     fn from(err: hf_hub::api::sync::ApiError) -> Self {
-        AppError::Custom(format!("Hugging Face API error: {err}"))
+        AppError::Download(format!("Hugging Face API error: {err}"))
     }
 }
 
@@ -411,152 +210,201 @@ fn find_git_repository(start_path: &Path) -> Option<PathBuf> {
     }
 }
 
-fn get_diff() -> Result<String> {
-    let output = Command::new("git").args(["diff", "--cached"]).output()?;
-    let diff = String::from_utf8(output.stdout)
-        .map_err(|e| AppError::Custom(format!("Invalid UTF-8 in diff: {e}")))?;
-    Ok(diff)
+/// The part of every commit prompt that comes before the diff.
+fn commit_prompt_prefix(language: &Language) -> String {
+    const MARKER: &str = "\u{1}";
+    let prompt = build_commit_prompt(MARKER, language, 0);
+    prompt.split(MARKER).next().unwrap_or_default().to_string()
 }
 
 fn build_commit_prompt(diff: &str, language: &Language, attempt: usize) -> String {
-    match language {
-        Language::English => {
-            let mut prompt = format!(
-                r#"SYSTEM: You are a commit message generator. You must output ONLY a commit message, nothing else.
-
-TASK: Analyze the git diff below and produce exactly ONE commit message in Git Flow format.
-
-FORMAT: <type>(<scope>): <subject>
-
-EXAMPLES:
-- feat(api): add user authentication endpoint
-- fix(cli): resolve model loading timeout
-- docs: update installation instructions
-- refactor(llama): simplify token sampling logic
-- chore(deps): update dependencies
-- test: add unit tests for diff parsing
-
-RULES:
-1. <type> MUST be one of: feat, fix, docs, style, refactor, test, chore
-2. <scope> is optional, use kebab-case when needed (e.g., cli, api, docs)
-3. <subject> is imperative, concise (<= 72 chars)
-4. NO explanations, NO markdown fences, NO extra text
-5. Output ONLY the commit message, nothing else
-
-HERE IS THE DIFF:
-{diff}
-
-YOUR OUTPUT (commit message only):"#
-            );
-
-            if attempt > 0 {
-                prompt.push_str(
-                    "\n\nCRITICAL: Previous output was invalid. You MUST output ONLY a commit message starting with '<type>(<scope>): <subject>'. NO other text, explanations, or formatting.",
-                );
-            }
-
-            prompt
-        }
-        Language::Chinese => {
-            let mut prompt = format!(
-                r#"系统：这是一个**任务指令**，不是对话。你的任务是直接生成提交信息，**不要回复或回应任何指令**。
-
-任务：分析以下 git diff，生成一个符合 Git Flow 格式的提交信息。
-
-**重要**：直接输出提交信息，**不要**说"好的"、"请使用..."、"我理解"等回复，直接生成即可。
-
-格式示例：
-feat(api): 添加用户认证接口
-fix(cli): 解决模型加载超时问题
-docs: 更新安装说明
-refactor(llama): 简化令牌采样逻辑
-chore(deps): 更新依赖包
-test: 添加 diff 解析单元测试
-style: 调整代码格式
-style(ui): 修改按钮颜色
-
-**必须遵循的规则**：
-1. <类型> 必须是以下之一：feat、fix、docs、style、refactor、test、chore
-2. <范围> 可选，使用 kebab-case（如 cli、api、docs、ui）
-3. <主题> 使用祈使语气，简练（≤72 字符）
-4. **绝对不要**输出任何解释、对话、回复或额外文字
-5. **首行**必须是：`<类型>(<范围>): <主题>`
-6. **不要**使用markdown、不添加代码块、不加符号
-
-以下是需要分析的 diff：
-
-{diff}
-
-**请直接生成提交信息（不要任何回复或解释）：**"#
-            );
-
-            if attempt > 0 {
-                prompt.push_str(
-                    "\n\n**严重错误**：上次输出不符合格式！**立即停止回复和对话**，**必须**直接输出一个以 '<类型>(<范围>): <主题>' 开头的提交信息。**不要**说'好的'、'理解了'、'请重新试'等任何回复文字。",
-                );
-            }
-
-            prompt
-        }
+    let mut prompt = language.commit_prompt().replace("{diff}", diff);
+    if attempt > 0 {
+        prompt.push_str(language.commit_prompt_retry());
     }
+    prompt
 }
 
 fn analyze_diff(
-    diff: &str,
+    diff: &StagedDiff,
     model_path: &Path,
     language: &Language,
-    context_size: i32,
+    message_language: &Language,
+    options: &SessionOptions,
+    timeout: Option<Duration>,
 ) -> Result<Option<String>> {
     println!("{}", language.generating_commit_message());
     eprintln!("\x1b[90m{}\x1b[0m", language.this_may_take_moment());
 
-    let mut session = LlamaSession::new(model_path, context_size).map_err(AppError::from)?;
+    let _interrupt = InterruptGuard::install();
+    let mut session = load_generator(model_path, options, language)?;
+    session.set_abort_signal(AbortSignal::new(timeout));
+    generate_commit_message(session.as_mut(), diff, language, message_language, false)
+}
+
+/// Uses a running `git ca daemon` that serves `model_path`, or loads the model
+/// into this process.
+fn load_generator(
+    model_path: &Path,
+    options: &SessionOptions,
+    language: &Language,
+) -> Result<Box<dyn Generator>> {
+    #[cfg(unix)]
+    if let Some(client) = daemon::DaemonClient::connect(model_path, options) {
+        eprintln!("\x1b[90m{}\x1b[0m", language.using_daemon());
+        return Ok(Box::new(client));
+    }
+    #[cfg(not(unix))]
+    let _ = language;
+
+    let mut session = LlamaSession::new(model_path, options).map_err(AppError::from)?;
+    if let Some(dir) = state_cache_dir() {
+        session = session.with_state_cache(dir);
+    }
+    Ok(Box::new(session))
+}
+
+/// Runs the prompt/validate loop for `diff` on an already loaded session.
+/// Progress is reported in `language`, or only warnings are printed when
+/// `quiet`; the message is written in `message_language`.
+fn generate_commit_message(
+    session: &mut dyn Generator,
+    diff: &StagedDiff,
+    language: &Language,
+    message_language: &Language,
+    quiet: bool,
+) -> Result<Option<String>> {
     const MAX_ATTEMPTS: usize = 2;
+    let say = |line: &str| {
+        if !quiet {
+            println!("{line}");
+        }
+    };
 
-    let diff_variants = build_diff_variants(diff, language, context_size);
+    if let Err(err) = session.prime_prefix(&commit_prompt_prefix(message_language)) {
+        if session.abort_reason().is_none() {
+            eprintln!("[git-ca] warning: {err}");
+        }
+    }
+
+    let budget = session.context_size().saturating_sub(MAX_GENERATION_TOKENS);
+    let max_chars = budget.saturating_mul(MAX_CHARS_PER_TOKEN);
+    // Diffs that cannot fit the context are condensed file by file first, so
+    // the final prompt sees every change instead of only the first hunks.
+    let needs_summary = diff.files.len() > 1
+        && (diff.text.len() > max_chars
+            || match session.count_tokens(&build_commit_prompt(&diff.text, message_language, 0)) {
+                Ok(tokens) => tokens > budget,
+                Err(err) => return Ok(prompt_budget_failed(err)),
+            });
+    let diff_variants = if needs_summary {
+        match summarize_changes(session, diff, language, message_language, budget) {
+            Ok(notes) => vec![(notes, KeepSide::Head)],
+            Err(_) if session.abort_reason().is_some() => {
+                say(abort_notice(session, language));
+                return Ok(None);
+            }
+            Err(err) => return Ok(prompt_budget_failed(err)),
+        }
+    } else {
+        build_diff_variants(diff, message_language, max_chars)
+    };
 
     for attempt in 0..MAX_ATTEMPTS {
-        let fragment = diff_variants
+        let (fragment, keep) = diff_variants
             .get(attempt)
             .or_else(|| diff_variants.last())
             .unwrap();
-        let prompt = build_commit_prompt(fragment, language, attempt);
-        let response = match session.infer(&prompt, 256) {
+        let fitted = fit_prompt_to_budget(
+            fragment,
+            *keep,
+            budget,
+            message_language.truncated_diff_notice(),
+            |diff| build_commit_prompt(diff, message_language, attempt),
+            |text| session.count_tokens(text),
+        );
+        let fitted = match fitted {
+            Ok(fitted) => fitted,
+            Err(err) => return Ok(prompt_budget_failed(err)),
+        };
+        if let Some(trim) = fitted.trim.as_ref().filter(|_| !quiet) {
+            eprintln!(
+                "\x1b[90m{}\x1b[0m",
+                language
+                    .prompt_trimmed_notice()
+                    .replace("{kept}", &trim.kept_lines.to_string())
+                    .replace("{total}", &trim.total_lines.to_string())
+                    .replace("{tokens}", &trim.prompt_tokens.to_string())
+                    .replace("{untrimmed}", &trim.untrimmed_tokens.to_string())
+            );
+        }
+        let prompt = fitted.prompt;
+        let response = if quiet {
+            session.infer(&prompt, MAX_GENERATION_TOKENS)
+        } else {
+            let mut progress = GenerationProgress::start(language);
+            let response = session.infer_streaming(&prompt, MAX_GENERATION_TOKENS, &mut |piece| {
+                progress.on_token(piece)
+            });
+            progress.finish(language);
+            response
+        };
+        let response = match response {
             Ok(output) => output,
+            Err(_) if session.abort_reason().is_some() => {
+                say(abort_notice(session, language));
+                return Ok(None);
+            }
             Err(err) => {
-                eprintln!("{err}");
+                if !quiet {
+                    eprintln!("{err}");
+                }
                 if attempt + 1 < MAX_ATTEMPTS {
-                    println!("{}", language.model_retrying_invalid_output());
+                    say(language.model_retrying_invalid_output());
                     continue;
                 } else {
-                    println!("{}", language.model_failed_generate());
+                    say(language.model_failed_generate());
                     return Ok(None);
                 }
             }
         };
 
-        println!("{}", language.processing_response());
+        say(language.processing_response());
 
         if let Some(processed) = process_model_response(&response) {
-            if is_valid_commit_message(&processed, language) {
-                println!("{processed}");
-                println!("{}", language.commit_message_generated());
+            if is_valid_commit_message(&processed, message_language) {
+                say(&processed);
+                say(language.commit_message_generated());
                 return Ok(Some(processed));
-            } else {
+            } else if !quiet {
                 debug_model_response("model output failed validation", &response);
             }
-        } else {
+        } else if !quiet {
             debug_model_response("model output did not contain a commit subject", &response);
         }
 
         if attempt + 1 < MAX_ATTEMPTS {
-            println!("{}", language.model_retrying_invalid_output());
+            say(language.model_retrying_invalid_output());
         }
     }
 
     Ok(None)
 }
 
+fn abort_notice(session: &dyn Generator, language: &Language) -> &'static str {
+    match session.abort_reason() {
+        Some(AbortReason::TimedOut) => language.generation_timed_out(),
+        _ => language.generation_cancelled(),
+    }
+}
+
+/// A prompt that cannot be tokenized or fitted into the context is not fatal:
+/// the caller falls back to the rule-based message.
+fn prompt_budget_failed(err: LlamaError) -> Option<String> {
+    eprintln!("[git-ca] warning: {err}");
+    None
+}
+
 fn process_model_response(response: &str) -> Option<String> {
     let response_without_thinking = if response.trim_start().starts_with("<think>") {
         response
@@ -680,197 +528,59 @@ fn looks_like_instruction(line: &str) -> bool {
     KEYWORDS.iter().any(|keyword| lower.contains(keyword))
 }
 
-#[derive(Default)]
-struct DiffSummary {
-    files: Vec<String>,
-    scope_candidates: Vec<String>,
-    has_docs: bool,
-    has_code: bool,
-    docs_only: bool,
-    has_main: bool,
-    has_llama: bool,
-    has_retry: bool,
-    has_kv_reset: bool,
-    new_files: HashSet<String>,
-    has_cargo_toml: bool,
-    has_cargo_lock: bool,
-    has_node_manifest: bool,
-    has_node_lock: bool,
-}
-
-impl DiffSummary {
-    fn has_docs_only(&self) -> bool {
-        self.has_docs && !self.has_code && self.docs_only
-    }
-}
-
-fn analyze_diff_summary(diff: &str) -> DiffSummary {
-    let mut summary = DiffSummary {
-        docs_only: true,
-        ..Default::default()
-    };
-
-    let mut seen_files = HashSet::new();
-    let mut current_file: Option<String> = None;
-
-    for line in diff.lines() {
-        if let Some(rest) = line.strip_prefix("diff --git ") {
-            let mut parts = rest.split_whitespace();
-            let _a = parts.next();
-            let b = parts.next();
-            if let Some(b) = b {
-                let path = b.strip_prefix("b/").unwrap_or(b).to_string();
-                current_file = Some(path.clone());
-
-                if seen_files.insert(path.clone()) {
-                    summary.files.push(path.clone());
-
-                    let scope = path_to_scope(&path);
-                    if !scope.is_empty() && !summary.scope_candidates.contains(&scope) {
-                        summary.scope_candidates.push(scope);
-                    }
-
-                    let ext = path.rsplit('.').next().unwrap_or("");
-                    let is_doc = matches!(ext, "md" | "rst" | "adoc" | "txt");
-                    if is_doc {
-                        summary.has_docs = true;
-                    } else {
-                        summary.docs_only = false;
-                    }
-                    if ext == "rs" {
-                        summary.has_code = true;
-                    }
-
-                    if path == "src/main.rs" {
-                        summary.has_main = true;
-                    }
-                    if path == "src/llama.rs" {
-                        summary.has_llama = true;
-                    }
-                    if path == "Cargo.toml" {
-                        summary.has_cargo_toml = true;
-                        summary.docs_only = false;
-                    }
-                    if path == "Cargo.lock" {
-                        summary.has_cargo_lock = true;
-                        summary.docs_only = false;
-                    }
-                    if path.ends_with("package.json") {
-                        summary.has_node_manifest = true;
-                        summary.docs_only = false;
-                    }
-                    if path.contains("pnpm-lock")
-                        || path.contains("package-lock")
-                        || path.contains("yarn.lock")
-                    {
-                        summary.has_node_lock = true;
-                        summary.docs_only = false;
-                    }
-                }
-            }
-        } else if line.starts_with("new file mode") {
-            if let Some(file) = current_file.clone() {
-                summary.new_files.insert(file);
-            }
-        } else if line.starts_with('+') {
-            let lower = line.to_ascii_lowercase();
-            if lower.contains("retry") || lower.contains("stricter instructions") {
-                summary.has_retry = true;
-            }
-            if lower.contains("kv_self_clear") || lower.contains("kv cache") {
-                summary.has_kv_reset = true;
-            }
-        }
-    }
-
-    summary
-}
-
-#[derive(Default)]
-struct FileSection {
-    path: String,
-    additions: usize,
-    deletions: usize,
+struct FileSection<'a> {
+    file: &'a FileDiff,
     snippet: Vec<String>,
     omitted: bool,
 }
 
-fn build_diff_summary(diff: &str, language: &Language, context_size: i32) -> String {
+fn build_diff_summary(diff: &StagedDiff, language: &Language, max_chars: usize) -> String {
     const SNIPPET_LINE_LIMIT: usize = 120;
     const PER_FILE_SNIPPET_LIMIT: usize = 1200;
 
-    let max_chars = (context_size as usize)
-        .saturating_mul(3)
-        .saturating_sub(512)
-        .max(2048);
-    let diff_truncated = diff.len() > max_chars;
-
-    let mut sections: Vec<FileSection> = Vec::new();
-    let mut current: Option<FileSection> = None;
-
-    for line in diff.lines() {
-        if let Some(path) = line
-            .strip_prefix("diff --git ")
-            .and_then(|rest| rest.split_whitespace().nth(1))
-            .map(|b_path| b_path.strip_prefix("b/").unwrap_or(b_path).to_string())
-        {
-            if let Some(section) = current.take() {
-                sections.push(section);
-            }
-            let mut section = FileSection {
-                path,
-                ..Default::default()
-            };
-            if is_generated_or_large_file(&section.path) {
-                section.omitted = true;
-            }
-            current = Some(section);
-            continue;
-        }
+    let diff_truncated = diff.text.len() > max_chars;
 
-        let Some(section) = current.as_mut() else {
-            continue;
-        };
+    let mut sections: Vec<FileSection> = Vec::with_capacity(diff.files.len());
 
-        if line.starts_with("+++") || line.starts_with("---") {
-            continue;
-        }
+    for file in &diff.files {
+        let mut section = FileSection {
+            file,
+            snippet: Vec::new(),
+            omitted: is_generated_or_large_file(&file.path),
+        };
 
-        if line.starts_with("@@") {
-            if !section.omitted && section.snippet.len() < SNIPPET_LINE_LIMIT {
-                section.snippet.push(line.to_string());
+        for line in &file.lines {
+            if section.omitted {
+                break;
             }
-            continue;
-        }
 
-        if line.starts_with('+') && !line.starts_with("+++") {
-            section.additions += 1;
-        } else if line.starts_with('-') && !line.starts_with("---") {
-            section.deletions += 1;
-        }
+            if line.starts_with("@@") {
+                if section.snippet.len() < SNIPPET_LINE_LIMIT {
+                    section.snippet.push(line.clone());
+                }
+                continue;
+            }
 
-        if section.omitted {
-            continue;
-        }
+            let snippet_chars: usize = section.snippet.iter().map(|l| l.len()).sum();
+            if section.snippet.len() >= SNIPPET_LINE_LIMIT
+                || snippet_chars >= PER_FILE_SNIPPET_LIMIT
+            {
+                section.omitted = true;
+                section.snippet.clear();
+                break;
+            }
 
-        let snippet_chars: usize = section.snippet.iter().map(|l| l.len()).sum();
-        if section.snippet.len() >= SNIPPET_LINE_LIMIT || snippet_chars >= PER_FILE_SNIPPET_LIMIT {
-            section.omitted = true;
-            section.snippet.clear();
-            continue;
+            section.snippet.push(line.clone());
         }
 
-        section.snippet.push(line.to_string());
-    }
-
-    if let Some(section) = current.take() {
         sections.push(section);
     }
 
     if sections.is_empty() {
         return diff
+            .text
             .chars()
-            .take(diff.len().min(max_chars))
+            .take(diff.text.len().min(max_chars))
             .collect::<String>();
     }
 
@@ -885,8 +595,10 @@ fn build_diff_summary(diff: &str, language: &Language, context_size: i32) -> Str
             String::new()
         };
         output.push_str(&format!(
-            "- {} (+{} / -{}){}\n",
-            section.path, section.additions, section.deletions, note
+            "- {} ({}){}\n",
+            section.file.path,
+            describe_file_change(section.file, language),
+            note
         ));
     }
 
@@ -906,7 +618,7 @@ fn build_diff_summary(diff: &str, language: &Language, context_size: i32) -> Str
 
         output.push_str(language.file_snippet_heading());
         output.push(' ');
-        output.push_str(&section.path);
+        output.push_str(&section.file.path);
         output.push('\n');
 
         for line in section.snippet {
@@ -932,275 +644,190 @@ fn build_diff_summary(diff: &str, language: &Language, context_size: i32) -> Str
     output
 }
 
-fn is_generated_or_large_file(path: &str) -> bool {
-    let lower = path.to_ascii_lowercase();
-    lower.contains("pnpm-lock")
-        || lower.contains("package-lock")
-        || lower.contains("yarn.lock")
-        || lower.contains("cargo.lock")
-        || lower.ends_with(".min.js")
-        || lower.ends_with(".min.css")
-}
-
-fn build_diff_raw_tail(diff: &str, language: &Language, context_size: i32) -> String {
-    let max_chars = (context_size as usize)
-        .saturating_mul(3)
-        .saturating_sub(512)
-        .max(2048);
-
-    if diff.len() <= max_chars {
-        return diff.to_string();
-    }
-
-    let mut chars: Vec<char> = diff.chars().collect();
-    if chars.len() > max_chars {
-        chars.drain(0..chars.len() - max_chars);
-    }
-
-    let mut trimmed: String = chars.into_iter().collect();
-    if let Some(pos) = trimmed.find("diff --git ") {
-        trimmed = trimmed[pos..].to_string();
-    }
-
-    format!("{}\n\n{}", language.truncated_diff_notice(), trimmed)
-}
-
-fn build_diff_variants(diff: &str, language: &Language, context_size: i32) -> Vec<String> {
-    let summary = build_diff_summary(diff, language, context_size);
-    let raw = build_diff_raw_tail(diff, language, context_size);
-    if summary.trim() == raw.trim() {
-        vec![summary]
-    } else {
-        vec![summary, raw]
-    }
-}
-
-fn path_to_scope(path: &str) -> String {
-    let mut trimmed = path.trim_start_matches("./");
-    if trimmed.starts_with("a/") || trimmed.starts_with("b/") {
-        trimmed = &trimmed[2..];
-    }
-    if trimmed.is_empty() {
-        return String::new();
-    }
-    let mut parts = trimmed.split('/');
-    let first = parts.next().unwrap_or(trimmed);
-    let candidate = if first == "src" {
-        parts.next().unwrap_or(first)
-    } else {
-        first
-    };
-    let candidate = candidate.split('.').next().unwrap_or(candidate);
-    slugify(candidate)
-}
-
-fn slugify(input: &str) -> String {
-    let mut slug = String::new();
-    let mut last_dash = false;
-    for ch in input.chars() {
-        if ch.is_ascii_alphanumeric() {
-            slug.push(ch.to_ascii_lowercase());
-            last_dash = false;
-        } else if !last_dash {
-            slug.push('-');
-            last_dash = true;
-        }
-    }
-    slug.trim_matches('-').to_string()
-}
-
-fn humanize_slug(slug: &str) -> String {
-    if slug.eq_ignore_ascii_case("cli") {
-        return "CLI".to_string();
-    }
-    if slug.eq_ignore_ascii_case("kv") {
-        return "KV".to_string();
-    }
-    if slug.eq_ignore_ascii_case("deps") {
-        return "Dependencies".to_string();
-    }
-    let parts: Vec<String> = slug
-        .split('-')
-        .filter(|part| !part.is_empty())
-        .map(|part| {
-            let mut chars = part.chars();
-            if let Some(first) = chars.next() {
-                format!(
-                    "{}{}",
-                    first.to_ascii_uppercase(),
-                    chars.as_str().to_ascii_lowercase()
-                )
-            } else {
-                String::new()
-            }
-        })
-        .collect();
-    if parts.is_empty() {
-        "Project".to_string()
-    } else {
-        parts.join(" ")
-    }
-}
-
-enum SubjectTemplate {
-    StabilizeCommitGeneration,
-    SyncDocsAndCode,
-    UpdateDocs,
-    IntroduceScope,
-    RefineScope,
-    UpdateScope,
-    UpdateDeps,
-}
-
-fn build_subject(language: &Language, template: SubjectTemplate, scope: &str) -> String {
-    match (language, template) {
-        (Language::English, SubjectTemplate::StabilizeCommitGeneration) => {
-            "stabilize commit message generation".to_string()
-        }
-        (Language::Chinese, SubjectTemplate::StabilizeCommitGeneration) => {
-            "稳定提交信息生成流程".to_string()
+fn describe_file_change(file: &FileDiff, language: &Language) -> String {
+    let mut notes = Vec::new();
+    match &file.kind {
+        ChangeKind::Added => notes.push(language.change_added().to_string()),
+        ChangeKind::Deleted => notes.push(language.change_deleted().to_string()),
+        ChangeKind::Modified => {}
+        ChangeKind::Renamed { from } => {
+            notes.push(language.change_renamed_from().replace("{}", from));
         }
-        (Language::English, SubjectTemplate::UpdateDeps) => "update dependencies".to_string(),
-        (Language::Chinese, SubjectTemplate::UpdateDeps) => "更新依赖".to_string(),
-        (Language::English, SubjectTemplate::SyncDocsAndCode) => {
-            "align docs and code changes".to_string()
+        ChangeKind::Copied { from } => {
+            notes.push(language.change_copied_from().replace("{}", from));
         }
-        (Language::Chinese, SubjectTemplate::SyncDocsAndCode) => "同步文档与代码更新".to_string(),
-        (Language::English, SubjectTemplate::UpdateDocs) => {
-            format!("update {} documentation", scope)
+        ChangeKind::ModeChanged { old_mode, new_mode } => {
+            notes.push(
+                language
+                    .change_mode()
+                    .replace("{}", &format!("{old_mode:o} -> {new_mode:o}")),
+            );
         }
-        (Language::Chinese, SubjectTemplate::UpdateDocs) => {
-            format!("更新{}文档", scope)
+        ChangeKind::Submodule { old, new } => {
+            let old = old.as_deref().unwrap_or("none");
+            let new = new.as_deref().unwrap_or("none");
+            notes.push(
+                language
+                    .change_submodule()
+                    .replace("{}", &format!("{old} -> {new}")),
+            );
+            return notes.join(", ");
         }
-        (Language::English, SubjectTemplate::IntroduceScope) => format!("add {}", scope),
-        (Language::Chinese, SubjectTemplate::IntroduceScope) => format!("新增{}", scope),
-        (Language::English, SubjectTemplate::RefineScope) => format!("refine {}", scope),
-        (Language::Chinese, SubjectTemplate::RefineScope) => format!("优化{}", scope),
-        (Language::English, SubjectTemplate::UpdateScope) => format!("update {}", scope),
-        (Language::Chinese, SubjectTemplate::UpdateScope) => format!("更新{}", scope),
     }
-}
 
-fn build_scope_readable(scopes: &[String], language: &Language) -> String {
-    if scopes.is_empty() {
-        return match language {
-            Language::English => "project".to_string(),
-            Language::Chinese => "项目".to_string(),
-        };
+    if file.binary {
+        notes.push(language.change_binary().to_string());
+    } else {
+        notes.push(format!("+{} / -{}", file.additions, file.deletions));
     }
 
-    let words: Vec<String> = scopes.iter().map(|slug| humanize_slug(slug)).collect();
-    match (language, words.len()) {
-        (Language::English, 1) => words[0].clone(),
-        (Language::English, 2) => format!("{} and {}", words[0], words[1]),
-        (Language::English, _) => format!("{} and more", words[0]),
-        (Language::Chinese, 1) => words[0].clone(),
-        (Language::Chinese, 2) => format!("{}和{}", words[0], words[1]),
-        (Language::Chinese, _) => format!("{}等", words[0]),
-    }
+    notes.join(", ")
 }
 
-fn build_scope_slug(scopes: &[String]) -> String {
-    if scopes.is_empty() {
-        return String::new();
-    }
-    if scopes.iter().any(|s| s == "deps") {
-        return "deps".to_string();
-    }
-    if scopes.iter().any(|s| s == "docs") && scopes.len() == 1 {
-        return "docs".to_string();
-    }
-    scopes.iter().take(2).cloned().collect::<Vec<_>>().join("-")
+fn is_generated_or_large_file(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.contains("pnpm-lock")
+        || lower.contains("package-lock")
+        || lower.contains("yarn.lock")
+        || lower.contains("cargo.lock")
+        || lower.ends_with(".min.js")
+        || lower.ends_with(".min.css")
 }
 
-fn compute_scopes(summary: &DiffSummary) -> Vec<String> {
-    fn push_unique(scopes: &mut Vec<String>, value: &str) {
-        if !scopes.iter().any(|s| s == value) {
-            scopes.push(value.to_string());
-        }
+fn build_diff_raw_tail(diff: &str, language: &Language, max_chars: usize) -> String {
+    if diff.len() <= max_chars {
+        return diff.to_string();
     }
 
-    let mut scopes = Vec::new();
-
-    if summary.has_main {
-        push_unique(&mut scopes, "cli");
-    }
-    if summary.has_llama {
-        push_unique(&mut scopes, "llama");
-    }
-    if summary.has_docs_only() {
-        push_unique(&mut scopes, "docs");
-    }
-    if summary.has_cargo_toml || summary.has_cargo_lock {
-        push_unique(&mut scopes, "deps");
-    }
-    if summary.has_node_manifest || summary.has_node_lock {
-        push_unique(&mut scopes, "deps");
+    let mut chars: Vec<char> = diff.chars().collect();
+    if chars.len() > max_chars {
+        chars.drain(0..chars.len() - max_chars);
     }
 
-    for candidate in &summary.scope_candidates {
-        if scopes.len() >= 3 {
-            break;
-        }
-        push_unique(&mut scopes, candidate);
+    let mut trimmed: String = chars.into_iter().collect();
+    if let Some(pos) = trimmed.find("diff --git ") {
+        trimmed = trimmed[pos..].to_string();
     }
 
-    if scopes.is_empty() {
-        push_unique(&mut scopes, "project");
+    format!("{}\n\n{}", language.truncated_diff_notice(), trimmed)
+}
+
+fn build_diff_variants(
+    diff: &StagedDiff,
+    language: &Language,
+    max_chars: usize,
+) -> Vec<(String, KeepSide)> {
+    let summary = build_diff_summary(diff, language, max_chars);
+    let raw = build_diff_raw_tail(&diff.text, language, max_chars);
+    if summary.trim() == raw.trim() {
+        vec![(summary, KeepSide::Head)]
+    } else {
+        vec![(summary, KeepSide::Head), (raw, KeepSide::Tail)]
     }
+}
 
-    scopes
+/// Which end of the diff to preserve when it has to be cut to fit the prompt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeepSide {
+    Head,
+    Tail,
 }
 
-fn generate_fallback_commit_message(diff: &str, language: &Language) -> Option<String> {
-    let summary = analyze_diff_summary(diff);
-    if summary.files.is_empty() {
-        return None;
-    }
+#[derive(Debug, PartialEq)]
+struct PromptTrim {
+    kept_lines: usize,
+    total_lines: usize,
+    prompt_tokens: usize,
+    untrimmed_tokens: usize,
+}
 
-    let mut scopes = compute_scopes(&summary);
-
-    let has_deps_change = summary.has_cargo_lock
-        || summary.has_cargo_toml
-        || summary.has_node_lock
-        || summary.has_node_manifest;
-    let has_runtime_change = summary.has_main || summary.has_llama;
-
-    let (commit_type, template) = if summary.has_retry || summary.has_kv_reset {
-        ("fix", SubjectTemplate::StabilizeCommitGeneration)
-    } else if has_runtime_change {
-        ("fix", SubjectTemplate::RefineScope)
-    } else if summary.has_docs && summary.has_code {
-        ("fix", SubjectTemplate::SyncDocsAndCode)
-    } else if summary.has_docs_only() {
-        ("docs", SubjectTemplate::UpdateDocs)
-    } else if has_deps_change && !summary.has_code {
-        ("chore", SubjectTemplate::UpdateDeps)
-    } else if summary.has_code {
-        if !summary.new_files.is_empty() {
-            ("feat", SubjectTemplate::IntroduceScope)
-        } else {
-            ("refactor", SubjectTemplate::RefineScope)
+struct FittedPrompt {
+    prompt: String,
+    trim: Option<PromptTrim>,
+}
+
+/// Renders a prompt around `diff` that fits in `budget` tokens as counted by
+/// `count_tokens`. Only diff lines are dropped (from the end opposite `keep`),
+/// never the instructions rendered by `render`.
+fn fit_prompt_to_budget(
+    diff: &str,
+    keep: KeepSide,
+    budget: usize,
+    notice: &str,
+    render: impl Fn(&str) -> String,
+    count_tokens: impl Fn(&str) -> std::result::Result<usize, LlamaError>,
+) -> std::result::Result<FittedPrompt, LlamaError> {
+    let full = render(diff);
+    let untrimmed_tokens = count_tokens(&full)?;
+    if untrimmed_tokens <= budget {
+        return Ok(FittedPrompt {
+            prompt: full,
+            trim: None,
+        });
+    }
+
+    let lines: Vec<&str> = diff.lines().collect();
+    let render_kept = |count: usize| {
+        let kept = match keep {
+            KeepSide::Head => lines[..count].join("\n"),
+            KeepSide::Tail => lines[lines.len() - count..].join("\n"),
+        };
+        match keep {
+            KeepSide::Head => render(&format!("{kept}\n{notice}")),
+            KeepSide::Tail => render(&format!("{notice}\n{kept}")),
         }
-    } else {
-        ("chore", SubjectTemplate::UpdateScope)
     };
 
-    if commit_type == "chore" && matches!(template, SubjectTemplate::UpdateDeps) {
-        scopes.clear();
-        scopes.push("deps".to_string());
+    let minimal = render_kept(0);
+    let minimal_tokens = count_tokens(&minimal)?;
+    if minimal_tokens > budget {
+        return Err(LlamaError::PromptTooLong {
+            tokens: minimal_tokens,
+            available: budget,
+        });
+    }
+
+    // Never consider more lines than could plausibly fit, so huge diffs are
+    // not re-tokenized in full on every probe.
+    let char_cap = budget.saturating_mul(MAX_CHARS_PER_TOKEN);
+    let ordered: Box<dyn Iterator<Item = &&str>> = match keep {
+        KeepSide::Head => Box::new(lines.iter()),
+        KeepSide::Tail => Box::new(lines.iter().rev()),
+    };
+    let mut chars = 0usize;
+    let plausible = ordered
+        .take_while(|line| {
+            chars += line.len() + 1;
+            chars <= char_cap
+        })
+        .count();
+
+    let (mut low, mut high) = (0usize, plausible.min(lines.len().saturating_sub(1)));
+    let (mut best_prompt, mut best_tokens) = (minimal, minimal_tokens);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        let candidate = render_kept(mid);
+        let tokens = count_tokens(&candidate)?;
+        if tokens <= budget {
+            low = mid;
+            best_prompt = candidate;
+            best_tokens = tokens;
+        } else {
+            high = mid - 1;
+        }
     }
 
-    let scope_slug = build_scope_slug(&scopes);
-    let scope_readable = build_scope_readable(&scopes, language);
-    let subject = build_subject(language, template, &scope_readable);
-
-    Some(if scope_slug.is_empty() {
-        format!("{commit_type}: {subject}")
-    } else {
-        format!("{commit_type}({scope_slug}): {subject}")
+    Ok(FittedPrompt {
+        prompt: best_prompt,
+        trim: Some(PromptTrim {
+            kept_lines: low,
+            total_lines: lines.len(),
+            prompt_tokens: best_tokens,
+            untrimmed_tokens,
+        }),
     })
 }
+
 fn is_valid_commit_message(message: &str, language: &Language) -> bool {
     let subject_line = message
         .lines()
@@ -1216,10 +843,8 @@ fn is_valid_commit_message(message: &str, language: &Language) -> bool {
         return false;
     }
 
-    if let Language::English = language {
-        if !subject_line.is_ascii() {
-            return false;
-        }
+    if language.ascii_subject() && !subject_line.is_ascii() {
+        return false;
     }
 
     true
@@ -1294,37 +919,111 @@ impl GitConfig {
     }
 }
 
-fn select_language(git_config: &mut GitConfig) -> Result<Language> {
+/// `git ca language [<code>] [--local | --ui]`: sets the language commit
+/// messages are written in, for every repository or with `--local` for this
+/// one only, or with `--ui` the interface language. Without a code the choice
+/// is made from a numbered list.
+fn select_language(git_config: &mut GitConfig, args: &[String]) -> Result<Language> {
     let current_lang = get_language(git_config);
-    println!("{}", current_lang.available_languages());
-    println!("1. English");
-    println!("2. 简体中文");
-
-    let choice = loop {
-        let input = get_user_input(&current_lang.select_language_prompt())?;
-        match input.parse::<usize>() {
-            Ok(1) => break Language::English,
-            Ok(2) => break Language::Chinese,
-            _ => println!("{}", current_lang.invalid_selection()),
+    let usage = || AppError::Usage(current_lang.language_usage().to_string());
+    let (mut ui, mut local, mut code) = (false, false, None);
+    for arg in args {
+        match arg.as_str() {
+            "--ui" => ui = true,
+            "--local" => local = true,
+            _ if code.is_none() && !arg.starts_with('-') => code = Some(arg.as_str()),
+            _ => return Err(usage()),
+        }
+    }
+    if ui && local {
+        return Err(usage());
+    }
+
+    let choice = match code {
+        Some(code) => Language::from_str(code).ok_or_else(usage)?,
+        None => {
+            let languages: Vec<Language> = Language::all().collect();
+            println!("{}", current_lang.available_languages());
+            for (index, language) in languages.iter().enumerate() {
+                println!("{}. {}", index + 1, language.display_name());
+            }
+
+            loop {
+                let input = get_user_input(current_lang.select_language_prompt())?;
+                match input.parse::<usize>() {
+                    Ok(number) if (1..=languages.len()).contains(&number) => {
+                        break languages[number - 1].clone()
+                    }
+                    _ => println!("{}", current_lang.invalid_selection()),
+                }
+            }
         }
     };
 
-    git_config.set(CONFIG_LANGUAGE_KEY, choice.to_string())?;
+    if ui {
+        git_config.set(CONFIG_UI_LANGUAGE_KEY, choice.to_string())?;
+        println!(
+            "{}",
+            choice
+                .language_set_to()
+                .replace("{}", choice.display_name())
+        );
+        return Ok(choice);
+    }
+
+    if local {
+        let root = find_git_repository(&env::current_dir()?).ok_or_else(|| {
+            AppError::NotInRepository(current_lang.not_in_git_repository().to_string())
+        })?;
+        Repository::open(root)?
+            .config()?
+            .open_level(ConfigLevel::Local)?
+            .set_str(CONFIG_LANGUAGE_KEY, choice.to_string())?;
+    } else {
+        git_config.set(CONFIG_LANGUAGE_KEY, choice.to_string())?;
+    }
+    // The interface may follow the message language, so look it up again.
     println!(
         "{}",
-        choice
-            .language_set_to()
-            .replace("{}", &choice.display_name())
+        get_language(git_config)
+            .message_language_set_to()
+            .replace("{}", choice.display_name())
     );
     Ok(choice)
 }
 
+/// The interface language: `commit-analyzer.uiLanguage`, else the locale from
+/// `LC_ALL`/`LC_MESSAGES`/`LANG`, else the commit message language.
 fn get_language(git_config: &GitConfig) -> Language {
     git_config
-        .get(CONFIG_LANGUAGE_KEY)
+        .get(CONFIG_UI_LANGUAGE_KEY)
         .ok()
-        .and_then(|lang| Language::from_str(&lang))
-        .unwrap_or(Language::English)
+        .and_then(|code| Language::from_str(&code))
+        .or_else(Language::from_environment)
+        .unwrap_or_else(get_message_language)
+}
+
+/// The language commit messages are written in: `commit-analyzer.language`
+/// as the current repository sees it, so a repository can set its own.
+fn get_message_language() -> Language {
+    env::current_dir()
+        .ok()
+        .and_then(|dir| find_git_repository(&dir))
+        .and_then(|root| Repository::open(root).ok())
+        .and_then(|repo| repo.config().ok())
+        .or_else(|| Config::open_default().ok())
+        .and_then(|config| config.get_string(CONFIG_LANGUAGE_KEY).ok())
+        .and_then(|code| Language::from_str(&code))
+        .unwrap_or_else(Language::english)
+}
+
+/// Reads `commit-analyzer.timeout` (seconds, `0` for no limit).
+fn inference_timeout(git_config: &GitConfig) -> Option<Duration> {
+    let seconds = git_config
+        .config
+        .get_i64(CONFIG_TIMEOUT_KEY)
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    (seconds > 0).then(|| Duration::from_secs(seconds as u64))
 }
 
 fn home_dir() -> Option<PathBuf> {
@@ -1363,51 +1062,138 @@ fn models_root_dir() -> Result<PathBuf> {
     }
 }
 
-fn model_record_candidates() -> Vec<PathBuf> {
-    let mut candidates = Vec::new();
-    if let Some(home) = home_dir() {
-        candidates.push(home.join(".cache/git-ca/default-model.path"));
+/// Where evaluated prompt prefixes are saved between runs.
+fn state_cache_dir() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(".cache/git-ca/state"))
+}
+
+/// Where a default-model record is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ModelScope {
+    /// `~/.cache/git-ca`, used in every repository.
+    Global,
+    /// `.git-ca` at the root of the current repository.
+    Repository,
+}
+
+/// The record file for `scope`, either the general one or the one used only
+/// when commit messages are written in `language`.
+fn model_record_path(scope: ModelScope, language: Option<&Language>) -> Option<PathBuf> {
+    let dir = match scope {
+        ModelScope::Global => home_dir()?.join(".cache/git-ca"),
+        ModelScope::Repository => find_git_repository(&env::current_dir().ok()?)?.join(".git-ca"),
+    };
+    let name = match language {
+        Some(language) => format!("default-model.{}.path", language.to_string()),
+        None => "default-model.path".to_string(),
+    };
+    Some(dir.join(name))
+}
+
+/// Records that may choose the model, most specific first: the repository's
+/// before the global one, and a record for `language` before the general one.
+fn model_record_candidates(language: &Language) -> Vec<(ModelScope, PathBuf)> {
+    [
+        (ModelScope::Repository, Some(language)),
+        (ModelScope::Repository, None),
+        (ModelScope::Global, Some(language)),
+        (ModelScope::Global, None),
+    ]
+    .into_iter()
+    .filter_map(|(scope, language)| Some((scope, model_record_path(scope, language)?)))
+    .collect()
+}
+
+/// The record currently choosing the model for `language`, with the model
+/// path it holds.
+fn effective_model_record(language: &Language) -> Option<(PathBuf, String)> {
+    model_record_candidates(language)
+        .into_iter()
+        .find_map(|(scope, record)| Some((record.clone(), read_model_record(scope, &record)?)))
+}
+
+/// The model path stored in `record`, if it holds one. Relative paths in a
+/// repository record are resolved against the repository root.
+fn read_model_record(scope: ModelScope, record: &Path) -> Option<String> {
+    if !record.is_file() {
+        return None;
     }
-    if let Ok(current) = env::current_dir() {
-        candidates.push(current.join(".git-ca/default-model.path"));
+    match fs::read_to_string(record) {
+        Ok(contents) => {
+            let trimmed = contents.trim();
+            if trimmed.is_empty() {
+                return None;
+            }
+            let root = record.parent().and_then(Path::parent);
+            let stored = match (scope, root) {
+                (ModelScope::Repository, Some(root))
+                    if Path::new(trimmed).is_relative() && !trimmed.starts_with('~') =>
+                {
+                    root.join(trimmed).to_string_lossy().into_owned()
+                }
+                _ => trimmed.to_string(),
+            };
+            Some(stored)
+        }
+        Err(err) => {
+            eprintln!(
+                "[git-ca] warning: could not read persisted model path ({}): {err}",
+                record.display()
+            );
+            None
+        }
     }
-    candidates
 }
 
-fn load_persisted_model_path() -> Option<String> {
-    for record in model_record_candidates() {
-        if !record.is_file() {
-            continue;
-        }
-        match fs::read_to_string(&record) {
-            Ok(contents) => {
-                let trimmed = contents.trim();
-                if !trimmed.is_empty() {
-                    return Some(trimmed.to_string());
-                }
+/// Every record, for any message language and in either scope, that points
+/// at the model `path` (as given by [`canonical_model_path`]).
+fn model_records_for(path: &Path) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    let mut records = Vec::new();
+    for language in Language::all() {
+        for (scope, record) in model_record_candidates(&language) {
+            if !seen.insert(record.clone()) {
+                continue;
             }
-            Err(err) => {
-                eprintln!(
-                    "[git-ca] warning: could not read persisted model path ({}): {err}",
-                    record.display()
-                );
+            let points_here = read_model_record(scope, &record)
+                .is_some_and(|stored| canonical_model_path(&expand_model_path(&stored)) == path);
+            if points_here {
+                records.push(record);
             }
         }
     }
-    None
+    records
 }
 
+fn load_persisted_model_path(language: &Language) -> Option<String> {
+    effective_model_record(language).map(|(_, stored)| stored)
+}
+
+/// Writes `path` to a record. Repository records store paths inside the
+/// repository relative to its root, so the record can be committed.
+fn write_model_record(scope: ModelScope, record: &Path, path: &Path) -> io::Result<()> {
+    let root = record.parent().and_then(Path::parent);
+    let serialized = match (scope, root) {
+        (ModelScope::Repository, Some(root)) => path
+            .strip_prefix(root)
+            .or_else(|_| path.strip_prefix(canonical_model_path(root)))
+            .unwrap_or(path),
+        _ => path,
+    };
+    if let Some(parent) = record.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(record, serialized.to_string_lossy().as_ref())
+}
+
+/// Remembers `path` as the default model, globally when possible.
 fn persist_model_path(path: &Path) {
     let mut last_error: Option<String> = None;
-    let serialized = path.to_string_lossy();
-    for record in model_record_candidates() {
-        if let Some(parent) = record.parent() {
-            if let Err(err) = fs::create_dir_all(parent) {
-                last_error = Some(err.to_string());
-                continue;
-            }
-        }
-        match fs::write(&record, serialized.as_ref()) {
+    for scope in [ModelScope::Global, ModelScope::Repository] {
+        let Some(record) = model_record_path(scope, None) else {
+            continue;
+        };
+        match write_model_record(scope, &record, path) {
             Ok(_) => return,
             Err(err) => {
                 last_error = Some(err.to_string());
@@ -1422,19 +1208,27 @@ fn persist_model_path(path: &Path) {
     }
 }
 
-fn clear_persisted_model_path() {
-    for record in model_record_candidates() {
-        if record.is_file() {
-            if let Err(err) = fs::remove_file(&record) {
-                eprintln!(
-                    "[git-ca] warning: could not clear cached model path ({}): {err}",
-                    record.display()
-                );
-            }
+/// Forgets the record currently choosing the model for `language`.
+fn clear_persisted_model_path(language: &Language) {
+    if let Some((record, _)) = effective_model_record(language) {
+        if let Err(err) = fs::remove_file(&record) {
+            eprintln!(
+                "[git-ca] warning: could not clear cached model path ({}): {err}",
+                record.display()
+            );
         }
     }
 }
 
+/// Whether `path` is the second or a later shard of a split model, which is
+/// listed and loaded through its first shard.
+fn is_later_shard(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(split_shard)
+        .is_some_and(|(_, index, _)| index > 1)
+}
+
 fn is_gguf(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
@@ -1442,6 +1236,20 @@ fn is_gguf(path: &Path) -> bool {
         .unwrap_or(false)
 }
 
+/// Absolute form of a model path. Only the directory is resolved: a pulled
+/// model may be a symlink into the Hugging Face cache, whose blob names say
+/// nothing about the model and hide the shards of a split one.
+fn canonical_model_path(path: &Path) -> PathBuf {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match (fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
 fn expand_model_path(input: &str) -> PathBuf {
     let trimmed = input.trim();
 
@@ -1478,7 +1286,11 @@ fn find_local_models() -> Vec<PathBuf> {
         if let Ok(entries) = fs::read_dir(&dir) {
             for entry in entries.flatten() {
                 let path = entry.path();
-                if path.is_file() && is_gguf(&path) && seen.insert(path.clone()) {
+                if path.is_file()
+                    && is_gguf(&path)
+                    && !is_later_shard(&path)
+                    && seen.insert(path.clone())
+                {
                     found.push(path);
                 }
             }
@@ -1489,83 +1301,37 @@ fn find_local_models() -> Vec<PathBuf> {
     found
 }
 
-fn download_model_from_hub(repo_id: &str, language: &Language) -> Result<PathBuf> {
-    let api = Api::new()
-        .map_err(|e| AppError::Custom(format!("Failed to initialize Hugging Face client: {e}")))?;
-    let repo = api.model(repo_id.to_string());
-    let info = repo.info().map_err(|e| {
-        AppError::Custom(format!(
-            "Failed to fetch repository '{repo_id}' metadata: {e}"
-        ))
-    })?;
-
-    let mut fallback: Option<&str> = None;
-    let mut preferred: Option<&str> = None;
-
-    for sibling in &info.siblings {
-        let name = sibling.rfilename.as_str();
-        let lower = name.to_ascii_lowercase();
-        if !lower.ends_with(".gguf") {
-            continue;
-        }
-
-        if fallback.is_none() {
-            fallback = Some(name);
-        }
-
-        if lower.contains("q4") {
-            preferred = Some(name);
-            break;
-        }
-    }
-
-    let filename = preferred.or(fallback).ok_or_else(|| {
-        AppError::Custom(format!("No GGUF files found in repository '{repo_id}'"))
-    })?;
-
-    println!("{}", language.downloading_model().replace("{}", repo_id));
-    let source_path = repo.get(filename).map_err(|e| {
-        AppError::Custom(format!(
-            "Failed to download '{}' from '{}': {e}",
-            filename, repo_id
-        ))
-    })?;
-
-    let dest_dir = models_root_dir()?;
-    fs::create_dir_all(&dest_dir)?;
-
-    let base_name = Path::new(filename)
-        .file_name()
-        .and_then(|os| os.to_str())
-        .unwrap_or(filename);
-    let sanitized_repo = repo_id.replace(['/', '\\'], "__");
-    let dest_file_name = format!("{sanitized_repo}__{base_name}");
-    let dest_path = dest_dir.join(dest_file_name);
-
-    if !dest_path.exists() {
-        fs::copy(&source_path, &dest_path)?;
+/// Tells the user how to get a model: pull one, or add a local file when
+/// offline.
+fn missing_model_hint(language: &Language) -> &'static str {
+    if HubSettings::load().offline {
+        language.model_add_hint()
+    } else {
+        language.model_pull_hint()
     }
-
-    let canonical = fs::canonicalize(&dest_path).unwrap_or(dest_path.clone());
-    println!(
-        "{}",
-        language
-            .download_completed()
-            .replace("{}", &canonical.to_string_lossy())
-    );
-    Ok(canonical)
 }
 
 fn ensure_default_model(language: &Language) -> Result<Option<PathBuf>> {
-    if find_local_models().is_empty() {
+    if find_local_models().is_empty() && !HubSettings::load().offline {
+        if !assume_yes() && io::stdin().is_terminal() {
+            let answer = get_user_input(
+                &language
+                    .confirm_default_download()
+                    .replace("{}", DEFAULT_MODEL_REPO),
+            )?;
+            if answer.eq_ignore_ascii_case("n") || answer.eq_ignore_ascii_case("no") {
+                println!("{}", language.default_download_declined());
+                return Err(AppError::Cancelled);
+            }
+        }
         println!(
             "{}",
             language
                 .auto_downloading_default()
                 .replace("{}", DEFAULT_MODEL_REPO)
         );
-        let downloaded = download_model_from_hub(DEFAULT_MODEL_REPO, language)?;
-        let canonical = fs::canonicalize(&downloaded).unwrap_or(downloaded);
+        let downloaded = hub::download(&PullSpec::new(DEFAULT_MODEL_REPO), language)?;
+        let canonical = canonical_model_path(&downloaded);
         persist_model_path(&canonical);
         println!(
             "{}",
@@ -1580,10 +1346,11 @@ fn ensure_default_model(language: &Language) -> Result<Option<PathBuf>> {
 }
 
 fn get_model_path(language: &Language) -> Result<PathBuf> {
-    if let Some(stored) = load_persisted_model_path() {
+    let message_language = get_message_language();
+    if let Some(stored) = load_persisted_model_path(&message_language) {
         let expanded = expand_model_path(&stored);
         if expanded.is_file() && is_gguf(&expanded) {
-            let canonical = fs::canonicalize(&expanded).unwrap_or(expanded);
+            let canonical = canonical_model_path(&expanded);
             println!(
                 "{}",
                 language
@@ -1598,7 +1365,7 @@ fn get_model_path(language: &Language) -> Result<PathBuf> {
                     .model_file_missing()
                     .replace("{}", &expanded.to_string_lossy())
             );
-            clear_persisted_model_path();
+            clear_persisted_model_path(&message_language);
         }
     }
 
@@ -1609,12 +1376,12 @@ fn get_model_path(language: &Language) -> Result<PathBuf> {
     let models = find_local_models();
     if models.is_empty() {
         println!("{}", language.no_default_model());
-        println!("{}", language.model_pull_hint());
+        println!("{}", missing_model_hint(language));
         return select_model_path(language);
     }
 
     if models.len() == 1 {
-        let canonical = fs::canonicalize(&models[0]).unwrap_or_else(|_| models[0].clone());
+        let canonical = canonical_model_path(&models[0]);
         persist_model_path(&canonical);
         println!(
             "{}",
@@ -1634,7 +1401,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
     let models = find_local_models();
     if models.is_empty() {
         println!("{}", language.no_models_found());
-        println!("{}", language.model_pull_hint());
+        println!("{}", missing_model_hint(language));
     } else {
         println!("{}", language.available_models());
         for (i, model) in models.iter().enumerate() {
@@ -1644,7 +1411,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
 
     if !io::stdin().is_terminal() {
         if let Some(first) = models.first() {
-            let canonical = fs::canonicalize(first).unwrap_or_else(|_| first.clone());
+            let canonical = canonical_model_path(first);
             persist_model_path(&canonical);
             println!(
                 "{}",
@@ -1654,7 +1421,9 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
             );
             return Ok(canonical);
         }
-        return Err(AppError::Custom(language.no_models_found().to_string()));
+        return Err(AppError::ModelNotFound(
+            language.no_models_found().to_string(),
+        ));
     }
 
     println!("{}", language.enter_model_path_hint());
@@ -1664,7 +1433,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
             Ok(value) => value,
             Err(AppError::InputClosed) => {
                 if let Some(first) = models.first() {
-                    let canonical = fs::canonicalize(first).unwrap_or_else(|_| first.clone());
+                    let canonical = canonical_model_path(first);
                     persist_model_path(&canonical);
                     println!(
                         "{}",
@@ -1687,8 +1456,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
 
         if let Ok(index) = trimmed.parse::<usize>() {
             if index > 0 && index <= models.len() {
-                let selected = fs::canonicalize(&models[index - 1])
-                    .unwrap_or_else(|_| models[index - 1].clone());
+                let selected = canonical_model_path(&models[index - 1]);
                 persist_model_path(&selected);
                 println!(
                     "{}",
@@ -1719,7 +1487,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
             continue;
         }
 
-        let canonical = fs::canonicalize(&candidate).unwrap_or(candidate);
+        let canonical = canonical_model_path(&candidate);
         persist_model_path(&canonical);
         println!(
             "{}",
@@ -1734,6 +1502,7 @@ fn select_model_path(language: &Language) -> Result<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diff::tests::file_diff;
 
     #[test]
     fn handles_extracts_subject_line() {
@@ -1766,11 +1535,20 @@ mod tests {
     fn validates_git_flow_subject() {
         assert!(is_valid_commit_message(
             "feat(cli): improve prompts",
-            &Language::English
+            &Language::english()
         ));
         assert!(is_valid_commit_message(
             "docs: 更新贡献指南",
-            &Language::Chinese
+            &Language::from_str("zh").expect("zh")
+        ));
+        // Only English insists on ASCII, so accented subjects pass elsewhere.
+        assert!(is_valid_commit_message(
+            "fix(cli): corrige el código de salida",
+            &Language::from_str("es").expect("es")
+        ));
+        assert!(!is_valid_commit_message(
+            "fix(cli): corrige el código de salida",
+            &Language::english()
         ));
     }
 
@@ -1778,146 +1556,318 @@ mod tests {
     fn rejects_invalid_commit_messages() {
         assert!(!is_valid_commit_message(
             "Implement new feature",
-            &Language::English
+            &Language::english()
         ));
         assert!(!is_valid_commit_message(
             "feat(): missing subject",
-            &Language::English
+            &Language::english()
         ));
         assert!(!is_valid_commit_message(
             "feat(cli) missing colon",
-            &Language::English
+            &Language::english()
         ));
     }
 
     #[test]
-    fn fallback_generates_for_retry_flow() {
-        let diff = "\
-diff --git a/src/main.rs b/src/main.rs
-index 1111111..2222222 100644
---- a/src/main.rs
-+++ b/src/main.rs
-@@
-+ println!(\"Model response was invalid. Retrying with stricter instructions...\");
-";
-        let message = generate_fallback_commit_message(diff, &Language::English).expect("fallback");
-        assert!(message.starts_with("fix("));
-        assert!(message.contains("stabilize commit message generation"));
+    fn commit_prompt_prefix_precedes_the_diff() {
+        for language in Language::all() {
+            let prefix = commit_prompt_prefix(&language);
+            assert!(!prefix.is_empty());
+            assert!(build_commit_prompt("+added line", &language, 1).starts_with(&prefix));
+            assert!(!prefix.contains("+added line"));
+        }
     }
 
     #[test]
-    fn fallback_generates_for_docs_only() {
-        let diff = "\
-diff --git a/AGENTS.md b/AGENTS.md
-new file mode 100644
-index 0000000..3333333
---- /dev/null
-+++ b/AGENTS.md
-@@
-+# Repository Guidelines
-";
-        let message =
-            generate_fallback_commit_message(diff, &Language::English).expect("fallback docs");
-        assert!(message.starts_with("docs("));
-        assert!(message.contains("documentation"));
+    fn truncates_diff_for_prompt() {
+        let language = Language::english();
+        let long_line = format!("+{}", "a".repeat(5000));
+        let long_diff = StagedDiff {
+            text: format!("diff --git a/file b/file\n{long_line}\n"),
+            files: vec![file_diff("file", ChangeKind::Modified, &[&long_line])],
+        };
+        let prepared = build_diff_summary(&long_diff, &language, 2048);
+        assert!(prepared.contains(language.truncated_diff_notice()));
+        assert!(prepared.len() < long_diff.text.len());
     }
 
     #[test]
-    fn fallback_prefers_runtime_scope() {
-        let diff = "\
-diff --git a/src/main.rs b/src/main.rs
-index 1111111..2222222 100644
---- a/src/main.rs
-+++ b/src/main.rs
-@@
-+ println!(\"Processing response...\");
-diff --git a/src/llama.rs b/src/llama.rs
-new file mode 100644
-index 0000000..3333333
---- /dev/null
-+++ b/src/llama.rs
-@@
-+ llama_kv_self_clear(ctx);
-";
-        let message =
-            generate_fallback_commit_message(diff, &Language::English).expect("fallback runtime");
-        assert!(message.starts_with("fix("));
-        assert!(
-            message.contains("stabilize commit message generation") || message.contains("refine")
+    fn repository_model_records_are_relative_to_the_root() {
+        let root = env::temp_dir().join(format!("git-ca-record-{}", std::process::id()));
+        let record = root.join(".git-ca/default-model.zh.path");
+        let model = root.join("models/qwen.gguf");
+
+        write_model_record(ModelScope::Repository, &record, &model).unwrap();
+        assert_eq!(
+            fs::read_to_string(&record).unwrap(),
+            Path::new("models").join("qwen.gguf").to_string_lossy()
+        );
+        assert_eq!(
+            read_model_record(ModelScope::Repository, &record).map(PathBuf::from),
+            Some(model.clone())
+        );
+
+        let elsewhere = Path::new("/opt/models/gemma.gguf");
+        write_model_record(ModelScope::Repository, &record, elsewhere).unwrap();
+        assert_eq!(
+            fs::read_to_string(&record).unwrap(),
+            "/opt/models/gemma.gguf"
         );
+
+        fs::remove_dir_all(&root).unwrap();
     }
 
     #[test]
-    fn fallback_handles_dependency_updates() {
-        let diff = concat!(
-            "diff --git a/package.json b/package.json\n",
-            "index 1111111..2222222 100644\n",
-            "--- a/package.json\n",
-            "+++ b/package.json\n",
-            "@@\n",
-            "+  \"llama-kit\": \"^2.0.0\"\n",
-            "diff --git a/pnpm-lock.yaml b/pnpm-lock.yaml\n",
-            "index 1111111..3333333 100644\n",
-            "--- a/pnpm-lock.yaml\n",
-            "+++ b/pnpm-lock.yaml\n",
-            "@@\n",
-            "+packages:\n",
+    fn error_kinds_map_to_distinct_exit_codes() {
+        let errors = [
+            AppError::Custom("x".to_string()),
+            AppError::Usage("x".to_string()),
+            AppError::NotInRepository("x".to_string()),
+            AppError::NoStagedChanges,
+            AppError::ModelNotFound("x".to_string()),
+            AppError::Llama(LlamaError::Load("x".to_string())),
+            AppError::Llama(LlamaError::Inference("x".to_string())),
+            AppError::Download("x".to_string()),
+            AppError::InputClosed,
+            AppError::Cancelled,
+            AppError::Llama(LlamaError::Aborted(AbortReason::TimedOut)),
+            AppError::Llama(LlamaError::Aborted(AbortReason::Interrupted)),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(AppError::exit_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+        assert_eq!(
+            AppError::from(LlamaError::ModelNotFound(PathBuf::from("m.gguf"))).exit_code(),
+            AppError::ModelNotFound("x".to_string()).exit_code()
         );
-        let message =
-            generate_fallback_commit_message(diff, &Language::English).expect("fallback deps");
-        assert_eq!(message, "chore(deps): update dependencies");
+        for error in &errors {
+            assert!(HELP.contains(&format!("  {:<4} ", error.exit_code())));
+        }
+    }
+
+    fn count_words(text: &str) -> std::result::Result<usize, LlamaError> {
+        Ok(text.split_whitespace().count())
     }
 
     #[test]
-    fn truncates_diff_for_prompt() {
-        let language = Language::English;
-        let long_diff = format!("diff --git a/file b/file\n{}", "a".repeat(5000));
-        let prepared = build_diff_summary(&long_diff, &language, 512);
-        assert!(prepared.contains(language.truncated_diff_notice()));
-        assert!(prepared.len() < long_diff.len());
+    fn fits_prompt_by_dropping_diff_lines_only() {
+        let diff = (1..=50)
+            .map(|n| format!("+line {n}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let render = |diff: &str| format!("INSTRUCTIONS KEEP ME\n{diff}\nEND");
+
+        let fitted = fit_prompt_to_budget(&diff, KeepSide::Head, 30, "[cut]", render, count_words)
+            .expect("fits");
+        let trim = fitted.trim.expect("trimmed");
+        assert!(fitted.prompt.starts_with("INSTRUCTIONS KEEP ME\n+line 1\n"));
+        assert!(fitted.prompt.ends_with("[cut]\nEND"));
+        assert_eq!(trim.kept_lines, 12);
+        assert_eq!(trim.total_lines, 50);
+        assert_eq!(trim.prompt_tokens, 29);
+        assert_eq!(trim.untrimmed_tokens, 104);
+
+        let fitted = fit_prompt_to_budget(&diff, KeepSide::Tail, 30, "[cut]", render, count_words)
+            .expect("fits");
+        assert!(fitted.prompt.contains("[cut]\n+line 39\n"));
+        assert!(fitted.prompt.ends_with("+line 50\nEND"));
+
+        let untouched =
+            fit_prompt_to_budget("+one", KeepSide::Head, 30, "[cut]", render, count_words)
+                .expect("fits");
+        assert!(untouched.trim.is_none());
+
+        assert!(
+            fit_prompt_to_budget(&diff, KeepSide::Head, 3, "[cut]", render, count_words).is_err()
+        );
+    }
+
+    #[test]
+    fn summary_describes_change_kinds() {
+        let file = |path: &str, kind: ChangeKind, binary: bool| FileDiff {
+            binary,
+            ..file_diff(path, kind, &[])
+        };
+        let staged = StagedDiff {
+            text: String::new(),
+            files: vec![
+                file(
+                    "src/new_name.rs",
+                    ChangeKind::Renamed {
+                        from: "src/old_name.rs".to_string(),
+                    },
+                    false,
+                ),
+                file("assets/logo.png", ChangeKind::Added, true),
+                file(
+                    "run.sh",
+                    ChangeKind::ModeChanged {
+                        old_mode: 0o100644,
+                        new_mode: 0o100755,
+                    },
+                    false,
+                ),
+                file(
+                    "vendor/foo",
+                    ChangeKind::Submodule {
+                        old: Some("1111111".to_string()),
+                        new: Some("2222222".to_string()),
+                    },
+                    false,
+                ),
+            ],
+        };
+
+        let summary = build_diff_summary(&staged, &Language::english(), 8192);
+        assert!(summary.contains("- src/new_name.rs (renamed from src/old_name.rs, +0 / -0)"));
+        assert!(summary.contains("- assets/logo.png (added, binary)"));
+        assert!(summary.contains("- run.sh (mode 100644 -> 100755, +0 / -0)"));
+        assert!(summary.contains("- vendor/foo (submodule 1111111 -> 2222222)"));
+    }
+
+    #[test]
+    fn falls_back_to_rules_when_the_prompt_cannot_fit() {
+        let diff = StagedDiff {
+            text: "diff --git a/lib.rs b/lib.rs\n+fn run() {}\n".to_string(),
+            files: vec![file_diff("lib.rs", ChangeKind::Modified, &["+fn run() {}"])],
+        };
+        // Ten tokens leave no room for the prompt instructions.
+        let mut session = llama::ScriptedGenerator {
+            context_size: MAX_GENERATION_TOKENS + 10,
+            ..Default::default()
+        };
+        let english = Language::english();
+
+        let message =
+            generate_commit_message(&mut session, &diff, &english, &english, false).unwrap();
+        assert_eq!(message, None);
+        assert!(session.prompts.is_empty());
+    }
+}
+
+const HELP: &str = "\
+Usage: git ca [command] [options]
+
+Commands:
+  (none)            Generate a message for the staged changes and commit
+  split             Propose one commit per scope and create them on approval
+  model [list|info|use|rm|verify|add|pull]
+                    Choose, inspect, check, remove, import or download models
+  language [<code>] [--local | --ui]
+                    Choose the commit message language (for this repository
+                    with --local) or the interface language
+  daemon [start|run|status|stop]
+                    Keep the model loaded in the background
+  doctor [--json]   Measure the model, check its commit format and suggest
+                    settings
+  lint [<file> | - | <commit> | <range>] [--json]
+                    Check commit messages against commitlint's conventional
+                    rules (HEAD by default)
+
+Options:
+  --json            Print errors as a JSON object on stderr (and the doctor
+                    and lint reports as JSON on stdout)
+  -y, --yes         Skip confirmations (model downloads and removals)
+  --ctx-size N, --threads N, --threads-batch N, --batch-size N, --ubatch-size N,
+  --gpu-layers N, --[no-]mmap, --[no-]mlock, --flash-attn auto|on|off,
+  --kv-cache-type TYPE
+                    Override the commit-analyzer.llama.* settings for this run
+  -h, --help        Show this help
+  -v, --version     Show the version
+
+Exit codes:
+  0    success
+  1    other error (git, I/O, configuration)
+  2    invalid arguments
+  3    not in a git repository
+  4    no changes staged
+  5    no model found
+  6    model failed to load
+  7    inference failed
+  8    model download failed
+  9    input closed while waiting for an answer
+  10   cancelled at a prompt
+  11   commit message failed lint
+  124  generation timed out
+  130  generation interrupted
+";
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let json = match args.iter().position(|arg| arg == "--json") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+
+    if let Err(err) = run(args, json) {
+        if json {
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "error": {
+                        "code": err.code(),
+                        "exit_code": err.exit_code(),
+                        "message": err.to_string(),
+                    }
+                })
+            );
+        } else if !err.already_reported() {
+            eprintln!("[git-ca] error: {err}");
+        }
+        std::process::exit(err.exit_code());
     }
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+fn run(mut args: Vec<String>, json: bool) -> Result<()> {
+    let llama_flags = LlamaFlags::extract(&mut args)?;
+    let before = args.len();
+    args.retain(|arg| arg != "--yes" && arg != "-y");
+    ASSUME_YES.store(args.len() != before, Ordering::Relaxed);
 
     if args.len() > 1 && (args[1] == "--version" || args[1] == "-v") {
         println!("git-ca version {}", env!("CARGO_PKG_VERSION"));
         return Ok(());
     }
 
+    if args.len() > 1 && matches!(args[1].as_str(), "--help" | "-h" | "help") {
+        print!("{HELP}");
+        return Ok(());
+    }
+
     let mut git_config = GitConfig::new()?;
     let language = get_language(&git_config);
+    let llama_options = session_options(&git_config.config, &llama_flags, DEFAULT_CONTEXT_SIZE);
 
     if args.len() > 1 {
         match args[1].as_str() {
             "doctor" => {
-                run_doctor(&language)?;
+                run_doctor(&language, &llama_options, json)?;
+                return Ok(());
+            }
+            "lint" => {
+                run_lint(&args[2..], &language, json)?;
                 return Ok(());
             }
             "model" => {
-                if args.len() > 2 && args[2] == "pull" {
-                    if args.len() < 4 {
-                        println!("{}", language.model_pull_usage());
-                        return Ok(());
-                    }
-                    let repo_id = &args[3];
-                    let downloaded = download_model_from_hub(repo_id, &language)?;
-                    persist_model_path(&downloaded);
-                    println!(
-                        "{}",
-                        language
-                            .model_set_as_default()
-                            .replace("{}", &downloaded.to_string_lossy())
-                    );
-                    return Ok(());
-                } else {
-                    select_model_path(&language)?;
-                    return Ok(());
-                }
+                models::run_command(&args[2..], &language)?;
+                return Ok(());
             }
             "language" => {
-                select_language(&mut git_config)?;
+                select_language(&mut git_config, &args[2..])?;
+                return Ok(());
+            }
+            "daemon" => {
+                #[cfg(unix)]
+                daemon::run_command(&args[2..], &git_config, &language, &llama_flags)?;
+                #[cfg(not(unix))]
+                println!("{}", language.daemon_unsupported());
+                return Ok(());
+            }
+            "split" => {
+                run_split(&mut git_config, &language, &llama_options)?;
                 return Ok(());
             }
             _ => {}
@@ -1928,7 +1878,7 @@ fn main() -> Result<()> {
 
     let current_dir = env::current_dir()?;
     let repo_path = find_git_repository(&current_dir)
-        .ok_or_else(|| AppError::Custom(language.not_in_git_repository().to_string()))?;
+        .ok_or_else(|| AppError::NotInRepository(language.not_in_git_repository().to_string()))?;
 
     let repo = Repository::open(&repo_path)?;
     let mut index = repo.index()?;
@@ -1936,17 +1886,28 @@ fn main() -> Result<()> {
     env::set_current_dir(&repo_path)?;
     index.read(true)?;
 
-    let diff = get_diff()?;
-    if diff.trim().is_empty() {
+    let diff = staged_diff(&repo, &index)?;
+    if diff.is_empty() {
         println!("{}", language.no_changes_staged());
-        return Ok(());
-    }
-
-    let context_size = DEFAULT_CONTEXT_SIZE;
-    let mut commit_msg = match analyze_diff(&diff, &model_path, &language, context_size)? {
+        return Err(AppError::NoStagedChanges);
+    }
+
+    let timeout = inference_timeout(&git_config);
+    let message_language = get_message_language();
+    let mut commit_msg = match analyze_diff(
+        &diff,
+        &model_path,
+        &language,
+        &message_language,
+        &llama_options,
+        timeout,
+    )? {
         Some(msg) => msg,
         None => {
-            if let Some(fallback) = generate_fallback_commit_message(&diff, &language) {
+            let rules = FallbackRules::from_config(&git_config.config);
+            if let Some(fallback) =
+                generate_fallback_commit_message(&diff, &message_language, &rules)
+            {
                 println!("{}", language.fallback_commit_generated());
                 println!("{fallback}");
                 fallback
@@ -1969,14 +1930,14 @@ fn main() -> Result<()> {
                 }
                 "c" => {
                     println!("{}", language.commit_cancelled());
-                    return Ok(());
+                    return Err(AppError::Cancelled);
                 }
                 _ => println!("{}", language.invalid_choice()),
             }
         }
     } else {
         // Non-interactive mode: automatically use the generated message
-        println!("\n[git-ca] Non-interactive mode detected. Using generated commit message.");
+        println!("{}", language.non_interactive_commit());
     }
 
     let name = git_config.get_or_prompt("user.name", &language.enter_name_prompt())?;
@@ -1985,19 +1946,7 @@ fn main() -> Result<()> {
     let signature = Signature::now(&name, &email)?;
     let tree_id = index.write_tree()?;
     let tree = repo.find_tree(tree_id)?;
-    let parents = match repo.head() {
-        Ok(head) => match head.peel_to_commit() {
-            Ok(commit) => vec![commit],
-            Err(err) if matches!(err.code(), ErrorCode::NotFound | ErrorCode::UnbornBranch) => {
-                Vec::new()
-            }
-            Err(err) => return Err(err.into()),
-        },
-        Err(err) if matches!(err.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => {
-            Vec::new()
-        }
-        Err(err) => return Err(err.into()),
-    };
+    let parents: Vec<Commit> = head_commit(&repo)?.into_iter().collect();
     let parent_refs: Vec<&Commit> = parents.iter().collect();
 
     repo.commit(
@@ -2018,30 +1967,17 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn run_doctor(language: &Language) -> Result<()> {
-    println!("Running llama.cpp smoke test…");
-
-    let context_size = DEFAULT_CONTEXT_SIZE;
-    let model_path = get_model_path(language)?;
-
-    println!("Using model: {}", model_path.to_string_lossy());
-    println!("Context length: {}", context_size);
-
-    let mut session = LlamaSession::new(&model_path, context_size).map_err(AppError::from)?;
-
-    let prompt = match language {
-        Language::English => {
-            "You are a helpful assistant. Reply with a short greeting that confirms the model is working, e.g. \"Model ok\".".to_string()
-        }
-        Language::Chinese => {
-            "你是一个乐于助人的助手。请用简短的话确认模型正常工作，例如“模型正常”。".to_string()
-        }
-    };
-
-    println!("\nPrompt:\n{}\n", prompt);
-
-    let response = session.infer(&prompt, 64).map_err(AppError::from)?;
-    println!("Model response:\n{}\n", response.trim());
-
-    Ok(())
+/// The commit `HEAD` points at, or `None` on an unborn branch.
+fn head_commit(repo: &Repository) -> Result<Option<Commit<'_>>> {
+    match repo.head() {
+        Ok(head) => match head.peel_to_commit() {
+            Ok(commit) => Ok(Some(commit)),
+            Err(err) if matches!(err.code(), ErrorCode::NotFound | ErrorCode::UnbornBranch) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        },
+        Err(err) if matches!(err.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
 }
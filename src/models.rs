@@ -0,0 +1,555 @@
+use crate::gguf::{format_parameters, model_files, read_info, GgufInfo};
+use crate::hub::{
+    self, checksum_path, install, record_checksum, recorded_checksum, sha256_file, PullSpec,
+};
+use crate::{
+    assume_yes, canonical_model_path, expand_model_path, find_local_models, get_message_language,
+    get_user_input, is_gguf, is_later_shard, load_persisted_model_path, missing_model_hint,
+    model_record_path, model_records_for, models_root_dir, persist_model_path, select_model_path,
+    write_model_record, AppError, Language, ModelScope, Result,
+};
+use indicatif::HumanBytes;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+/// The default model for commit messages in the configured language.
+fn default_model() -> Option<PathBuf> {
+    load_persisted_model_path(&get_message_language())
+        .map(|stored| canonical_model_path(&expand_model_path(&stored)))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Picks the installed model `name` refers to: an existing path, an exact
+/// file name (with or without `.gguf`), or a unique case-insensitive part of
+/// one.
+fn resolve_model(name: &str, language: &Language) -> Result<PathBuf> {
+    let path = expand_model_path(name);
+    if path.is_file() {
+        return Ok(canonical_model_path(&path));
+    }
+
+    let models = find_local_models();
+    let wanted = name.to_lowercase();
+    let exact = models.iter().find(|model| {
+        let file = file_name(model).to_lowercase();
+        file == wanted || file.strip_suffix(".gguf") == Some(wanted.as_str())
+    });
+    if let Some(model) = exact {
+        return Ok(canonical_model_path(model));
+    }
+
+    let partial: Vec<&PathBuf> = models
+        .iter()
+        .filter(|model| file_name(model).to_lowercase().contains(&wanted))
+        .collect();
+    match partial.as_slice() {
+        [model] => Ok(canonical_model_path(model)),
+        [] => Err(AppError::ModelNotFound(
+            language.model_no_match().replace("{}", name),
+        )),
+        matches => {
+            let mut message = language.model_ambiguous().replace("{}", name);
+            for model in matches {
+                message.push_str("\n  ");
+                message.push_str(&file_name(model));
+            }
+            Err(AppError::Usage(message))
+        }
+    }
+}
+
+/// Total size of a model, counting every shard of a split one.
+fn model_size(path: &Path) -> u64 {
+    model_files(path)
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+fn describe(info: &GgufInfo) -> String {
+    let mut parts = Vec::new();
+    if let Some(quant) = info.quantization() {
+        parts.push(quant.to_string());
+    }
+    if info.parameters > 0 {
+        parts.push(format!("{} params", format_parameters(info.parameters)));
+    }
+    if let Some(context) = info.context_length {
+        parts.push(format!("ctx {context}"));
+    }
+    if let Some((_, count)) = info.split {
+        parts.push(format!("{count} parts"));
+    }
+    parts.join(", ")
+}
+
+fn list(language: &Language) -> Result<()> {
+    let models = find_local_models();
+    if models.is_empty() {
+        println!("{}", language.no_models_found());
+        println!("{}", missing_model_hint(language));
+        return Ok(());
+    }
+
+    let default = default_model();
+    println!("{}", language.model_list_heading());
+    for model in &models {
+        let marker = if default.as_deref() == Some(canonical_model_path(model).as_path()) {
+            '*'
+        } else {
+            ' '
+        };
+        let size = model_size(model);
+        let details = match read_info(model) {
+            Ok(info) => describe(&info),
+            Err(err) => language.model_unreadable().replace("{}", &err.to_string()),
+        };
+        println!(
+            "{marker} {}  {}  {details}",
+            file_name(model),
+            HumanBytes(size)
+        );
+        println!("\x1b[90m    {}\x1b[0m", model.display());
+    }
+    Ok(())
+}
+
+fn info(name: &str, language: &Language) -> Result<()> {
+    let path = resolve_model(name, language)?;
+    let info = read_info(&path).map_err(|err| {
+        AppError::Custom(
+            language
+                .model_info_unreadable()
+                .replace("{path}", &path.to_string_lossy())
+                .replace("{error}", &err.to_string()),
+        )
+    })?;
+    let size = model_size(&path);
+    let unknown = || language.model_info_unknown().to_string();
+    let line = |template: &str, value: &str| println!("{}", template.replace("{}", value));
+
+    line(language.model_info_path(), &path.to_string_lossy());
+    line(language.model_info_size(), &HumanBytes(size).to_string());
+    line(
+        language.model_info_name(),
+        &info.name.clone().unwrap_or_else(unknown),
+    );
+    line(
+        language.model_info_architecture(),
+        &info.architecture.clone().unwrap_or_else(unknown),
+    );
+    line(
+        language.model_info_quantization(),
+        &info
+            .quantization()
+            .map(str::to_string)
+            .or_else(|| {
+                info.file_type.map(|kind| {
+                    language
+                        .model_info_file_type()
+                        .replace("{}", &kind.to_string())
+                })
+            })
+            .unwrap_or_else(unknown),
+    );
+    line(
+        language.model_info_parameters(),
+        &format!(
+            "{}{}",
+            format_parameters(info.parameters),
+            info.size_label
+                .as_ref()
+                .map(|label| language.model_info_size_label().replace("{}", label))
+                .unwrap_or_default()
+        ),
+    );
+    line(
+        language.model_info_context_length(),
+        &info
+            .context_length
+            .map(|length| length.to_string())
+            .unwrap_or_else(unknown),
+    );
+    line(
+        language.model_info_tensors(),
+        &info.tensor_count.to_string(),
+    );
+    line(
+        language.model_info_gguf_version(),
+        &info.version.to_string(),
+    );
+    if let Some((index, count)) = info.split {
+        println!(
+            "{}",
+            language
+                .model_info_split()
+                .replace("{index}", &index.to_string())
+                .replace("{count}", &count.to_string())
+        );
+    }
+    println!(
+        "{}",
+        if default_model().as_deref() == Some(path.as_path()) {
+            language.model_info_default_yes()
+        } else {
+            language.model_info_default_no()
+        }
+    );
+    Ok(())
+}
+
+/// `model use <model> [--local] [--language <code>]`: records the default
+/// model globally or for the current repository, optionally only for commit
+/// messages in one language.
+fn use_model(args: &[&str], language: &Language) -> Result<()> {
+    let usage = || AppError::Usage(language.model_use_usage().to_string());
+    let mut scope = ModelScope::Global;
+    let mut for_language = None;
+    let mut name = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "--local" => scope = ModelScope::Repository,
+            "--global" => scope = ModelScope::Global,
+            "--language" | "--lang" => {
+                let code = iter.next().ok_or_else(usage)?;
+                for_language = Some(Language::from_str(code).ok_or_else(usage)?);
+            }
+            flag if flag.starts_with('-') => return Err(usage()),
+            model if name.is_none() => name = Some(model),
+            _ => return Err(usage()),
+        }
+    }
+    let name = name.ok_or_else(usage)?;
+
+    let path = resolve_model(name, language)?;
+    let record = model_record_path(scope, for_language.as_ref()).ok_or_else(|| match scope {
+        ModelScope::Repository => {
+            AppError::NotInRepository(language.not_in_git_repository().to_string())
+        }
+        ModelScope::Global => AppError::Custom(language.home_dir_unknown().to_string()),
+    })?;
+    write_model_record(scope, &record, &path)?;
+    println!(
+        "{}",
+        language
+            .model_set_as_default()
+            .replace("{}", &path.to_string_lossy())
+    );
+    println!("\x1b[90m    {}\x1b[0m", record.display());
+    Ok(())
+}
+
+/// Deletes a model from the managed models directory. Files elsewhere were
+/// put there by the user and are left alone.
+fn remove(name: &str, language: &Language) -> Result<()> {
+    let path = resolve_model(name, language)?;
+    let root = models_root_dir()?;
+    let root = fs::canonicalize(&root).unwrap_or(root);
+    if !path.starts_with(&root) {
+        return Err(AppError::Usage(
+            language
+                .model_remove_unmanaged()
+                .replace("{path}", &path.to_string_lossy())
+                .replace("{dir}", &root.to_string_lossy()),
+        ));
+    }
+
+    if !assume_yes() && io::stdin().is_terminal() {
+        let answer = get_user_input(
+            &language
+                .model_confirm_remove()
+                .replace("{}", &path.to_string_lossy()),
+        )?;
+        if !answer.eq_ignore_ascii_case("y") {
+            println!("{}", language.commit_cancelled());
+            return Err(AppError::Cancelled);
+        }
+    }
+
+    let records = model_records_for(&path);
+    for file in model_files(&path) {
+        fs::remove_file(&file)?;
+        let _ = fs::remove_file(checksum_path(&file));
+    }
+    println!(
+        "{}",
+        language
+            .model_removed()
+            .replace("{}", &path.to_string_lossy())
+    );
+    for record in &records {
+        if let Err(err) = fs::remove_file(record) {
+            eprintln!(
+                "[git-ca] warning: could not clear cached model path ({}): {err}",
+                record.display()
+            );
+        }
+    }
+    if !records.is_empty() {
+        println!("{}", language.model_default_cleared());
+    }
+    Ok(())
+}
+
+/// Re-hashes model files and compares them with the checksums recorded when
+/// they were pulled; models added by hand are only checked to be GGUF.
+fn verify(name: Option<&str>, language: &Language) -> Result<()> {
+    let models = match name {
+        Some(name) => vec![resolve_model(name, language)?],
+        None => find_local_models(),
+    };
+    if models.is_empty() {
+        println!("{}", language.no_models_found());
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for model in &models {
+        if let Err(err) = read_info(model) {
+            failed += 1;
+            println!(
+                "{}",
+                language
+                    .model_verify_unreadable()
+                    .replace("{path}", &model.to_string_lossy())
+                    .replace("{error}", &err.to_string())
+            );
+            continue;
+        }
+        for file in model_files(model) {
+            let actual = sha256_file(&file)?;
+            match recorded_checksum(&file) {
+                Some(expected) if expected == actual => println!(
+                    "{}",
+                    language
+                        .model_verify_ok()
+                        .replace("{}", &file.to_string_lossy())
+                ),
+                Some(expected) => {
+                    failed += 1;
+                    println!(
+                        "{}",
+                        language
+                            .model_verify_mismatch()
+                            .replace("{path}", &file.to_string_lossy())
+                            .replace("{actual}", &actual)
+                            .replace("{expected}", &expected)
+                    );
+                }
+                None => println!(
+                    "{}",
+                    language
+                        .model_verify_no_checksum()
+                        .replace("{path}", &file.to_string_lossy())
+                        .replace("{actual}", &actual)
+                ),
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(AppError::Custom(
+            language
+                .model_verify_failed()
+                .replace("{}", &failed.to_string()),
+        ));
+    }
+    Ok(())
+}
+
+/// Imports a GGUF model, or every model in a directory such as a copied
+/// mirror, into the managed models directory without touching the network.
+fn add(source: &str, language: &Language) -> Result<()> {
+    let source = expand_model_path(source);
+    let models = if source.is_dir() {
+        let mut found: Vec<PathBuf> = fs::read_dir(&source)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_gguf(path) && !is_later_shard(path))
+            .collect();
+        found.sort();
+        found
+    } else if source.is_file() {
+        vec![source.clone()]
+    } else {
+        return Err(AppError::ModelNotFound(
+            language
+                .model_file_missing()
+                .replace("{}", &source.to_string_lossy()),
+        ));
+    };
+    if models.is_empty() {
+        return Err(AppError::ModelNotFound(
+            language
+                .model_add_none_found()
+                .replace("{}", &source.to_string_lossy()),
+        ));
+    }
+
+    let dest_dir = models_root_dir()?;
+    fs::create_dir_all(&dest_dir)?;
+    let mut first_added = None;
+    for model in &models {
+        let info = read_info(model).map_err(|err| {
+            AppError::Custom(
+                language
+                    .model_add_unusable()
+                    .replace("{path}", &model.to_string_lossy())
+                    .replace("{error}", &err.to_string()),
+            )
+        })?;
+        let files = model_files(model);
+        if let Some((_, count)) = info.split {
+            if files.len() != usize::from(count) {
+                return Err(AppError::Custom(
+                    language
+                        .model_add_missing_parts()
+                        .replace("{path}", &model.to_string_lossy())
+                        .replace("{count}", &count.to_string())
+                        .replace("{found}", &files.len().to_string()),
+                ));
+            }
+        }
+
+        for file in &files {
+            let dest = dest_dir.join(file_name(file));
+            let sha256 = sha256_file(file)?;
+            if dest.is_file() {
+                if sha256_file(&dest)? != sha256 {
+                    return Err(AppError::Usage(
+                        language
+                            .model_add_conflict()
+                            .replace("{}", &dest.to_string_lossy()),
+                    ));
+                }
+                println!(
+                    "{}",
+                    language
+                        .model_already_added()
+                        .replace("{}", &dest.to_string_lossy())
+                );
+            } else {
+                install(file, &dest, false)?;
+                println!(
+                    "{}",
+                    language
+                        .model_added()
+                        .replace("{}", &dest.to_string_lossy())
+                );
+            }
+            record_checksum(&dest, &sha256)?;
+        }
+        println!("\x1b[90m    {}\x1b[0m", describe(&info));
+        first_added.get_or_insert_with(|| dest_dir.join(file_name(model)));
+    }
+
+    if let Some(path) =
+        first_added.filter(|_| load_persisted_model_path(&get_message_language()).is_none())
+    {
+        let path = canonical_model_path(&path);
+        persist_model_path(&path);
+        println!(
+            "{}",
+            language
+                .model_set_as_default()
+                .replace("{}", &path.to_string_lossy())
+        );
+    }
+    Ok(())
+}
+
+/// Parses `<repo>[@<revision>] [--file <name>] [--quant <type>]`.
+fn parse_pull_args(args: &[&str]) -> Option<PullSpec> {
+    let mut spec: Option<PullSpec> = None;
+    let (mut file, mut quant) = (None, None);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value)),
+            _ => (*arg, None),
+        };
+        let slot = match flag {
+            "--file" => &mut file,
+            "--quant" => &mut quant,
+            _ if flag.starts_with('-') || spec.is_some() => return None,
+            _ => {
+                spec = Some(PullSpec::new(arg));
+                continue;
+            }
+        };
+        let value = inline.or_else(|| iter.next().copied())?;
+        *slot = Some(value.to_string());
+    }
+
+    let mut spec = spec?;
+    spec.file = file;
+    spec.quant = quant;
+    Some(spec)
+}
+
+fn pull(args: &[&str], language: &Language) -> Result<()> {
+    let Some(spec) = parse_pull_args(args) else {
+        return Err(AppError::Usage(language.model_pull_usage().to_string()));
+    };
+    let downloaded = hub::download(&spec, language)?;
+    persist_model_path(&downloaded);
+    println!(
+        "{}",
+        language
+            .model_set_as_default()
+            .replace("{}", &downloaded.to_string_lossy())
+    );
+    Ok(())
+}
+
+/// Entry point for `git ca model [list|info|use|rm|verify|add|pull]`; without a
+/// subcommand the default model is chosen interactively.
+pub fn run_command(args: &[String], language: &Language) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] => select_model_path(language).map(|_| ()),
+        ["list"] | ["ls"] => list(language),
+        ["info", name] => info(name, language),
+        ["use", rest @ ..] => use_model(rest, language),
+        ["rm", name] | ["remove", name] => remove(name, language),
+        ["verify"] => verify(None, language),
+        ["verify", name] => verify(Some(name), language),
+        ["add", source] => add(source, language),
+        ["add", ..] => Err(AppError::Usage(language.model_add_usage().to_string())),
+        ["pull", rest @ ..] => pull(rest, language),
+        _ => {
+            println!("{}", language.model_usage());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pull_arguments() {
+        let spec = parse_pull_args(&["org/model@dev", "--quant", "Q8_0"]).expect("spec");
+        assert_eq!(spec.repo, "org/model");
+        assert_eq!(spec.revision.as_deref(), Some("dev"));
+        assert_eq!(spec.quant.as_deref(), Some("Q8_0"));
+
+        let spec = parse_pull_args(&["--file=model-Q5_K_M.gguf", "org/model"]).expect("spec");
+        assert_eq!(spec.file.as_deref(), Some("model-Q5_K_M.gguf"));
+        assert_eq!(spec.revision, None);
+
+        assert_eq!(parse_pull_args(&[]), None);
+        assert_eq!(parse_pull_args(&["org/model", "--quant"]), None);
+        assert_eq!(parse_pull_args(&["org/model", "--bogus", "x"]), None);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::fallback::read_multivar;
+use crate::llama::{FlashAttention, KvCacheType, Mirostat, SamplingOptions, SessionOptions};
+use crate::{AppError, Result};
+use git2::Config;
+use std::fs;
+use std::str::FromStr;
+
+const CONFIG_CONTEXT_SIZE_KEY: &str = "commit-analyzer.llama.contextSize";
+const CONFIG_THREADS_KEY: &str = "commit-analyzer.llama.threads";
+const CONFIG_THREADS_BATCH_KEY: &str = "commit-analyzer.llama.threadsBatch";
+const CONFIG_BATCH_SIZE_KEY: &str = "commit-analyzer.llama.batchSize";
+const CONFIG_UBATCH_SIZE_KEY: &str = "commit-analyzer.llama.ubatchSize";
+const CONFIG_GPU_LAYERS_KEY: &str = "commit-analyzer.llama.gpuLayers";
+const CONFIG_MMAP_KEY: &str = "commit-analyzer.llama.mmap";
+const CONFIG_MLOCK_KEY: &str = "commit-analyzer.llama.mlock";
+const CONFIG_FLASH_ATTENTION_KEY: &str = "commit-analyzer.llama.flashAttention";
+const CONFIG_KV_CACHE_TYPE_KEY: &str = "commit-analyzer.llama.kvCacheType";
+const CONFIG_TEMPERATURE_KEY: &str = "commit-analyzer.sampling.temperature";
+const CONFIG_TOP_K_KEY: &str = "commit-analyzer.sampling.topK";
+const CONFIG_TOP_P_KEY: &str = "commit-analyzer.sampling.topP";
+const CONFIG_MIN_P_KEY: &str = "commit-analyzer.sampling.minP";
+const CONFIG_TYPICAL_P_KEY: &str = "commit-analyzer.sampling.typicalP";
+const CONFIG_REPEAT_PENALTY_KEY: &str = "commit-analyzer.sampling.repeatPenalty";
+const CONFIG_FREQUENCY_PENALTY_KEY: &str = "commit-analyzer.sampling.frequencyPenalty";
+const CONFIG_PRESENCE_PENALTY_KEY: &str = "commit-analyzer.sampling.presencePenalty";
+const CONFIG_PENALTY_LAST_N_KEY: &str = "commit-analyzer.sampling.penaltyLastN";
+const CONFIG_MIROSTAT_KEY: &str = "commit-analyzer.sampling.mirostat";
+const CONFIG_MIROSTAT_TAU_KEY: &str = "commit-analyzer.sampling.mirostatTau";
+const CONFIG_MIROSTAT_ETA_KEY: &str = "commit-analyzer.sampling.mirostatEta";
+const CONFIG_DRY_MULTIPLIER_KEY: &str = "commit-analyzer.sampling.dryMultiplier";
+const CONFIG_DRY_BASE_KEY: &str = "commit-analyzer.sampling.dryBase";
+const CONFIG_DRY_ALLOWED_LENGTH_KEY: &str = "commit-analyzer.sampling.dryAllowedLength";
+const CONFIG_DRY_PENALTY_LAST_N_KEY: &str = "commit-analyzer.sampling.dryPenaltyLastN";
+const CONFIG_GRAMMAR_FILE_KEY: &str = "commit-analyzer.sampling.grammarFile";
+const CONFIG_SEED_KEY: &str = "commit-analyzer.sampling.seed";
+const CONFIG_STOP_KEY: &str = "commit-analyzer.sampling.stop";
+
+/// Model and context settings given on the command line. They override the
+/// `commit-analyzer.llama.*` config keys for this run.
+#[derive(Debug, Default)]
+pub struct LlamaFlags {
+    ctx_size: Option<i32>,
+    threads: Option<i32>,
+    threads_batch: Option<i32>,
+    batch_size: Option<u32>,
+    ubatch_size: Option<u32>,
+    gpu_layers: Option<i32>,
+    mmap: Option<bool>,
+    mlock: Option<bool>,
+    flash_attention: Option<FlashAttention>,
+    kv_cache_type: Option<KvCacheType>,
+    /// The flags as given, so they can be passed on to `git ca daemon run`.
+    raw: Vec<String>,
+}
+
+impl LlamaFlags {
+    /// Removes the recognised flags from `args`. Both `--flag value` and
+    /// `--flag=value` are accepted.
+    pub fn extract(args: &mut Vec<String>) -> Result<Self> {
+        let mut flags = Self::default();
+        let mut remaining = Vec::with_capacity(args.len());
+        let mut iter = std::mem::take(args).into_iter();
+
+        while let Some(arg) = iter.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+
+            let switch = match name.as_str() {
+                "--mmap" => Some((&mut flags.mmap, true)),
+                "--no-mmap" => Some((&mut flags.mmap, false)),
+                "--mlock" => Some((&mut flags.mlock, true)),
+                "--no-mlock" => Some((&mut flags.mlock, false)),
+                _ => None,
+            };
+            if let Some((slot, value)) = switch {
+                if inline.is_some() {
+                    return Err(AppError::Usage(format!("{name} does not take a value")));
+                }
+                *slot = Some(value);
+                flags.raw.push(arg);
+                continue;
+            }
+
+            if !matches!(
+                name.as_str(),
+                "--ctx-size"
+                    | "--threads"
+                    | "--threads-batch"
+                    | "--batch-size"
+                    | "--ubatch-size"
+                    | "--gpu-layers"
+                    | "--flash-attn"
+                    | "--kv-cache-type"
+            ) {
+                remaining.push(arg);
+                continue;
+            }
+
+            let value = match inline {
+                Some(value) => value,
+                None => iter
+                    .next()
+                    .ok_or_else(|| AppError::Usage(format!("{name} requires a value")))?,
+            };
+            match name.as_str() {
+                "--ctx-size" => {
+                    let size: i32 = parse_flag(&name, &value)?;
+                    if size <= 0 {
+                        return Err(invalid_value(&name, &value, "expected a positive number"));
+                    }
+                    flags.ctx_size = Some(size);
+                }
+                "--threads" => flags.threads = Some(parse_flag(&name, &value)?),
+                "--threads-batch" => flags.threads_batch = Some(parse_flag(&name, &value)?),
+                "--batch-size" => flags.batch_size = Some(parse_flag(&name, &value)?),
+                "--ubatch-size" => flags.ubatch_size = Some(parse_flag(&name, &value)?),
+                "--gpu-layers" => flags.gpu_layers = Some(parse_flag(&name, &value)?),
+                "--flash-attn" => {
+                    flags.flash_attention =
+                        Some(FlashAttention::parse(&value).ok_or_else(|| {
+                            invalid_value(&name, &value, "expected auto, on or off")
+                        })?)
+                }
+                _ => {
+                    flags.kv_cache_type = Some(KvCacheType::parse(&value).ok_or_else(|| {
+                        invalid_value(
+                            &name,
+                            &value,
+                            "expected f32, f16, bf16, q8_0, q5_1, q5_0, q4_1 or q4_0",
+                        )
+                    })?)
+                }
+            }
+            flags.raw.push(format!("{name}={value}"));
+        }
+
+        *args = remaining;
+        Ok(flags)
+    }
+
+    pub fn raw(&self) -> &[String] {
+        &self.raw
+    }
+}
+
+fn invalid_value(name: &str, value: &str, hint: &str) -> AppError {
+    AppError::Usage(format!("Invalid value '{value}' for {name}: {hint}"))
+}
+
+fn parse_flag<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_value(name, value, "expected a non-negative number"))
+}
+
+/// Reads an optional config value, warning about and ignoring values that do
+/// not parse so a typo does not break every commit.
+fn config_value<T>(config: &Config, key: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let value = config.get_string(key).ok()?;
+    let parsed = parse(&value);
+    if parsed.is_none() {
+        eprintln!("[git-ca] warning: ignoring invalid value '{value}' for {key}");
+    }
+    parsed
+}
+
+fn parse_count<T: FromStr>(value: &str) -> Option<T> {
+    value.trim().parse().ok()
+}
+
+fn parse_float(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|penalty| penalty.is_finite())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Combines the config keys and `flags` into the options a session is loaded
+/// with; anything left unset keeps the session's defaults, and `n_ctx` is the
+/// context size used when none is configured.
+pub fn session_options(config: &Config, flags: &LlamaFlags, n_ctx: i32) -> SessionOptions {
+    let n_ctx = flags
+        .ctx_size
+        .or_else(|| {
+            config_value(config, CONFIG_CONTEXT_SIZE_KEY, |value| {
+                parse_count(value).filter(|size: &i32| *size > 0)
+            })
+        })
+        .unwrap_or(n_ctx);
+    SessionOptions {
+        threads: flags
+            .threads
+            .or_else(|| config_value(config, CONFIG_THREADS_KEY, parse_count)),
+        threads_batch: flags
+            .threads_batch
+            .or_else(|| config_value(config, CONFIG_THREADS_BATCH_KEY, parse_count)),
+        batch_size: flags
+            .batch_size
+            .or_else(|| config_value(config, CONFIG_BATCH_SIZE_KEY, parse_count)),
+        ubatch_size: flags
+            .ubatch_size
+            .or_else(|| config_value(config, CONFIG_UBATCH_SIZE_KEY, parse_count)),
+        gpu_layers: flags
+            .gpu_layers
+            .or_else(|| config_value(config, CONFIG_GPU_LAYERS_KEY, parse_count)),
+        mmap: flags
+            .mmap
+            .or_else(|| config_value(config, CONFIG_MMAP_KEY, parse_bool)),
+        mlock: flags
+            .mlock
+            .or_else(|| config_value(config, CONFIG_MLOCK_KEY, parse_bool)),
+        flash_attention: flags
+            .flash_attention
+            .or_else(|| config_value(config, CONFIG_FLASH_ATTENTION_KEY, FlashAttention::parse)),
+        kv_cache_type: flags
+            .kv_cache_type
+            .or_else(|| config_value(config, CONFIG_KV_CACHE_TYPE_KEY, KvCacheType::parse)),
+        sampling: sampling_options(config),
+        ..SessionOptions::new(n_ctx)
+    }
+}
+
+/// Reads the GBNF grammar named by `commit-analyzer.sampling.grammarFile`.
+fn grammar_file(config: &Config) -> Option<String> {
+    let path = config.get_path(CONFIG_GRAMMAR_FILE_KEY).ok()?;
+    match fs::read_to_string(&path) {
+        Ok(grammar) => Some(grammar),
+        Err(err) => {
+            eprintln!(
+                "[git-ca] warning: ignoring grammar file {}: {err}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Sampler settings from `commit-analyzer.sampling.*`. Stop strings are a
+/// multi-valued key, with `\n` and `\t` escapes understood.
+fn sampling_options(config: &Config) -> SamplingOptions {
+    let defaults = SamplingOptions::default();
+    SamplingOptions {
+        temperature: config_value(config, CONFIG_TEMPERATURE_KEY, parse_float)
+            .unwrap_or(defaults.temperature),
+        top_k: config_value(config, CONFIG_TOP_K_KEY, parse_count).unwrap_or(defaults.top_k),
+        top_p: config_value(config, CONFIG_TOP_P_KEY, parse_float).unwrap_or(defaults.top_p),
+        min_p: config_value(config, CONFIG_MIN_P_KEY, parse_float).unwrap_or(defaults.min_p),
+        typical_p: config_value(config, CONFIG_TYPICAL_P_KEY, parse_float)
+            .unwrap_or(defaults.typical_p),
+        repeat_penalty: config_value(config, CONFIG_REPEAT_PENALTY_KEY, |value| {
+            parse_float(value).filter(|penalty| *penalty > 0.0)
+        })
+        .unwrap_or(defaults.repeat_penalty),
+        frequency_penalty: config_value(config, CONFIG_FREQUENCY_PENALTY_KEY, parse_float)
+            .unwrap_or(defaults.frequency_penalty),
+        presence_penalty: config_value(config, CONFIG_PRESENCE_PENALTY_KEY, parse_float)
+            .unwrap_or(defaults.presence_penalty),
+        penalty_last_n: config_value(config, CONFIG_PENALTY_LAST_N_KEY, parse_count)
+            .unwrap_or(defaults.penalty_last_n),
+        mirostat: config_value(config, CONFIG_MIROSTAT_KEY, Mirostat::parse)
+            .unwrap_or(defaults.mirostat),
+        mirostat_tau: config_value(config, CONFIG_MIROSTAT_TAU_KEY, parse_float)
+            .unwrap_or(defaults.mirostat_tau),
+        mirostat_eta: config_value(config, CONFIG_MIROSTAT_ETA_KEY, parse_float)
+            .unwrap_or(defaults.mirostat_eta),
+        dry_multiplier: config_value(config, CONFIG_DRY_MULTIPLIER_KEY, parse_float)
+            .unwrap_or(defaults.dry_multiplier),
+        dry_base: config_value(config, CONFIG_DRY_BASE_KEY, parse_float)
+            .unwrap_or(defaults.dry_base),
+        dry_allowed_length: config_value(config, CONFIG_DRY_ALLOWED_LENGTH_KEY, parse_count)
+            .unwrap_or(defaults.dry_allowed_length),
+        dry_penalty_last_n: config_value(config, CONFIG_DRY_PENALTY_LAST_N_KEY, parse_count)
+            .unwrap_or(defaults.dry_penalty_last_n),
+        grammar: grammar_file(config),
+        seed: config_value(config, CONFIG_SEED_KEY, parse_count),
+        stop: read_multivar(config, CONFIG_STOP_KEY)
+            .iter()
+            .map(|stop| stop.replace("\\n", "\n").replace("\\t", "\t"))
+            .filter(|stop| !stop.is_empty())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn extracts_llama_flags_from_arguments() {
+        let mut argv = args(&[
+            "git-ca",
+            "--threads",
+            "6",
+            "split",
+            "--no-mmap",
+            "--flash-attn=on",
+            "--kv-cache-type",
+            "Q8_0",
+        ]);
+        let flags = LlamaFlags::extract(&mut argv).expect("flags");
+        assert_eq!(argv, args(&["git-ca", "split"]));
+        assert_eq!(flags.threads, Some(6));
+        assert_eq!(flags.mmap, Some(false));
+        assert_eq!(flags.flash_attention, Some(FlashAttention::Enabled));
+        assert_eq!(flags.kv_cache_type, Some(KvCacheType::Q8_0));
+        assert_eq!(
+            flags.raw(),
+            args(&[
+                "--threads=6",
+                "--no-mmap",
+                "--flash-attn=on",
+                "--kv-cache-type=Q8_0"
+            ])
+        );
+
+        assert!(LlamaFlags::extract(&mut args(&["git-ca", "--batch-size"])).is_err());
+        assert!(LlamaFlags::extract(&mut args(&["git-ca", "--threads", "many"])).is_err());
+        assert!(LlamaFlags::extract(&mut args(&["git-ca", "--mlock=yes"])).is_err());
+        assert!(LlamaFlags::extract(&mut args(&["git-ca", "--ctx-size", "0"])).is_err());
+        assert!(LlamaFlags::extract(&mut args(&["git-ca", "--ctx-size=-5"])).is_err());
+    }
+
+    #[test]
+    fn flags_override_config_values() {
+        let dir = std::env::temp_dir().join(format!("git-ca-options-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let path = dir.join("config");
+        std::fs::write(
+            &path,
+            "[commit-analyzer \"llama\"]\n\tthreads = 2\n\tbatchSize = 256\n\tmlock = true\n\tkvCacheType = bogus\n\
+             [commit-analyzer \"sampling\"]\n\tpresencePenalty = 0.5\n\tmirostat = 2\n\ttopK = 0\n\tpenaltyLastN = 16\n\
+             \tstop = \"\\\\n\\\\n\"\n\tstop = Explanation:\n",
+        )
+        .expect("config");
+        let config = Config::open(&path).expect("open config");
+
+        let mut argv = args(&["git-ca", "--threads", "8"]);
+        let flags = LlamaFlags::extract(&mut argv).expect("flags");
+        let options = session_options(&config, &flags, 1024);
+        let mut argv = args(&["git-ca", "--ctx-size=4096"]);
+        let sized = session_options(
+            &config,
+            &LlamaFlags::extract(&mut argv).expect("flags"),
+            1024,
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(options.n_ctx, 1024);
+        assert_eq!(sized.n_ctx, 4096);
+        assert_eq!(options.threads, Some(8));
+        assert_eq!(options.batch_size, Some(256));
+        assert_eq!(options.mlock, Some(true));
+        assert_eq!(options.kv_cache_type, None);
+        assert_eq!(options.gpu_layers, None);
+        assert_eq!(options.sampling.presence_penalty, 0.5);
+        assert_eq!(options.sampling.penalty_last_n, 16);
+        assert_eq!(options.sampling.mirostat, Mirostat::V2);
+        assert_eq!(options.sampling.top_k, 0);
+        assert_eq!(options.sampling.seed, None);
+        assert_eq!(options.sampling.repeat_penalty, 1.1);
+        assert_eq!(options.sampling.stop, vec!["\n\n", "Explanation:"]);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::Language;
+use hf_hub::api::Progress;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Live view of a generation on stderr: a spinner with the elapsed time until
+/// the first token arrives, then the tokens themselves, then the throughput.
+///
+/// Nothing is drawn when stderr is not a terminal.
+pub struct GenerationProgress {
+    spinner: Option<ProgressBar>,
+    live: bool,
+    first_token: Option<Instant>,
+    tokens: usize,
+}
+
+impl GenerationProgress {
+    pub fn start(language: &Language) -> Self {
+        let live = io::stderr().is_terminal();
+        let spinner = live.then(|| {
+            let spinner = ProgressBar::new_spinner();
+            spinner.set_style(
+                ProgressStyle::with_template("{spinner} {msg} {elapsed}")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            );
+            spinner.set_message(language.waiting_for_first_token());
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner
+        });
+
+        Self {
+            spinner,
+            live,
+            first_token: None,
+            tokens: 0,
+        }
+    }
+
+    pub fn on_token(&mut self, piece: &str) {
+        if self.first_token.is_none() {
+            self.first_token = Some(Instant::now());
+            if let Some(spinner) = self.spinner.take() {
+                spinner.finish_and_clear();
+            }
+        }
+        self.tokens += 1;
+
+        if self.live {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "\x1b[90m{piece}\x1b[0m");
+            let _ = stderr.flush();
+        }
+    }
+
+    pub fn finish(mut self, language: &Language) {
+        if let Some(spinner) = self.spinner.take() {
+            spinner.finish_and_clear();
+        }
+        let Some(first_token) = self.first_token else {
+            return;
+        };
+        if !self.live {
+            return;
+        }
+
+        let seconds = first_token.elapsed().as_secs_f64();
+        let rate = if seconds > 0.0 {
+            self.tokens as f64 / seconds
+        } else {
+            0.0
+        };
+        eprintln!(
+            "\n\x1b[90m{}\x1b[0m",
+            language
+                .generation_stats()
+                .replace("{tokens}", &self.tokens.to_string())
+                .replace("{seconds}", &format!("{seconds:.1}"))
+                .replace("{rate}", &format!("{rate:.1}"))
+        );
+    }
+}
+
+/// Progress bar on stderr for one downloaded file: bytes so far, throughput
+/// and time left. Hidden when stderr is not a terminal.
+pub struct DownloadProgress {
+    bar: ProgressBar,
+    /// The label as shown once the download resumes.
+    resumed: String,
+    /// Set until the first update after `init`, which reports the bytes a
+    /// resumed download already had on disk.
+    starting: bool,
+}
+
+impl DownloadProgress {
+    pub fn new(label: String, language: &Language) -> Self {
+        let bar = if io::stderr().is_terminal() {
+            ProgressBar::new(0)
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{wide_bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> "),
+        );
+        let resumed = language.download_resumed().replace("{label}", &label);
+        bar.set_message(label);
+        Self {
+            bar,
+            resumed,
+            starting: true,
+        }
+    }
+}
+
+impl Progress for DownloadProgress {
+    fn init(&mut self, size: usize, _filename: &str) {
+        self.bar.set_length(size as u64);
+        self.bar.set_position(0);
+        self.starting = true;
+    }
+
+    fn update(&mut self, size: usize) {
+        if !self.starting {
+            self.bar.inc(size as u64);
+            return;
+        }
+        // Counting resumed bytes as downloaded now would inflate the rate.
+        self.starting = false;
+        self.bar.set_position(size as u64);
+        self.bar.reset_eta();
+        if size > 0 {
+            self.bar.set_message(
+                self.resumed
+                    .replace("{size}", &HumanBytes(size as u64).to_string()),
+            );
+        }
+    }
+
+    fn finish(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
@@ -0,0 +1,839 @@
+use crate::cancel::{AbortReason, AbortSignal, InterruptGuard};
+use crate::diff::{apply_hunks, index_diff, staged_diff, ChangeKind, StagedDiff};
+use crate::fallback::{file_scope, generate_fallback_commit_message, slugify, FallbackRules};
+use crate::llama::{Generator, LlamaError, SessionOptions};
+use crate::{
+    describe_file_change, find_git_repository, generate_commit_message, get_message_language,
+    get_model_path, get_user_input, head_commit, inference_timeout, load_generator, AppError,
+    GitConfig, Language, Result,
+};
+use git2::{Commit, Index, IndexEntry, Oid, Repository, Signature};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::time::Duration;
+
+/// Most units the model is asked to group; larger splits keep the path-based
+/// grouping.
+const MAX_MODEL_UNITS: usize = 60;
+const GROUPING_MAX_TOKENS: usize = 256;
+/// Changed lines shown to the model for each unit, when the prompt fits.
+const UNIT_PREVIEW_LINES: usize = 4;
+const UNIT_PREVIEW_CHARS: usize = 100;
+
+/// A piece of the staged changes that goes into one commit: a single hunk of
+/// a modified text file, or a whole file otherwise.
+#[derive(Debug, Clone, PartialEq)]
+struct SplitUnit {
+    /// The staged path, followed by the source of a rename so no commit
+    /// contains half of one.
+    paths: Vec<String>,
+    /// The hunk's number in the file's diff, and how many hunks it has.
+    hunk: Option<(usize, usize)>,
+    scope: String,
+    /// What the model sees: the path with a hunk header or change notes.
+    label: String,
+    /// Changed lines of the hunk or file.
+    lines: Vec<String>,
+}
+
+/// Units that will be committed together.
+#[derive(Debug, PartialEq)]
+struct SplitGroup {
+    scope: String,
+    units: Vec<SplitUnit>,
+}
+
+/// One proposed commit: the group, the tree it produces on top of the
+/// previous proposal, and the diff against that previous tree.
+struct PlannedCommit {
+    group: SplitGroup,
+    tree: Oid,
+    diff: StagedDiff,
+    message: String,
+}
+
+/// Breaks the staged diff into units. Modified text files with several hunks
+/// contribute one unit per hunk so they can be spread over commits; every
+/// other file moves as a whole.
+fn split_units(diff: &StagedDiff, rules: &FallbackRules, language: &Language) -> Vec<SplitUnit> {
+    let mut units = Vec::new();
+
+    for file in &diff.files {
+        let scope = file_scope(file, rules);
+        let headers: Vec<usize> = file
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with("@@"))
+            .map(|(index, _)| index)
+            .collect();
+
+        if file.kind == ChangeKind::Modified && !file.binary && headers.len() > 1 {
+            for (number, &start) in headers.iter().enumerate() {
+                let end = headers.get(number + 1).copied().unwrap_or(file.lines.len());
+                units.push(SplitUnit {
+                    paths: vec![file.path.clone()],
+                    hunk: Some((number, headers.len())),
+                    scope: scope.clone(),
+                    label: format!("{} {}", file.path, file.lines[start]),
+                    lines: file.lines[start + 1..end].to_vec(),
+                });
+            }
+            continue;
+        }
+
+        let mut paths = vec![file.path.clone()];
+        if let ChangeKind::Renamed { from } = &file.kind {
+            paths.push(from.clone());
+        }
+        let notes = describe_file_change(file, language);
+        units.push(SplitUnit {
+            paths,
+            hunk: None,
+            scope,
+            label: if notes.is_empty() {
+                file.path.clone()
+            } else {
+                format!("{} ({notes})", file.path)
+            },
+            lines: file
+                .lines
+                .iter()
+                .filter(|line| !line.starts_with("@@"))
+                .cloned()
+                .collect(),
+        });
+    }
+
+    units
+}
+
+/// Collects `units` into groups named by `scope_of`, keeping first-seen order.
+fn collect_groups(
+    units: &[SplitUnit],
+    scope_of: impl Fn(usize, &SplitUnit) -> String,
+) -> Vec<SplitGroup> {
+    let mut groups: Vec<SplitGroup> = Vec::new();
+    for (number, unit) in units.iter().enumerate() {
+        let scope = scope_of(number, unit);
+        match groups.iter_mut().find(|group| group.scope == scope) {
+            Some(group) => group.units.push(unit.clone()),
+            None => groups.push(SplitGroup {
+                scope,
+                units: vec![unit.clone()],
+            }),
+        }
+    }
+    groups
+}
+
+/// Groups units by the scope their path maps to, the starting point the model
+/// is asked to refine.
+fn plan_groups(units: &[SplitUnit]) -> Vec<SplitGroup> {
+    collect_groups(units, |_, unit| unit.scope.clone())
+}
+
+/// Numbers the units for the grouping prompt, with up to `preview` changed
+/// lines each.
+fn render_units(units: &[SplitUnit], preview: usize) -> String {
+    let mut text = String::new();
+    for (number, unit) in units.iter().enumerate() {
+        text.push_str(&format!("{}. {}\n", number + 1, unit.label));
+        for line in unit
+            .lines
+            .iter()
+            .filter(|line| line.starts_with(['+', '-']))
+            .take(preview)
+        {
+            let line: String = line.chars().take(UNIT_PREVIEW_CHARS).collect();
+            text.push_str(&format!("   {line}\n"));
+        }
+    }
+    text
+}
+
+/// Renders groups the way the model is asked to answer: `scope: 1 2`.
+fn render_groups(units: &[SplitUnit], groups: &[SplitGroup]) -> String {
+    let mut text = String::new();
+    for group in groups {
+        let numbers: Vec<String> = group
+            .units
+            .iter()
+            .filter_map(|unit| units.iter().position(|candidate| candidate == unit))
+            .map(|index| (index + 1).to_string())
+            .collect();
+        text.push_str(&format!("{}: {}\n", group.scope, numbers.join(" ")));
+    }
+    text
+}
+
+/// Reads the model's `scope: 1 2` lines into one scope per unit. Other lines
+/// are ignored; `None` unless every unit is placed exactly once.
+fn parse_grouping(response: &str, units: usize) -> Option<Vec<String>> {
+    let mut assigned: Vec<Option<String>> = vec![None; units];
+    for line in response.lines() {
+        let line = line.trim().trim_start_matches(['-', '*', '•', ' ']);
+        let Some((scope, numbers)) = line.split_once(':') else {
+            continue;
+        };
+        let scope = slugify(scope);
+        let numbers: Option<Vec<usize>> = numbers
+            .split([' ', ',', '`'])
+            .filter(|word| !word.is_empty())
+            .map(|word| word.trim_start_matches('#').parse().ok())
+            .collect();
+        let Some(numbers) = numbers.filter(|_| !scope.is_empty()) else {
+            continue;
+        };
+        for number in numbers {
+            let slot = assigned.get_mut(number.checked_sub(1)?)?;
+            if slot.is_some() {
+                return None;
+            }
+            *slot = Some(scope.clone());
+        }
+    }
+    assigned.into_iter().collect()
+}
+
+/// Asks the model to regroup `units`, starting from the path-based `groups`:
+/// it may merge scopes that belong together or move unrelated hunks of one
+/// file apart. Returns `None` when there is nothing usable to show for it, and
+/// an error only when the user interrupted.
+fn model_groups(
+    session: &mut dyn Generator,
+    units: &[SplitUnit],
+    groups: &[SplitGroup],
+    message_language: &Language,
+) -> Result<Option<Vec<SplitGroup>>> {
+    if units.len() > MAX_MODEL_UNITS {
+        return Ok(None);
+    }
+    let budget = session.context_size().saturating_sub(GROUPING_MAX_TOKENS);
+    let render = |preview: usize| {
+        message_language
+            .split_grouping_prompt()
+            .replace("{changes}", &render_units(units, preview))
+            .replace("{groups}", &render_groups(units, groups))
+    };
+
+    let mut prompt = None;
+    for preview in [UNIT_PREVIEW_LINES, 0] {
+        let candidate = render(preview);
+        match session.count_tokens(&candidate) {
+            Ok(tokens) if tokens <= budget => {
+                prompt = Some(candidate);
+                break;
+            }
+            Ok(_) => {}
+            Err(_) => return Ok(None),
+        }
+    }
+    let Some(prompt) = prompt else {
+        return Ok(None);
+    };
+
+    let response = session.infer(&prompt, GROUPING_MAX_TOKENS);
+    if session.abort_reason() == Some(AbortReason::Interrupted) {
+        return Err(AppError::Llama(LlamaError::Aborted(
+            AbortReason::Interrupted,
+        )));
+    }
+    Ok(response
+        .ok()
+        .and_then(|response| parse_grouping(&response, units.len()))
+        .map(|scopes| collect_groups(units, |number, _| scopes[number].clone())))
+}
+
+/// Copies the staged entries for `paths` into `partial`, removing paths that
+/// are no longer staged.
+fn stage_paths(partial: &mut Index, staged: &Index, paths: &[String]) -> Result<()> {
+    for path in paths {
+        let path = Path::new(path);
+        match staged.get_path(path, 0) {
+            Some(entry) => partial.add(&entry)?,
+            None if partial.get_path(path, 0).is_some() => partial.remove(path, 0)?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Builds one tree per group, each layered on the previous one, starting from
+/// `HEAD`. A file whose hunks are spread over several groups gets the hunks
+/// committed so far applied to its `HEAD` content; the last tree matches the
+/// staged index.
+fn plan_commits(
+    repo: &Repository,
+    staged: &Index,
+    groups: Vec<SplitGroup>,
+) -> Result<Vec<PlannedCommit>> {
+    let mut partial = Index::new()?;
+    let head = match head_commit(repo)? {
+        Some(commit) => {
+            let tree = commit.tree()?;
+            partial.read_tree(&tree)?;
+            Some(tree)
+        }
+        None => None,
+    };
+    let mut base = head.clone();
+    let mut applied: HashMap<String, Vec<usize>> = HashMap::new();
+
+    let mut planned = Vec::new();
+    for group in groups {
+        let mut split_files = Vec::new();
+        for unit in &group.units {
+            match unit.hunk {
+                None => stage_paths(&mut partial, staged, &unit.paths)?,
+                Some((hunk, total)) => {
+                    let path = &unit.paths[0];
+                    applied.entry(path.clone()).or_default().push(hunk);
+                    if !split_files.contains(&(path, total)) {
+                        split_files.push((path, total));
+                    }
+                }
+            }
+        }
+        for (path, total) in split_files {
+            let hunks = &applied[path];
+            if hunks.len() == total {
+                stage_paths(&mut partial, staged, std::slice::from_ref(path))?;
+                continue;
+            }
+            let entry = staged
+                .get_path(Path::new(path), 0)
+                .ok_or_else(|| AppError::Custom(format!("{path} is no longer staged")))?;
+            let content = apply_hunks(repo, head.as_ref(), staged, path, hunks)?;
+            partial.add(&IndexEntry {
+                id: repo.blob(&content)?,
+                file_size: content.len() as u32,
+                ..entry
+            })?;
+        }
+
+        let diff = index_diff(repo, base.as_ref(), &partial)?;
+        let tree = partial.write_tree_to(repo)?;
+        base = Some(repo.find_tree(tree)?);
+        planned.push(PlannedCommit {
+            group,
+            tree,
+            diff,
+            message: String::new(),
+        });
+    }
+
+    Ok(planned)
+}
+
+fn print_plan(planned: &[PlannedCommit], language: &Language) {
+    println!("\n{}", language.split_plan_heading());
+    for (number, commit) in planned.iter().enumerate() {
+        println!(
+            "\n{}",
+            language
+                .split_commit_heading()
+                .replace("{current}", &(number + 1).to_string())
+                .replace("{total}", &planned.len().to_string())
+                .replace("{scope}", &commit.group.scope)
+        );
+        for file in &commit.diff.files {
+            println!("  - {}", file.path);
+        }
+        for line in commit.message.lines() {
+            println!("    {line}");
+        }
+    }
+}
+
+/// Generates a message for every planned commit, falling back to the rules
+/// when the model cannot. An interrupt stops the whole split.
+fn write_messages(
+    session: &mut dyn Generator,
+    planned: &mut [PlannedCommit],
+    language: &Language,
+    message_language: &Language,
+    rules: &FallbackRules,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let total = planned.len();
+    for (number, commit) in planned.iter_mut().enumerate() {
+        session.set_abort_signal(AbortSignal::new(timeout));
+        println!(
+            "{}",
+            language
+                .split_generating()
+                .replace("{current}", &(number + 1).to_string())
+                .replace("{total}", &total.to_string())
+                .replace("{scope}", &commit.group.scope)
+        );
+        let generated =
+            generate_commit_message(session, &commit.diff, language, message_language, false)?;
+        // Ctrl-C cancels the whole split; a timeout only costs this message.
+        if session.abort_reason() == Some(AbortReason::Interrupted) {
+            return Err(AppError::Llama(LlamaError::Aborted(
+                AbortReason::Interrupted,
+            )));
+        }
+        commit.message = match generated {
+            Some(message) => message,
+            None => generate_fallback_commit_message(&commit.diff, message_language, rules)
+                .unwrap_or_else(|| {
+                    format!(
+                        "chore({}): {}",
+                        commit.group.scope,
+                        message_language
+                            .subject_split_fallback()
+                            .replace("{scope}", &commit.group.scope)
+                    )
+                }),
+        };
+    }
+    Ok(())
+}
+
+/// `git ca split`: breaks the staged changes into files and hunks, groups
+/// them by path scope and lets the model regroup them (see [`model_groups`]),
+/// then proposes one commit per group with a generated message each and
+/// creates them in order once approved. Anything not committed stays staged.
+pub fn run_split(
+    git_config: &mut GitConfig,
+    language: &Language,
+    options: &SessionOptions,
+) -> Result<()> {
+    let current_dir = env::current_dir()?;
+    let repo_path = find_git_repository(&current_dir)
+        .ok_or_else(|| AppError::NotInRepository(language.not_in_git_repository().to_string()))?;
+    let repo = Repository::open(&repo_path)?;
+    let mut index = repo.index()?;
+    env::set_current_dir(&repo_path)?;
+    index.read(true)?;
+
+    let diff = staged_diff(&repo, &index)?;
+    if diff.is_empty() {
+        println!("{}", language.no_changes_staged());
+        return Err(AppError::NoStagedChanges);
+    }
+
+    let rules = FallbackRules::from_config(&git_config.config);
+    let message_language = get_message_language();
+    let units = split_units(&diff, &rules, &message_language);
+    if units.len() < 2 {
+        println!("{}", language.split_single_scope());
+        return Ok(());
+    }
+
+    let model_path = get_model_path(language)?;
+    eprintln!("\x1b[90m{}\x1b[0m", language.this_may_take_moment());
+    let interrupt = InterruptGuard::install();
+    let mut session = load_generator(&model_path, options, language)?;
+    let timeout = inference_timeout(git_config);
+
+    let path_groups = plan_groups(&units);
+    println!(
+        "{}",
+        language
+            .split_grouping()
+            .replace("{}", &units.len().to_string())
+    );
+    session.set_abort_signal(AbortSignal::new(timeout));
+    let groups = match model_groups(session.as_mut(), &units, &path_groups, &message_language)? {
+        Some(groups) => groups,
+        None => {
+            eprintln!("\x1b[90m{}\x1b[0m", language.split_grouping_fallback());
+            path_groups
+        }
+    };
+    if groups.len() < 2 {
+        println!("{}", language.split_single_scope());
+        return Ok(());
+    }
+
+    let mut planned = plan_commits(&repo, &index, groups)?;
+    let total = planned.len();
+    write_messages(
+        session.as_mut(),
+        &mut planned,
+        language,
+        &message_language,
+        &rules,
+        timeout,
+    )?;
+    drop(session);
+    drop(interrupt);
+
+    print_plan(&planned, language);
+
+    if io::stdin().is_terminal() {
+        loop {
+            let choice = get_user_input(
+                &language
+                    .split_confirm_prompt()
+                    .replace("{}", &total.to_string()),
+            )?;
+            match choice.to_lowercase().as_str() {
+                "y" => break,
+                "e" => {
+                    for commit in &mut planned {
+                        let edited = get_user_input(
+                            &language
+                                .split_edit_message_prompt()
+                                .replace("{scope}", &commit.group.scope),
+                        )?;
+                        if !edited.is_empty() {
+                            commit.message = edited;
+                        }
+                    }
+                    print_plan(&planned, language);
+                }
+                "c" => {
+                    println!("{}", language.commit_cancelled());
+                    return Err(AppError::Cancelled);
+                }
+                _ => println!("{}", language.invalid_choice()),
+            }
+        }
+    } else {
+        println!("{}", language.split_non_interactive());
+    }
+
+    let name = git_config.get_or_prompt("user.name", language.enter_name_prompt())?;
+    let email = git_config.get_or_prompt("user.email", language.enter_email_prompt())?;
+    let signature = Signature::now(&name, &email)?;
+
+    let mut parent: Option<Commit> = head_commit(&repo)?;
+    for commit in &planned {
+        let tree = repo.find_tree(commit.tree)?;
+        let parents: Vec<&Commit> = parent.iter().collect();
+        let oid = repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            &commit.message,
+            &tree,
+            &parents,
+        )?;
+        parent = Some(repo.find_commit(oid)?);
+        println!(
+            "{}",
+            language
+                .commit_message_label()
+                .replace("{}", &commit.message)
+        );
+    }
+
+    println!(
+        "{}",
+        language
+            .split_commits_created()
+            .replace("{}", &total.to_string())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::tests::{file_diff, TempRepo};
+    use crate::fallback::tests::default_rules;
+    use crate::llama::ScriptedGenerator;
+    use std::collections::VecDeque;
+
+    fn unit_paths(groups: &[SplitGroup]) -> Vec<(&str, Vec<&str>)> {
+        groups
+            .iter()
+            .map(|group| {
+                (
+                    group.scope.as_str(),
+                    group
+                        .units
+                        .iter()
+                        .flat_map(|unit| &unit.paths)
+                        .map(String::as_str)
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_files_by_scope_in_first_seen_order() {
+        let rules = default_rules();
+        let diff = StagedDiff {
+            text: String::new(),
+            files: vec![
+                file_diff("src/parser/lexer.rs", ChangeKind::Modified, &["+x"]),
+                file_diff("Cargo.lock", ChangeKind::Modified, &["+x"]),
+                file_diff(
+                    "src/cli/args.rs",
+                    ChangeKind::Renamed {
+                        from: "src/cli/opts.rs".to_string(),
+                    },
+                    &["+x"],
+                ),
+                file_diff("src/parser/ast.rs", ChangeKind::Added, &["+x"]),
+                file_diff("docs/guide.md", ChangeKind::Modified, &["+x"]),
+            ],
+        };
+
+        let units = split_units(&diff, &rules, &Language::english());
+        assert_eq!(
+            unit_paths(&plan_groups(&units)),
+            vec![
+                ("parser", vec!["src/parser/lexer.rs", "src/parser/ast.rs"]),
+                ("deps", vec!["Cargo.lock"]),
+                ("cli", vec!["src/cli/args.rs", "src/cli/opts.rs"]),
+                ("docs", vec!["docs/guide.md"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_modified_text_files_into_hunks() {
+        let rules = default_rules();
+        let lines = ["@@ -1,2 +1,2 @@", "-a", "+b", "@@ -9,1 +9,2 @@", " c", "+d"];
+        let lexer = file_diff("src/parser/lexer.rs", ChangeKind::Modified, &lines);
+        let added = file_diff("src/parser/ast.rs", ChangeKind::Added, &lines);
+        let diff = StagedDiff {
+            text: String::new(),
+            files: vec![lexer, added],
+        };
+
+        let units = split_units(&diff, &rules, &Language::english());
+        let labels: Vec<&str> = units.iter().map(|unit| unit.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "src/parser/lexer.rs @@ -1,2 +1,2 @@",
+                "src/parser/lexer.rs @@ -9,1 +9,2 @@",
+                "src/parser/ast.rs (added, +2 / -1)",
+            ]
+        );
+        let hunks: Vec<Option<(usize, usize)>> = units.iter().map(|unit| unit.hunk).collect();
+        assert_eq!(hunks, vec![Some((0, 2)), Some((1, 2)), None]);
+        assert_eq!(units[1].lines, vec![" c", "+d"]);
+        assert_eq!(units[2].lines, vec!["-a", "+b", " c", "+d"]);
+    }
+
+    #[test]
+    fn parses_complete_model_groupings_only() {
+        assert_eq!(
+            parse_grouping("Sure:\n- parser: 1, 3\n`Docs`: 2\n", 3),
+            Some(vec!["parser".into(), "docs".into(), "parser".into()])
+        );
+        assert_eq!(parse_grouping("parser: 1 2", 3), None);
+        assert_eq!(parse_grouping("parser: 1 2\ndocs: 2 3", 3), None);
+        assert_eq!(parse_grouping("parser: 1 2 4", 3), None);
+        assert_eq!(parse_grouping("parser: 0 1 2", 3), None);
+    }
+
+    #[test]
+    fn model_regroups_units_or_leaves_it_to_the_caller() {
+        let rules = default_rules();
+        let diff = StagedDiff {
+            text: String::new(),
+            files: vec![
+                file_diff("src/parser/lexer.rs", ChangeKind::Modified, &["+x"]),
+                file_diff("src/cli/args.rs", ChangeKind::Modified, &["+x"]),
+                file_diff("docs/guide.md", ChangeKind::Modified, &["+x"]),
+            ],
+        };
+        let english = Language::english();
+        let units = split_units(&diff, &rules, &english);
+        let groups = plan_groups(&units);
+        let mut session = ScriptedGenerator {
+            context_size: 8192,
+            responses: VecDeque::from([
+                Ok("cli: 2 3\nparser: 1".to_string()),
+                Ok("parser: 1 2".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let regrouped = model_groups(&mut session, &units, &groups, &english).expect("grouping");
+        assert_eq!(
+            regrouped.as_deref().map(unit_paths),
+            Some(vec![
+                ("parser", vec!["src/parser/lexer.rs"]),
+                ("cli", vec!["src/cli/args.rs", "docs/guide.md"]),
+            ])
+        );
+        assert!(session.prompts[0].contains("1. src/parser/lexer.rs (+1 / -0)\n   +x\n"));
+        assert!(session.prompts[0].contains("parser: 1\ncli: 2\ndocs: 3\n"));
+
+        let incomplete = model_groups(&mut session, &units, &groups, &english).expect("grouping");
+        assert_eq!(incomplete, None);
+
+        let mut tiny = ScriptedGenerator {
+            context_size: 64,
+            ..Default::default()
+        };
+        let skipped = model_groups(&mut tiny, &units, &groups, &english).expect("grouping");
+        assert_eq!(skipped, None);
+        assert!(tiny.prompts.is_empty());
+    }
+
+    #[test]
+    fn layers_commit_trees_up_to_the_staged_index() {
+        let repo = TempRepo::new("split");
+        repo.write("src/parser/lexer.rs", b"fn lex() {}\n");
+        repo.write("src/cli/opts.rs", b"fn parse_args() {}\n");
+        repo.write("docs/old.md", b"# Old\n");
+        repo.commit();
+
+        repo.write("src/parser/lexer.rs", b"fn lex() {}\nfn peek() {}\n");
+        repo.remove("src/cli/opts.rs");
+        repo.write("src/cli/args.rs", b"fn parse_args() {}\n");
+        repo.remove("docs/old.md");
+        let mut index = repo.stage_all();
+
+        let rules = default_rules();
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let groups = plan_groups(&split_units(&diff, &rules, &Language::english()));
+        assert_eq!(groups.len(), 3);
+
+        let planned = plan_commits(&repo.repo, &index, groups).expect("plan");
+        let changed: Vec<Vec<&str>> = planned
+            .iter()
+            .map(|commit| {
+                commit
+                    .diff
+                    .files
+                    .iter()
+                    .map(|file| file.path.as_str())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                vec!["docs/old.md"],
+                vec!["src/cli/args.rs"],
+                vec!["src/parser/lexer.rs"],
+            ]
+        );
+        assert!(matches!(
+            planned[1].diff.files[0].kind,
+            ChangeKind::Renamed { .. }
+        ));
+        assert_eq!(
+            planned.last().map(|commit| commit.tree),
+            Some(index.write_tree().expect("index tree"))
+        );
+    }
+
+    #[test]
+    fn spreads_the_hunks_of_one_file_over_commits() {
+        let repo = TempRepo::new("split-hunks");
+        let lines: Vec<String> = (1..=20).map(|n| format!("line {n}\n")).collect();
+        repo.write("src/parser/lexer.rs", lines.concat().as_bytes());
+        repo.commit();
+
+        let mut changed = lines.clone();
+        changed[1] = "line two\n".to_string();
+        changed[18] = "line nineteen\n".to_string();
+        repo.write("src/parser/lexer.rs", changed.concat().as_bytes());
+        repo.write("docs/guide.md", b"# Guide\n");
+        let mut index = repo.stage_all();
+
+        let rules = default_rules();
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let units = split_units(&diff, &rules, &Language::english());
+        assert_eq!(units.len(), 3);
+        // The second hunk goes out with the docs, ahead of the first one.
+        let scopes = ["docs", "parser", "docs"];
+        let groups = collect_groups(&units, |number, _| scopes[number].to_string());
+
+        let planned = plan_commits(&repo.repo, &index, groups).expect("plan");
+        let lexer_at = |commit: &PlannedCommit| {
+            let tree = repo.repo.find_tree(commit.tree).expect("tree");
+            let entry = tree
+                .get_path(Path::new("src/parser/lexer.rs"))
+                .expect("entry");
+            let blob = repo.repo.find_blob(entry.id()).expect("blob");
+            String::from_utf8(blob.content().to_vec()).expect("utf-8")
+        };
+        let mut second_hunk = lines.clone();
+        second_hunk[18] = "line nineteen\n".to_string();
+        assert_eq!(lexer_at(&planned[0]), second_hunk.concat());
+        assert_eq!(lexer_at(&planned[1]), changed.concat());
+        let paths: Vec<&str> = planned[0]
+            .diff
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["docs/guide.md", "src/parser/lexer.rs"]);
+        assert_eq!(
+            (
+                planned[1].diff.files[0].additions,
+                planned[1].diff.files[0].deletions
+            ),
+            (1, 1)
+        );
+        assert_eq!(
+            planned.last().map(|commit| commit.tree),
+            Some(index.write_tree().expect("index tree"))
+        );
+    }
+
+    fn planned_split(repo: &TempRepo) -> Vec<PlannedCommit> {
+        repo.write("src/parser/lexer.rs", b"fn lex() {}\n");
+        repo.write("docs/guide.md", b"# Guide\n");
+        let index = repo.stage_all();
+        let rules = default_rules();
+        let diff = staged_diff(&repo.repo, &index).expect("diff");
+        let units = split_units(&diff, &rules, &Language::english());
+        plan_commits(&repo.repo, &index, plan_groups(&units)).expect("plan")
+    }
+
+    #[test]
+    fn interrupt_stops_the_split() {
+        let repo = TempRepo::new("split-interrupt");
+        let mut planned = planned_split(&repo);
+        let rules = default_rules();
+        let english = Language::english();
+        let mut session = ScriptedGenerator {
+            context_size: 8192,
+            responses: VecDeque::from([Err(LlamaError::Aborted(AbortReason::Interrupted))]),
+            ..Default::default()
+        };
+
+        let result = write_messages(&mut session, &mut planned, &english, &english, &rules, None);
+        assert!(matches!(
+            result,
+            Err(AppError::Llama(LlamaError::Aborted(
+                AbortReason::Interrupted
+            )))
+        ));
+        assert_eq!(session.prompts.len(), 1);
+        assert!(planned.iter().all(|commit| commit.message.is_empty()));
+    }
+
+    #[test]
+    fn timeout_falls_back_for_that_commit_only() {
+        let repo = TempRepo::new("split-timeout");
+        let mut planned = planned_split(&repo);
+        let rules = default_rules();
+        let english = Language::english();
+        let mut session = ScriptedGenerator {
+            context_size: 8192,
+            responses: VecDeque::from([
+                Err(LlamaError::Aborted(AbortReason::TimedOut)),
+                Ok("feat(parser): add the lexer".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        write_messages(&mut session, &mut planned, &english, &english, &rules, None)
+            .expect("messages");
+        assert_eq!(session.prompts.len(), 2);
+        assert_eq!(
+            Some(planned[0].message.clone()),
+            generate_fallback_commit_message(&planned[0].diff, &english, &rules)
+        );
+        assert_eq!(planned[1].message, "feat(parser): add the lexer");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::diff::{FileDiff, StagedDiff};
+use crate::llama::{Generator, LlamaError};
+use crate::{
+    describe_file_change, fit_prompt_to_budget, is_generated_or_large_file, KeepSide, Language,
+};
+
+/// Maximum number of model calls spent on per-chunk notes before the final
+/// commit message is generated; files beyond the cap are listed without notes.
+const MAX_SUMMARY_CALLS: usize = 8;
+const NOTE_MAX_TOKENS: usize = 64;
+const NOTE_MAX_CHARS: usize = 160;
+
+/// Summarises a diff too large for a single prompt: every chunk of files is
+/// condensed into a one-line note with the loaded model, and the notes are
+/// returned as the text the commit prompt is built from. Progress is shown in
+/// `language`; prompts and notes use `message_language`.
+pub fn summarize_changes(
+    session: &mut dyn Generator,
+    diff: &StagedDiff,
+    language: &Language,
+    message_language: &Language,
+    budget: usize,
+) -> Result<String, LlamaError> {
+    let overhead = session.count_tokens(&build_note_prompt("", message_language))?;
+    let chunk_budget = budget.saturating_sub(overhead).max(1);
+
+    let mut blocks = Vec::new();
+    let mut listed = Vec::new();
+    for file in &diff.files {
+        if file.lines.is_empty() || is_generated_or_large_file(&file.path) {
+            listed.push(file);
+            continue;
+        }
+        let block = render_file_block(file, message_language);
+        let tokens = session.count_tokens(&block)?;
+        blocks.push((file, block, tokens));
+    }
+
+    let sizes: Vec<usize> = blocks.iter().map(|(_, _, tokens)| *tokens).collect();
+    let chunks = plan_chunks(&sizes, chunk_budget);
+    let summarized = chunks.len().min(MAX_SUMMARY_CALLS);
+
+    let mut notes = Vec::new();
+    for (number, chunk) in chunks.iter().take(summarized).enumerate() {
+        let files: Vec<&FileDiff> = chunk.iter().map(|&i| blocks[i].0).collect();
+        let paths = files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!(
+            "\x1b[90m{}\x1b[0m",
+            language
+                .summarizing_chunk()
+                .replace("{current}", &(number + 1).to_string())
+                .replace("{total}", &summarized.to_string())
+                .replace("{files}", &paths)
+        );
+
+        let body = chunk
+            .iter()
+            .map(|&i| blocks[i].1.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let fitted = fit_prompt_to_budget(
+            &body,
+            KeepSide::Head,
+            budget,
+            message_language.truncated_body_notice(),
+            |text| build_note_prompt(text, message_language),
+            |text| session.count_tokens(text),
+        );
+
+        // A chunk whose note prompt cannot fit is described without the model.
+        let note = match fitted {
+            Ok(fitted) => match session.infer(&fitted.prompt, NOTE_MAX_TOKENS) {
+                Ok(response) => clean_note(&response),
+                Err(err) if session.abort_reason().is_some() => return Err(err),
+                Err(_) => None,
+            },
+            Err(err) => {
+                eprintln!("[git-ca] warning: {err}");
+                None
+            }
+        };
+        match note {
+            Some(note) => notes.push(format!("- {paths}: {note}")),
+            None => notes.extend(
+                files
+                    .iter()
+                    .map(|file| describe_line(file, message_language)),
+            ),
+        }
+    }
+
+    let skipped: Vec<&FileDiff> = chunks
+        .iter()
+        .skip(summarized)
+        .flatten()
+        .map(|&i| blocks[i].0)
+        .collect();
+    if !skipped.is_empty() {
+        eprintln!(
+            "\x1b[90m{}\x1b[0m",
+            language
+                .summary_call_cap_reached()
+                .replace("{}", &skipped.len().to_string())
+        );
+    }
+    notes.extend(
+        skipped
+            .into_iter()
+            .chain(listed)
+            .map(|file| describe_line(file, message_language)),
+    );
+
+    Ok(format!(
+        "{}\n{}\n",
+        message_language.change_notes_heading(),
+        notes.join("\n")
+    ))
+}
+
+fn describe_line(file: &FileDiff, language: &Language) -> String {
+    format!("- {} ({})", file.path, describe_file_change(file, language))
+}
+
+fn render_file_block(file: &FileDiff, language: &Language) -> String {
+    let mut block = format!(
+        "{} {} ({})\n",
+        language.file_snippet_heading(),
+        file.path,
+        describe_file_change(file, language)
+    );
+    for line in &file.lines {
+        block.push_str(line);
+        block.push('\n');
+    }
+    block
+}
+
+/// Greedily packs consecutive blocks (given by token size) into chunks that
+/// fit `chunk_budget`. Oversized blocks get a chunk of their own and are
+/// trimmed when the prompt is fitted.
+fn plan_chunks(sizes: &[usize], chunk_budget: usize) -> Vec<Vec<usize>> {
+    let mut chunks: Vec<Vec<usize>> = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    let mut used = 0usize;
+
+    for (index, &size) in sizes.iter().enumerate() {
+        if !current.is_empty() && used + size > chunk_budget {
+            chunks.push(std::mem::take(&mut current));
+            used = 0;
+        }
+        current.push(index);
+        used += size;
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+fn clean_note(response: &str) -> Option<String> {
+    let line = response
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("<think>"))?;
+    let line = line
+        .trim_start_matches(['-', '*', '•', ' '])
+        .trim_matches(['"', '`', '\''])
+        .trim();
+    if line.is_empty() {
+        return None;
+    }
+    Some(line.chars().take(NOTE_MAX_CHARS).collect())
+}
+
+fn build_note_prompt(changes: &str, language: &Language) -> String {
+    language.note_prompt().replace("{changes}", changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::AbortReason;
+    use crate::diff::tests::file_diff;
+    use crate::diff::ChangeKind;
+    use crate::llama::ScriptedGenerator;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn packs_blocks_into_chunks_within_budget() {
+        assert_eq!(
+            plan_chunks(&[40, 30, 50, 200, 10, 10], 100),
+            vec![vec![0, 1], vec![2], vec![3], vec![4, 5]]
+        );
+        assert!(plan_chunks(&[], 100).is_empty());
+    }
+
+    #[test]
+    fn describes_chunks_whose_prompt_cannot_fit() {
+        let diff = StagedDiff {
+            files: vec![
+                file_diff("src/a.rs", ChangeKind::Modified, &["+fn a() {}"]),
+                file_diff("src/b.rs", ChangeKind::Modified, &["+fn b() {}"]),
+            ],
+            text: String::new(),
+        };
+        let english = Language::english();
+        let mut session = ScriptedGenerator::default();
+
+        let notes = summarize_changes(&mut session, &diff, &english, &english, 10).unwrap();
+        assert!(session.prompts.is_empty());
+        assert!(notes.contains(&describe_line(&diff.files[0], &english)));
+        assert!(notes.contains(&describe_line(&diff.files[1], &english)));
+    }
+
+    #[test]
+    fn summarizes_chunks_up_to_the_call_cap() {
+        let english = Language::english();
+        let mut files: Vec<FileDiff> = (0..MAX_SUMMARY_CALLS + 2)
+            .map(|i| {
+                file_diff(
+                    &format!("src/f{i}.rs"),
+                    ChangeKind::Modified,
+                    &[&format!("+{}", "x".repeat(150))],
+                )
+            })
+            .collect();
+        files.push(file_diff(
+            "Cargo.lock",
+            ChangeKind::Modified,
+            &["+checksum"],
+        ));
+        let diff = StagedDiff {
+            files,
+            text: String::new(),
+        };
+        // Room for exactly one file per note prompt.
+        let budget = build_note_prompt("", &english).len() + 200;
+        let mut responses: VecDeque<Result<String, LlamaError>> = (0..MAX_SUMMARY_CALLS)
+            .map(|i| Ok(format!("- Note {i}\n")))
+            .collect();
+        responses[1] = Err(LlamaError::Inference("bad output".to_string()));
+        let mut session = ScriptedGenerator {
+            responses,
+            ..Default::default()
+        };
+
+        let notes = summarize_changes(&mut session, &diff, &english, &english, budget).unwrap();
+        assert_eq!(session.prompts.len(), MAX_SUMMARY_CALLS);
+        assert!(session.prompts[0].contains("src/f0.rs"));
+        assert!(notes.starts_with(english.change_notes_heading()));
+        assert!(notes.contains("- src/f0.rs: Note 0\n"));
+        assert!(!notes.contains("Note 1"));
+        for described in [
+            1,
+            MAX_SUMMARY_CALLS,
+            MAX_SUMMARY_CALLS + 1,
+            MAX_SUMMARY_CALLS + 2,
+        ] {
+            assert!(notes.contains(&describe_line(&diff.files[described], &english)));
+        }
+    }
+
+    #[test]
+    fn stops_summarizing_once_aborted() {
+        let diff = StagedDiff {
+            files: vec![
+                file_diff("src/a.rs", ChangeKind::Modified, &["+fn a() {}"]),
+                file_diff("src/b.rs", ChangeKind::Modified, &["+fn b() {}"]),
+            ],
+            text: String::new(),
+        };
+        let english = Language::english();
+        let budget = build_note_prompt("", &english).len() + 200;
+        let mut session = ScriptedGenerator {
+            responses: VecDeque::from([Err(LlamaError::Aborted(AbortReason::Interrupted))]),
+            ..Default::default()
+        };
+
+        let result = summarize_changes(&mut session, &diff, &english, &english, budget);
+        assert!(matches!(
+            result,
+            Err(LlamaError::Aborted(AbortReason::Interrupted))
+        ));
+        assert_eq!(session.prompts.len(), 1);
+    }
+
+    #[test]
+    fn cleans_model_notes() {
+        assert_eq!(
+            clean_note("\n - \"Add retry with backoff to the HTTP client\"\nextra"),
+            Some("Add retry with backoff to the HTTP client".to_string())
+        );
+        assert_eq!(clean_note("  \n- \n"), None);
+        assert_eq!(
+            clean_note(&"x".repeat(400)).map(|note| note.len()),
+            Some(NOTE_MAX_CHARS)
+        );
+    }
+}